@@ -0,0 +1,143 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use unsvg::Color;
+use crate::canvas::Canvas;
+use crate::svg::{fill_tag, line_tag, render_png, write_header};
+
+/// How an `AnimatedCanvas` shows the drawing being made.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Animation {
+    /// One SVG in which each line appears in turn, `speed` lines a second.
+    Smil { speed: f32 },
+    /// A numbered PNG of the drawing so far after every `every` lines, and
+    /// one more of the finished drawing.
+    Frames { every: usize },
+}
+
+/// Records every line and fill in the order they are drawn, to save as an
+/// animation rather than only the final image.
+pub struct AnimatedCanvas {
+    width: u32,
+    height: u32,
+    background: Color,
+    animation: Animation,
+    /// Shapes in stacking order, which differs from drawing order for fills.
+    shapes: Vec<Shape>,
+    /// Lines drawn so far.
+    lines: usize,
+    fill_start: Option<usize>,
+}
+
+/// A shape, with how many lines had been drawn once it appeared.
+struct Shape {
+    tag: String,
+    at: usize,
+}
+
+impl AnimatedCanvas {
+    pub fn new(width: u32, height: u32, animation: Animation) -> AnimatedCanvas {
+        AnimatedCanvas {
+            width,
+            height,
+            background: Color::black(),
+            animation,
+            shapes: Vec::new(),
+            lines: 0,
+            fill_start: None,
+        }
+    }
+
+    /// An SVG of the shapes that had appeared once `lines` lines were drawn.
+    fn frame(&self, lines: usize) -> String {
+        let mut out = String::new();
+        write_header(&mut out, self.width, self.height, self.background);
+        for shape in self.shapes.iter().filter(|shape| shape.at <= lines) {
+            let _ = writeln!(out, "    {}/>", shape.tag);
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+
+    /// An SVG in which every shape starts hidden, and is shown once the lines
+    /// before it would have taken their time to draw.
+    fn smil(&self, speed: f32) -> String {
+        let mut out = String::new();
+        write_header(&mut out, self.width, self.height, self.background);
+        for shape in &self.shapes {
+            let begin = shape.at as f32 / speed;
+            let _ = writeln!(out, r#"    {} visibility="hidden">"#, shape.tag);
+            let _ = writeln!(out, r#"        <set attributeName="visibility" to="visible" begin="{begin}s" fill="freeze"/>"#);
+            out.push_str("    </path>\n");
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+
+    fn save_frames(&self, path: &Path, every: usize) -> Result<(), String> {
+        let mut counts: Vec<usize> = (1..=self.lines / every).map(|frame| frame * every).collect();
+        if counts.last() != Some(&self.lines) {
+            counts.push(self.lines);
+        }
+        let digits = counts.len().to_string().len().max(4);
+        for (index, lines) in counts.into_iter().enumerate() {
+            let frame_path = numbered(path, index + 1, digits);
+            render_png(&self.frame(lines))?
+                .save_png(&frame_path)
+                .map_err(|e| format!("{}: {e}", frame_path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// `out.png` becomes `out_0001.png`, and so on.
+fn numbered(path: &Path, number: usize, digits: usize) -> PathBuf
+{
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let extension = path.extension().map(|extension| extension.to_string_lossy()).unwrap_or("png".into());
+    path.with_file_name(format!("{stem}_{number:0digits$}.{extension}"))
+}
+
+impl Canvas for AnimatedCanvas {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn set_background(&mut self, color: Color) {
+        self.background = color;
+    }
+
+    fn line(&mut self, start: (f32, f32), end: (f32, f32), color: Color, width: f32) -> Result<(), String> {
+        self.lines += 1;
+        self.shapes.push(Shape { tag: line_tag(start, end, color, width), at: self.lines });
+        Ok(())
+    }
+
+    fn begin_fill(&mut self) {
+        self.fill_start = Some(self.shapes.len());
+    }
+
+    /// The fill goes beneath the lines drawn while it was recorded, as in
+    /// `SvgCanvas`, but only appears once it is finished.
+    fn end_fill(&mut self, points: &[(f32, f32)], color: Color) -> Result<(), String> {
+        let start = self.fill_start.take().unwrap_or(self.shapes.len());
+        if points.len() < 3 {
+            return Ok(());
+        }
+        self.shapes.insert(start, Shape { tag: fill_tag(points, color), at: self.lines });
+        Ok(())
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        match self.animation {
+            Animation::Smil { speed } => std::fs::write(path, self.smil(speed)).map_err(|e| e.to_string()),
+            Animation::Frames { every } => self.save_frames(path, every),
+        }
+    }
+
+    fn to_text(&self) -> Option<String> {
+        match self.animation {
+            Animation::Smil { speed } => Some(self.smil(speed)),
+            Animation::Frames { .. } => None,
+        }
+    }
+}
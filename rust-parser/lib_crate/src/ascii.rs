@@ -0,0 +1,104 @@
+use std::path::Path;
+use unsvg::Color;
+use crate::canvas::Canvas;
+
+/// Most columns in a text preview, so that it fits in a terminal.
+const MAX_COLUMNS: u32 = 80;
+
+/// Draws a rough preview in text, with `#` for lines and `.` for fills.
+///
+/// Each character covers a cell of the image twice as tall as it is wide, as
+/// terminal characters roughly are. Colors and line widths are ignored.
+pub struct AsciiCanvas {
+    width: u32,
+    height: u32,
+    columns: usize,
+    rows: usize,
+    /// Pixels covered by each character.
+    scale: f32,
+    cells: Vec<Vec<char>>,
+}
+
+impl AsciiCanvas {
+    pub fn new(width: u32, height: u32) -> AsciiCanvas {
+        let columns = width.min(MAX_COLUMNS);
+        let scale = width as f32 / columns as f32;
+        let rows = ((height as f32 / (scale * 2.0)).ceil() as usize).max(1);
+        AsciiCanvas {
+            width,
+            height,
+            columns: columns as usize,
+            rows,
+            scale,
+            cells: vec![vec![' '; columns as usize]; rows],
+        }
+    }
+
+    /// The cell holding a point, if it is on the canvas.
+    fn cell(&self, (x, y): (f32, f32)) -> Option<(usize, usize)> {
+        let column = (x / self.scale).floor();
+        let row = (y / (self.scale * 2.0)).floor();
+        if column < 0.0 || row < 0.0 || column >= self.columns as f32 || row >= self.rows as f32 {
+            return None;
+        }
+        Some((row as usize, column as usize))
+    }
+}
+
+impl Canvas for AsciiCanvas {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn set_background(&mut self, _color: Color) {}
+
+    fn line(&mut self, start: (f32, f32), end: (f32, f32), _color: Color, _width: f32) -> Result<(), String> {
+        // Sample the line at least twice per cell so that no cell is skipped
+        let length = (end.0 - start.0).hypot(end.1 - start.1);
+        let steps = ((length / self.scale * 2.0).ceil() as usize).clamp(1, 100_000);
+        for step in 0..=steps {
+            let t = step as f32 / steps as f32;
+            let point = (start.0 + (end.0 - start.0) * t, start.1 + (end.1 - start.1) * t);
+            if let Some((row, column)) = self.cell(point) {
+                self.cells[row][column] = '#';
+            }
+        }
+        Ok(())
+    }
+
+    fn end_fill(&mut self, points: &[(f32, f32)], _color: Color) -> Result<(), String> {
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                let centre = ((column as f32 + 0.5) * self.scale, (row as f32 + 0.5) * self.scale * 2.0);
+                if self.cells[row][column] == ' ' && contains(points, centre) {
+                    self.cells[row][column] = '.';
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_text().unwrap_or_default()).map_err(|e| e.to_string())
+    }
+
+    fn to_text(&self) -> Option<String> {
+        let lines: Vec<String> = self.cells.iter()
+            .map(|row| row.iter().collect::<String>().trim_end().to_string())
+            .collect();
+        Some(lines.join("\n") + "\n")
+    }
+}
+
+/// Whether `point` is inside the polygon, by the even-odd rule as SVG fills.
+fn contains(polygon: &[(f32, f32)], (x, y): (f32, f32)) -> bool
+{
+    let mut inside = false;
+    for (index, &(x1, y1)) in polygon.iter().enumerate() {
+        let (x2, y2) = polygon[(index + 1) % polygon.len()];
+        if (y1 > y) != (y2 > y) && x < x1 + (y - y1) / (y2 - y1) * (x2 - x1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
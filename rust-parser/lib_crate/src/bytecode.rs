@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::rc::Rc;
+use crate::load::Sources;
+use crate::structs::{Block, Expression, LSystem, Procedure, Program, Span, Statement, StatementKind};
+
+/// What an instruction does. Jump targets are indices into the instructions
+/// of the same procedure, resolved when the program is compiled.
+#[derive(Debug)]
+pub enum InstructionKind {
+    Procedure(Procedure),
+    /// Checks the condition of an IF or WHILE, jumping to `target` if it is false.
+    Branch { statement: &'static str, condition: Expression, target: usize },
+    Jump(usize),
+    /// Starts a REPEAT, jumping to `end` if the count is zero.
+    Repeat { count: Expression, end: usize },
+    /// Ends a pass of the innermost REPEAT, jumping back to `body` while passes remain.
+    Loop { body: usize },
+    /// Starts a FOREACH and binds the first item, or jumps to `end` if the list is empty.
+    Foreach { name: String, list: Expression, end: usize },
+    /// Binds the next item of the innermost FOREACH and jumps back to `body`, if any remain.
+    Next { name: String, body: usize },
+    /// Starts an ASK, making the turtle named by `turtle` the one drawing.
+    Ask(Expression),
+    /// Ends the innermost ASK, going back to the turtle drawing before it.
+    EndAsk,
+    /// Expands an L-system and draws it, all in one instruction.
+    LSystem(LSystem),
+    /// A call to a procedure defined with `TO`, with one expression per parameter.
+    Call(String, Vec<Expression>),
+    Return,
+}
+
+impl InstructionKind {
+    /// Whether the instruction starts a statement of the program, rather than
+    /// jumping back to the start of a loop or returning from a procedure.
+    pub fn is_statement(&self) -> bool {
+        !matches!(
+            self,
+            InstructionKind::Jump(_) | InstructionKind::Loop { .. } | InstructionKind::Next { .. }
+                | InstructionKind::EndAsk | InstructionKind::Return
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct Instruction {
+    pub kind: InstructionKind,
+    pub span: Span,
+}
+
+/// A `TO` definition with its body compiled. Running off the end of the
+/// body returns to the caller.
+#[derive(Debug)]
+pub struct CompiledProcedure {
+    pub name: String,
+    pub parameters: Vec<String>,
+    pub code: Rc<[Instruction]>,
+    pub span: Span,
+}
+
+/// A program lowered to flat lists of instructions, one for the top level
+/// statements and one for each procedure.
+#[derive(Debug)]
+pub struct CompiledProgram {
+    pub procedures: HashMap<String, Rc<CompiledProcedure>>,
+    pub main: Rc<[Instruction]>,
+}
+
+/// Lowers a parsed program to instructions. Loops and conditions become
+/// jumps, so running the program never has to search for the end of a block.
+pub fn compile(program: Program) -> CompiledProgram
+{
+    let procedures = program.procedures.into_values()
+        .map(|definition| {
+            let mut compiler = Compiler::default();
+            compiler.compile_block(definition.body);
+            compiler.emit(InstructionKind::Return, definition.span);
+            let procedure = CompiledProcedure {
+                name: definition.name,
+                parameters: definition.parameters,
+                code: compiler.code.into(),
+                span: definition.span,
+            };
+            (procedure.name.clone(), Rc::new(procedure))
+        })
+        .collect();
+
+    let mut compiler = Compiler::default();
+    compiler.compile_block(program.statements);
+    CompiledProgram { procedures, main: compiler.code.into() }
+}
+
+#[derive(Default)]
+struct Compiler {
+    code: Vec<Instruction>,
+}
+
+impl Compiler {
+    fn compile_block(&mut self, block: Block) {
+        for statement in block {
+            self.compile_statement(statement);
+        }
+    }
+
+    fn compile_statement(&mut self, statement: Statement) {
+        let span = statement.span;
+        match statement.kind {
+            StatementKind::Procedure(procedure) => {
+                self.emit(InstructionKind::Procedure(procedure), span);
+            },
+            StatementKind::If(condition, body) => {
+                let branch = self.emit(InstructionKind::Branch { statement: "IF", condition, target: 0 }, span);
+                self.compile_block(body);
+                self.patch(branch);
+            },
+            StatementKind::While(condition, body) => {
+                let branch = self.emit(InstructionKind::Branch { statement: "WHILE", condition, target: 0 }, span);
+                self.compile_block(body);
+                self.emit(InstructionKind::Jump(branch), span);
+                self.patch(branch);
+            },
+            StatementKind::Repeat(count, body) => {
+                let start = self.emit(InstructionKind::Repeat { count, end: 0 }, span);
+                self.compile_block(body);
+                self.emit(InstructionKind::Loop { body: start + 1 }, span);
+                self.patch(start);
+            },
+            StatementKind::Foreach(name, list, body) => {
+                let start = self.emit(InstructionKind::Foreach { name: name.clone(), list, end: 0 }, span);
+                self.compile_block(body);
+                self.emit(InstructionKind::Next { name, body: start + 1 }, span);
+                self.patch(start);
+            },
+            StatementKind::Ask(turtle, body) => {
+                self.emit(InstructionKind::Ask(turtle), span);
+                self.compile_block(body);
+                self.emit(InstructionKind::EndAsk, span);
+            },
+            StatementKind::LSystem(lsystem) => {
+                self.emit(InstructionKind::LSystem(lsystem), span);
+            },
+            StatementKind::Call(name, arguments) => {
+                self.emit(InstructionKind::Call(name, arguments), span);
+            },
+        }
+    }
+
+    /// Adds an instruction, returning its index.
+    fn emit(&mut self, kind: InstructionKind, span: Span) -> usize {
+        self.code.push(Instruction { kind, span });
+        self.code.len() - 1
+    }
+
+    /// Points the forward jump at `index` past the last instruction emitted.
+    fn patch(&mut self, index: usize) {
+        let here = self.code.len();
+        match &mut self.code[index].kind {
+            InstructionKind::Branch { target, .. } => *target = here,
+            InstructionKind::Repeat { end, .. } | InstructionKind::Foreach { end, .. } => *end = here,
+            other => unreachable!("{other:?} has no forward jump"),
+        }
+    }
+}
+
+impl fmt::Display for InstructionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstructionKind::Procedure(procedure) => write!(f, "{procedure}"),
+            InstructionKind::Branch { statement, condition, target } => {
+                write!(f, "{statement} {condition} else -> {target}")
+            },
+            InstructionKind::Jump(target) => write!(f, "JUMP -> {target}"),
+            InstructionKind::Repeat { count, end } => write!(f, "REPEAT {count} else -> {end}"),
+            InstructionKind::Loop { body } => write!(f, "LOOP -> {body}"),
+            InstructionKind::Foreach { name, list, end } => write!(f, "FOREACH \"{name} {list} else -> {end}"),
+            InstructionKind::Next { name, body } => write!(f, "NEXT \"{name} -> {body}"),
+            InstructionKind::Ask(turtle) => write!(f, "ASK {turtle}"),
+            InstructionKind::EndAsk => write!(f, "ENDASK"),
+            InstructionKind::LSystem(lsystem) => write!(f, "{lsystem}"),
+            InstructionKind::Call(name, arguments) => {
+                write!(f, "CALL {name}")?;
+                for argument in arguments {
+                    write!(f, " {argument}")?;
+                }
+                Ok(())
+            },
+            InstructionKind::Return => write!(f, "RETURN"),
+        }
+    }
+}
+
+/// Lists every instruction of a compiled program, with its index and the
+/// line it came from. Procedures are listed in alphabetical order. Lines in
+/// files other than the program itself, such as the prelude or a file it
+/// loads, are followed by that file's path from `sources`.
+pub fn dump(program: &CompiledProgram, sources: &Sources) -> String
+{
+    let mut out = String::new();
+    out.push_str("main:\n");
+    dump_code(&mut out, &program.main, sources);
+
+    let mut procedures: Vec<_> = program.procedures.values().collect();
+    procedures.sort_by(|a, b| a.name.cmp(&b.name));
+    for procedure in procedures {
+        let _ = write!(out, "\nTO {}", procedure.name);
+        for parameter in &procedure.parameters {
+            let _ = write!(out, " \"{parameter}");
+        }
+        out.push_str(":\n");
+        dump_code(&mut out, &procedure.code, sources);
+    }
+    out
+}
+
+fn dump_code(out: &mut String, code: &[Instruction], sources: &Sources)
+{
+    for (index, instruction) in code.iter().enumerate() {
+        let text = instruction.kind.to_string();
+        let span = instruction.span;
+        let _ = write!(out, "{index:>6}  {text:<48} ; line {}", span.line);
+        match sources.get(span.file) {
+            Some(file) if span.file != 0 => {
+                let _ = writeln!(out, " of {}", file.path);
+            },
+            _ => out.push('\n'),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parser};
+
+    fn compile_source(source: &str) -> CompiledProgram
+    {
+        compile(parser::parse(&lexer::tokenize(source)).unwrap())
+    }
+
+    fn listing(code: &[Instruction]) -> Vec<String>
+    {
+        code.iter().map(|instruction| instruction.kind.to_string()).collect()
+    }
+
+    #[test]
+    fn blocks_become_jumps_to_resolved_targets()
+    {
+        let program = compile_source("\
+MAKE \"i 0
+WHILE LT :i 3 [
+  IF EQ :i 1 [ FORWARD 1 ]
+  ADDASSIGN \"i 1
+]
+REPEAT 2 [ Step 5 ]
+FOREACH \"n [1 2] [ TURN :n ]
+TO Step \"size
+  FORWARD :size
+END
+");
+        assert_eq!(listing(&program.main), [
+            "MAKE \"i \"0",
+            "WHILE LT :i \"3 else -> 6",
+            "IF EQ :i \"1 else -> 4",
+            "FORWARD \"1",
+            "ADDASSIGN \"i \"1",
+            "JUMP -> 1",
+            "REPEAT \"2 else -> 9",
+            "CALL Step \"5",
+            "LOOP -> 7",
+            "FOREACH \"n [1 2] else -> 12",
+            "TURN :n",
+            "NEXT \"n -> 10",
+        ]);
+        assert_eq!(listing(&program.procedures["Step"].code), ["FORWARD :size", "RETURN"]);
+    }
+
+    #[test]
+    fn dumps_list_each_instruction_with_its_line()
+    {
+        let dump = dump(&compile_source("TO B\nEND\nTO A \"x\nEND\nIF TRUE [\n  A 1\n]\n"), &Sources::default());
+        // Columns are padded to line up, which does not matter here
+        let lines: Vec<String> = dump.lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect();
+        assert_eq!(lines, [
+            "main:",
+            "0 IF \"TRUE else -> 2 ; line 5",
+            "1 CALL A \"1 ; line 6",
+            "",
+            "TO A \"x:",
+            "0 RETURN ; line 3",
+            "",
+            "TO B:",
+            "0 RETURN ; line 1",
+        ]);
+    }
+
+    #[test]
+    fn dumps_name_the_file_of_lines_outside_the_program()
+    {
+        let mut sources = Sources::default();
+        let path = std::path::Path::new("prog.lg");
+        let tokens = crate::load::expand("POLYGON 3 10\n", path, true, crate::dialect::Dialect::Strict, &mut sources)
+            .unwrap();
+        let dump = dump(&compile(parser::parse(&tokens).unwrap()), &sources);
+        assert!(dump.lines().any(|line| line.contains("CALL POLYGON") && line.ends_with("; line 1")), "{dump}");
+        assert!(dump.lines().any(|line| line.ends_with(" of <prelude>")), "{dump}");
+        assert!(!dump.lines().any(|line| line.contains("of prog.lg")), "{dump}");
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use unsvg::{Color, Image};
+use crate::ascii::AsciiCanvas;
+use crate::plotter::{PlotterCanvas, PlotterFormat};
+use crate::svg::SvgCanvas;
+
+/// Somewhere the turtle can draw.
+///
+/// The interpreter only ever draws straight lines and filled polygons, so
+/// that is all a backend has to support. Points are in pixels, with the
+/// origin at the top left and y increasing downwards.
+pub trait Canvas {
+    fn dimensions(&self) -> (u32, u32);
+
+    fn set_background(&mut self, color: Color);
+
+    /// Draws a straight line `width` pixels wide.
+    fn line(&mut self, start: (f32, f32), end: (f32, f32), color: Color, width: f32) -> Result<(), String>;
+
+    /// Marks where a fill starts, so that backends which layer their output
+    /// can put the fill beneath the lines drawn while it was recorded.
+    fn begin_fill(&mut self) {}
+
+    /// Fills the polygon through `points`.
+    fn end_fill(&mut self, points: &[(f32, f32)], color: Color) -> Result<(), String>;
+
+    /// Called when a procedure starts, so that backends can group what it draws.
+    fn begin_group(&mut self, _name: &str) {}
+
+    fn end_group(&mut self) {}
+
+    /// Writes the output to `path`.
+    fn save(&self, path: &Path) -> Result<(), String>;
+
+    /// The output as text, for formats that can be printed to a terminal.
+    fn to_text(&self) -> Option<String> {
+        None
+    }
+}
+
+/// The output formats a program can be drawn in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// SVG written directly, with each procedure call in its own group.
+    Svg,
+    /// The SVG rendered to an image.
+    Png,
+    /// SVG or PNG drawn with `unsvg`, which only supports whole-pixel lines
+    /// of width 1 on a black background, and no fills.
+    Unsvg,
+    /// A rough text preview.
+    Ascii,
+    /// Every line and fill as JSON.
+    Json,
+    /// Moves for a pen plotter.
+    Gcode,
+}
+
+impl Format {
+    /// The format usually stored in files with the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "svg" => Some(Format::Svg),
+            "png" => Some(Format::Png),
+            "txt" => Some(Format::Ascii),
+            "json" => Some(Format::Json),
+            "gcode" | "gco" | "nc" => Some(Format::Gcode),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "svg" => Ok(Format::Svg),
+            "png" => Ok(Format::Png),
+            "unsvg" => Ok(Format::Unsvg),
+            "ascii" => Ok(Format::Ascii),
+            "json" => Ok(Format::Json),
+            "gcode" => Ok(Format::Gcode),
+            _ => Err(format!("unknown format '{s}', expected svg, png, unsvg, ascii, json or gcode")),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Svg => "svg",
+            Format::Png => "png",
+            Format::Unsvg => "unsvg",
+            Format::Ascii => "ascii",
+            Format::Json => "json",
+            Format::Gcode => "gcode",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Where (0, 0) is, in the coordinates a program sees through XCOR, YCOR,
+/// SETX and SETY.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Origin {
+    /// The top left corner of the canvas, so coordinates are pixels.
+    #[default]
+    TopLeft,
+    Center,
+}
+
+impl FromStr for Origin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Origin, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "top-left" => Ok(Origin::TopLeft),
+            "center" => Ok(Origin::Center),
+            _ => Err(format!("unknown origin '{s}', expected top-left or center")),
+        }
+    }
+}
+
+/// Which way y increases, in the coordinates a program sees.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum YAxis {
+    #[default]
+    Down,
+    Up,
+}
+
+impl FromStr for YAxis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<YAxis, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "down" => Ok(YAxis::Down),
+            "up" => Ok(YAxis::Up),
+            _ => Err(format!("unknown y-axis '{s}', expected down or up")),
+        }
+    }
+}
+
+/// Converts between the coordinates a program sees and pixels on the
+/// canvas. The turtle itself always moves in pixels, so headings keep their
+/// meaning on screen whichever way y points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Axes {
+    /// Where (0, 0) is on the canvas.
+    origin: (f32, f32),
+    y_up: bool,
+}
+
+impl Default for Axes {
+    fn default() -> Axes {
+        Axes { origin: (0.0, 0.0), y_up: false }
+    }
+}
+
+impl Axes {
+    pub fn new(origin: Origin, y_axis: YAxis, width: u32, height: u32) -> Axes {
+        let origin = match origin {
+            Origin::TopLeft => (0.0, 0.0),
+            // The same point the turtle starts at
+            Origin::Center => ((width / 2) as f32, (height / 2) as f32),
+        };
+        Axes { origin, y_up: y_axis == YAxis::Up }
+    }
+
+    pub fn x_to_canvas(&self, x: f32) -> f32 {
+        self.origin.0 + x
+    }
+
+    pub fn y_to_canvas(&self, y: f32) -> f32 {
+        if self.y_up { self.origin.1 - y } else { self.origin.1 + y }
+    }
+
+    pub fn x_from_canvas(&self, x: f32) -> f32 {
+        x - self.origin.0
+    }
+
+    pub fn y_from_canvas(&self, y: f32) -> f32 {
+        if self.y_up { self.origin.1 - y } else { y - self.origin.1 }
+    }
+}
+
+/// Creates an empty canvas for `format`. Both dimensions must be greater
+/// than zero. `layers` puts each pen color on its own layer in SVG and PNG
+/// output.
+pub fn create_canvas(format: Format, width: u32, height: u32, layers: bool) -> Box<dyn Canvas>
+{
+    match format {
+        Format::Svg | Format::Png => {
+            let mut canvas = SvgCanvas::new(width, height);
+            canvas.layers = layers;
+            canvas.png = format == Format::Png;
+            Box::new(canvas)
+        },
+        Format::Unsvg => Box::new(UnsvgCanvas::new(width, height)),
+        Format::Ascii => Box::new(AsciiCanvas::new(width, height)),
+        Format::Json => Box::new(PlotterCanvas::new(width, height, PlotterFormat::Json)),
+        Format::Gcode => Box::new(PlotterCanvas::new(width, height, PlotterFormat::Gcode)),
+    }
+}
+
+/// Formats a color as `#rrggbb`.
+pub fn hex(color: Color) -> String
+{
+    format!("#{:02x}{:02x}{:02x}", color.red, color.green, color.blue)
+}
+
+/// Draws with `unsvg::Image`, as rslogo always used to.
+pub struct UnsvgCanvas {
+    image: Image,
+}
+
+impl UnsvgCanvas {
+    pub fn new(width: u32, height: u32) -> UnsvgCanvas {
+        UnsvgCanvas { image: Image::new(width, height) }
+    }
+}
+
+impl Canvas for UnsvgCanvas {
+    fn dimensions(&self) -> (u32, u32) {
+        self.image.get_dimensions()
+    }
+
+    fn set_background(&mut self, _color: Color) {}
+
+    /// unsvg only draws lines given as a start, a whole-degree direction
+    /// and a length, so the line is converted to that form and may end up
+    /// slightly off. The cursor keeps its exact position, so the error never
+    /// builds up. The width is ignored.
+    fn line(&mut self, start: (f32, f32), end: (f32, f32), color: Color, _width: f32) -> Result<(), String> {
+        let dx = end.0 - start.0;
+        let dy = end.1 - start.1;
+        let direction = (dy.atan2(dx).to_degrees() + 90.0).round() as i32;
+        self.image.draw_simple_line(start.0, start.1, direction, dx.hypot(dy), color).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn end_fill(&mut self, _points: &[(f32, f32)], _color: Color) -> Result<(), String> {
+        Err("the unsvg format cannot draw fills".to_string())
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        match path.extension().and_then(|s| s.to_str()) {
+            Some("png") => self.image.save_png(path),
+            _ => self.image.save_svg(path),
+        }
+        .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashSet;
+use crate::errors::{ErrorKind, LogoError};
+use crate::parser;
+use crate::structs::{Expression, ExpressionKind, Procedure, Program, Span, Statement, StatementKind, Token, TokenKind, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The program is certain to fail if this part of it runs.
+    Error,
+    /// The program might fail, depending on which branches run.
+    Warning,
+}
+
+impl Severity {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// A problem found by `check`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub error: LogoError,
+}
+
+impl Diagnostic {
+    fn error(error: LogoError) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, error }
+    }
+
+    fn warning(error: LogoError) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, error }
+    }
+
+    pub fn render(&self, source: &str, path: &str) -> String {
+        self.error.render_as(self.severity.label(), source, path)
+    }
+}
+
+/// Finds every problem in a program that can be spotted without running it:
+/// unbalanced brackets and `TO`/`END`, anything the parser rejects such as
+/// the wrong number of arguments, and variables that are used before they
+/// are made. Problems are returned in the order they appear in the source.
+pub fn check(tokens: &[Token]) -> Vec<Diagnostic>
+{
+    let structure = check_structure(tokens);
+    let (program, errors) = parser::parse_recovering(tokens);
+
+    // Unbalanced brackets or definitions throw the parser off, so when there
+    // are any, only the structural check's account of them is kept
+    let mut diagnostics: Vec<Diagnostic> = errors.into_iter()
+        .filter(|err| structure.is_empty() || !is_structural(&err.kind))
+        .map(Diagnostic::error)
+        .collect();
+    diagnostics.extend(structure.into_iter().map(Diagnostic::error));
+    diagnostics.extend(check_variables(&program));
+
+    diagnostics.sort_by_key(|diagnostic| {
+        let span = diagnostic.error.span;
+        (span.file, span.line, span.column)
+    });
+    diagnostics.dedup();
+    diagnostics
+}
+
+fn is_structural(kind: &ErrorKind) -> bool
+{
+    matches!(
+        kind,
+        ErrorKind::UnmatchedCloseBracket | ErrorKind::MissingCloseBracket | ErrorKind::UnmatchedCloseParen
+            | ErrorKind::MissingCloseParen | ErrorKind::NestedDefinition | ErrorKind::EndWithoutTo
+            | ErrorKind::MissingEnd
+    )
+}
+
+/// Matches up brackets, parentheses and `TO`/`END` by themselves, so that
+/// each one out of place is reported where it is rather than wherever the
+/// parser gives up.
+fn check_structure(tokens: &[Token]) -> Vec<LogoError>
+{
+    let mut errors = Vec::new();
+    let mut open: Vec<&Token> = Vec::new();
+    let mut definition: Option<(&Token, String)> = None;
+
+    for (index, token) in tokens.iter().enumerate() {
+        match &token.kind {
+            TokenKind::OpenBracket | TokenKind::OpenParen => open.push(token),
+            TokenKind::CloseBracket => {
+                close(&mut open, &mut errors, token, TokenKind::OpenBracket, ErrorKind::UnmatchedCloseBracket);
+            },
+            TokenKind::CloseParen => {
+                close(&mut open, &mut errors, token, TokenKind::OpenParen, ErrorKind::UnmatchedCloseParen);
+            },
+            TokenKind::Word(word) if word == "TO" => {
+                let name = match tokens.get(index + 1) {
+                    Some(Token { kind: TokenKind::Word(name), .. }) => name.clone(),
+                    _ => word.clone(),
+                };
+                if let Some((to, name)) = definition.replace((token, name)) {
+                    errors.push(LogoError::new(ErrorKind::MissingEnd, to.span, name));
+                }
+            },
+            TokenKind::Word(word) if word == "END" => {
+                let closed = definition.take();
+                if closed.is_none() {
+                    errors.push(LogoError::new(ErrorKind::EndWithoutTo, token.span, word));
+                }
+            },
+            _ => {},
+        }
+    }
+
+    for token in open {
+        let kind = match token.kind {
+            TokenKind::OpenBracket => ErrorKind::MissingCloseBracket,
+            _ => ErrorKind::MissingCloseParen,
+        };
+        errors.push(LogoError::new(kind, token.span, token.kind.to_string()));
+    }
+    if let Some((to, name)) = definition {
+        errors.push(LogoError::new(ErrorKind::MissingEnd, to.span, name));
+    }
+    errors
+}
+
+/// Closes the innermost opener of the same kind as `close`. Any openers
+/// inside it are left unclosed.
+fn close(open: &mut Vec<&Token>, errors: &mut Vec<LogoError>, close: &Token, opener: TokenKind, unmatched: ErrorKind)
+{
+    match open.iter().rposition(|token| token.kind == opener) {
+        Some(index) => {
+            for token in open.drain(index..).skip(1) {
+                let kind = match token.kind {
+                    TokenKind::OpenBracket => ErrorKind::MissingCloseBracket,
+                    _ => ErrorKind::MissingCloseParen,
+                };
+                errors.push(LogoError::new(kind, token.span, token.kind.to_string()));
+            }
+        },
+        None => errors.push(LogoError::new(unmatched, close.span, close.kind.to_string())),
+    }
+}
+
+/// Reports variables that are read, or added to, where they cannot have been
+/// made yet.
+///
+/// At the top level, a variable is certainly made once a MAKE of it has run
+/// on every path there. Procedures can also read any global variable, which
+/// they are given the benefit of the doubt for, since they may be called
+/// from anywhere.
+fn check_variables(program: &Program) -> Vec<Diagnostic>
+{
+    let mut globals = HashSet::new();
+    collect_made(&program.statements, &mut globals);
+
+    let mut checker = VariableChecker { possible: globals.clone(), diagnostics: Vec::new() };
+    checker.check_block(&program.statements, &mut HashSet::new());
+
+    for definition in program.procedures.values() {
+        let mut certain: HashSet<String> = globals.iter().chain(&definition.parameters).cloned().collect();
+        let mut possible = certain.clone();
+        collect_made(&definition.body, &mut possible);
+        checker.possible = possible;
+        checker.check_block(&definition.body, &mut certain);
+    }
+    checker.diagnostics
+}
+
+/// Every variable made anywhere in `block`, including inside nested blocks.
+fn collect_made(block: &[Statement], made: &mut HashSet<String>)
+{
+    for statement in block {
+        match &statement.kind {
+            StatementKind::Procedure(Procedure::MAKE(name, _)) => {
+                made.insert(name.clone());
+            },
+            StatementKind::If(_, body) | StatementKind::While(_, body) | StatementKind::Repeat(_, body)
+            | StatementKind::Ask(_, body) => {
+                collect_made(body, made);
+            },
+            StatementKind::Foreach(name, _, body) => {
+                made.insert(name.clone());
+                collect_made(body, made);
+            },
+            StatementKind::LSystem(lsystem) => {
+                for (procedure, _) in lsystem.actions.iter().flat_map(|action| &action.procedures) {
+                    if let Procedure::MAKE(name, _) = procedure {
+                        made.insert(name.clone());
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+struct VariableChecker {
+    /// Variables that might have been made somewhere in the code being checked.
+    possible: HashSet<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl VariableChecker {
+    /// Checks a block, given the variables `certain` to be made before it
+    /// runs, and adds those it certainly makes.
+    fn check_block(&mut self, block: &[Statement], certain: &mut HashSet<String>) {
+        for statement in block {
+            match &statement.kind {
+                StatementKind::Procedure(procedure) => self.check_procedure(procedure, statement.span, certain),
+                StatementKind::If(condition, body) | StatementKind::While(condition, body) => {
+                    self.check_expression(condition, certain);
+                    self.check_block(body, &mut certain.clone());
+                },
+                StatementKind::Repeat(count, body) => {
+                    self.check_expression(count, certain);
+                    let mut inner = certain.clone();
+                    self.check_block(body, &mut inner);
+                    if runs_at_least_once(count) {
+                        *certain = inner;
+                    }
+                },
+                StatementKind::Ask(turtle, body) => {
+                    // The block always runs, unless the turtle does not exist
+                    self.check_expression(turtle, certain);
+                    self.check_block(body, certain);
+                },
+                StatementKind::Foreach(name, list, body) => {
+                    self.check_expression(list, certain);
+                    let mut inner = certain.clone();
+                    inner.insert(name.clone());
+                    self.check_block(body, &mut inner);
+                },
+                StatementKind::LSystem(lsystem) => {
+                    self.check_expression(&lsystem.axiom, certain);
+                    self.check_expression(&lsystem.rules, certain);
+                    self.check_expression(&lsystem.iterations, certain);
+                    // Actions may run in any order, or not at all
+                    for action in &lsystem.actions {
+                        let mut inner = certain.clone();
+                        for (procedure, span) in &action.procedures {
+                            self.check_procedure(procedure, *span, &mut inner);
+                        }
+                    }
+                },
+                StatementKind::Call(_, arguments) => {
+                    for argument in arguments {
+                        self.check_expression(argument, certain);
+                    }
+                },
+            }
+        }
+    }
+
+    fn check_procedure(&mut self, procedure: &Procedure, span: Span, certain: &mut HashSet<String>) {
+        for argument in procedure.arguments() {
+            self.check_expression(argument, certain);
+        }
+        match procedure {
+            Procedure::MAKE(name, _) => {
+                certain.insert(name.clone());
+            },
+            Procedure::ADDASSIGN(name, _) => {
+                let token = format!("\"{name}");
+                self.check_use(name, LogoError::new(ErrorKind::UnknownVariable, span, token), certain);
+            },
+            _ => {},
+        }
+    }
+
+    fn check_expression(&mut self, expression: &Expression, certain: &HashSet<String>) {
+        match &expression.kind {
+            ExpressionKind::Variable(name) => {
+                let err = LogoError::new(ErrorKind::UnknownVariable, expression.span, expression.token());
+                self.check_use(name, err, certain);
+            },
+            ExpressionKind::Binary(_, left, right) => {
+                self.check_expression(left, certain);
+                self.check_expression(right, certain);
+            },
+            ExpressionKind::Negate(operand) => self.check_expression(operand, certain),
+            ExpressionKind::Function(_, arguments) => {
+                for argument in arguments {
+                    self.check_expression(argument, certain);
+                }
+            },
+            // Lists are data, so `:name` inside one is not a use
+            ExpressionKind::Value(_) | ExpressionKind::Query(_) | ExpressionKind::List(_) => {},
+        }
+    }
+
+    /// Reports `err` if `name` is never made, or a warning if it might not be yet.
+    fn check_use(&mut self, name: &str, mut err: LogoError, certain: &HashSet<String>) {
+        if certain.contains(name) {
+            return;
+        }
+        if self.possible.contains(name) {
+            err.kind = ErrorKind::PossiblyUnknownVariable;
+            self.diagnostics.push(Diagnostic::warning(err));
+        }
+        else {
+            self.diagnostics.push(Diagnostic::error(err));
+        }
+    }
+}
+
+/// Whether a REPEAT count is written as a number of at least one.
+fn runs_at_least_once(count: &Expression) -> bool
+{
+    match &count.kind {
+        ExpressionKind::Value(word) => matches!(Value::from_literal(word), Value::Number(n) if n >= 1.0),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+
+    /// Every problem found in `source`, and where it is.
+    fn diagnose(source: &str) -> Vec<(Severity, ErrorKind, usize, usize)>
+    {
+        check(&lexer::tokenize(source)).into_iter()
+            .map(|diagnostic| {
+                let span = diagnostic.error.span;
+                (diagnostic.severity, diagnostic.error.kind, span.line, span.column)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_every_structural_problem_where_it_is()
+    {
+        let source = "\
+]
+REPEAT 2 [ FORWARD (1
+END
+TO Box
+  FORWARD 1
+";
+        assert_eq!(diagnose(source), [
+            (Severity::Error, ErrorKind::UnmatchedCloseBracket, 1, 1),
+            (Severity::Error, ErrorKind::MissingCloseBracket, 2, 10),
+            (Severity::Error, ErrorKind::MissingCloseParen, 2, 20),
+            (Severity::Error, ErrorKind::EndWithoutTo, 3, 1),
+            (Severity::Error, ErrorKind::MissingEnd, 4, 1),
+        ]);
+    }
+
+    #[test]
+    fn reports_problems_the_parser_finds_after_the_first()
+    {
+        assert_eq!(diagnose("FORWARD\nTURN 1 2\nMAKE \"a 1\nA :a\n"), [
+            (Severity::Error, ErrorKind::NotEnoughArgs, 1, 1),
+            (Severity::Error, ErrorKind::TooManyArgs, 2, 8),
+            (Severity::Error, ErrorKind::UnknownProcedure, 4, 1),
+        ]);
+    }
+
+    #[test]
+    fn tells_unknown_variables_from_possibly_unknown_ones()
+    {
+        let source = "\
+IF TRUE [ MAKE \"maybe 1 ]
+REPEAT 1 [ MAKE \"once 1 ]
+FORWARD :maybe
+FORWARD :once
+ADDASSIGN \"never 1
+FOREACH \"item [1] [ FORWARD :item ]
+TO Box \"size
+  FORWARD :size + :maybe
+  FORWARD :local
+END
+";
+        assert_eq!(diagnose(source), [
+            (Severity::Warning, ErrorKind::PossiblyUnknownVariable, 3, 9),
+            (Severity::Error, ErrorKind::UnknownVariable, 5, 1),
+            (Severity::Error, ErrorKind::UnknownVariable, 9, 11),
+        ]);
+    }
+}
@@ -0,0 +1,279 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use crate::parser;
+use crate::structs::{Comment, Span, Token, TokenKind};
+
+/// Abbreviations UCBLogo programs use for the most common commands.
+const ABBREVIATIONS: [(&str, &str); 6] = [
+    ("FD", "FORWARD"), ("BK", "BACK"), ("LT", "LEFT"), ("RT", "RIGHT"), ("PU", "PENUP"), ("PD", "PENDOWN"),
+];
+
+/// The syntax a program is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Dialect {
+    /// Uppercase keywords, procedure names exactly as defined, and
+    /// parameters written as `"name`.
+    #[default]
+    Strict,
+    /// As in UCBLogo: keywords and names in any case, `FD`, `BK`, `LT`, `RT`,
+    /// `PU` and `PD`, parameters written as `:name`, and `;` comments. LEFT
+    /// and RIGHT turn the turtle rather than moving it sideways.
+    Ucb,
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Dialect, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(Dialect::Strict),
+            "ucb" => Ok(Dialect::Ucb),
+            _ => Err(format!("unknown dialect '{s}', expected strict or ucb")),
+        }
+    }
+}
+
+impl Dialect {
+    /// Removes what the dialect treats as comments, leaving everything before
+    /// them where it was so that spans still match the source. In the UCB
+    /// dialect a `;` starts a comment wherever it is, as in `fd 10;back`.
+    pub fn without_comments(self, source: &str) -> Cow<'_, str> {
+        if self == Dialect::Strict || !source.contains(';') {
+            return Cow::Borrowed(source);
+        }
+        let lines = source.split_inclusive('\n').map(|line| match line.find(';') {
+            Some(start) => {
+                let end = if line.ends_with("\r\n") { "\r\n" } else if line.ends_with('\n') { "\n" } else { "" };
+                format!("{}{end}", &line[..start])
+            },
+            None => line.to_string(),
+        });
+        Cow::Owned(lines.collect())
+    }
+
+    /// The comments `without_comments` removes, for tools that write the
+    /// program back out.
+    pub fn comments(self, source: &str) -> Vec<Comment> {
+        if self == Dialect::Strict {
+            return Vec::new();
+        }
+        source.lines()
+            .enumerate()
+            .filter_map(|(index, line)| {
+                let start = line.find(';')?;
+                let text = line[start..].trim_end();
+                let column = line[..start].chars().count() + 1;
+                Some(Comment { text: text.to_string(), span: Span { line: index + 1, column, length: text.chars().count(), file: 0 } })
+            })
+            .collect()
+    }
+
+    /// Whether `word`, in uppercase, is one of the dialect's abbreviations
+    /// for a command, such as `FD`.
+    pub fn is_abbreviation(self, word: &str) -> bool {
+        self == Dialect::Ucb && ABBREVIATIONS.iter().any(|(short, _)| *short == word)
+    }
+
+    /// Rewrites the tokens of one file, with comments already removed by
+    /// `without_comments`, into the strict syntax the parser reads. Lists
+    /// that are data rather than blocks of statements are left as written.
+    /// Spans are kept, so errors point at what was written.
+    pub fn translate(self, tokens: Vec<Token>) -> Vec<Token> {
+        match self {
+            Dialect::Strict => tokens,
+            Dialect::Ucb => {
+                let translated = translate_ucb(&tokens);
+                let data = data_lists(&translated);
+                let tokens = tokens.into_iter()
+                    .zip(translated)
+                    .zip(&data)
+                    .map(|((written, translated), data)| if *data { written } else { translated })
+                    .collect::<Vec<Token>>();
+                turns(tokens, &data)
+            },
+        }
+    }
+
+    /// Writes each call to a procedure that `known` says is defined the way
+    /// its definition names it, for calls to procedures from other files,
+    /// which `translate` cannot see. Names are known in uppercase.
+    pub fn name_calls(self, tokens: &mut [Token], known: impl Fn(&str) -> bool) {
+        if self == Dialect::Strict {
+            return;
+        }
+        for token in tokens {
+            if let TokenKind::Word(word) = &mut token.kind {
+                let upper = word.to_ascii_uppercase();
+                if known(&upper) {
+                    *word = upper;
+                }
+            }
+        }
+    }
+}
+
+/// Translates each token on its own, so that the result lines up with
+/// `tokens` one for one.
+fn translate_ucb(tokens: &[Token]) -> Vec<Token>
+{
+    // Names are compared in uppercase, so that a procedure can be called in
+    // a different case from its definition
+    let defined: HashSet<String> = tokens.windows(2)
+        .filter_map(|pair| match (&pair[0].kind, &pair[1].kind) {
+            (TokenKind::Word(to), TokenKind::Word(name)) if to.eq_ignore_ascii_case("TO") => {
+                Some(name.to_ascii_uppercase())
+            },
+            _ => None,
+        })
+        .collect();
+
+    let mut translated: Vec<Token> = Vec::with_capacity(tokens.len());
+    // The line of the `TO` header being read, if any
+    let mut header = None;
+    for token in tokens {
+        let line = token.span.line;
+        if header != Some(line) {
+            header = None;
+        }
+
+        let kind = match token.kind.clone() {
+            TokenKind::Word(word) => {
+                let upper = word.to_ascii_uppercase();
+                let upper = ABBREVIATIONS.iter()
+                    .find(|(short, _)| *short == upper)
+                    .map_or(upper, |(_, long)| long.to_string());
+                if upper == "TO" {
+                    header = Some(line);
+                }
+                if parser::is_reserved(&upper) || upper == "TRUE" || upper == "FALSE" || defined.contains(&upper) {
+                    TokenKind::Word(upper)
+                }
+                else {
+                    TokenKind::Word(word)
+                }
+            },
+            // `TO SQUARE :size` names the parameter `"SIZE`
+            TokenKind::Variable(name) | TokenKind::Value(name) if header.is_some() => {
+                TokenKind::Value(name.to_ascii_uppercase())
+            },
+            TokenKind::Variable(name) => TokenKind::Variable(name.to_ascii_uppercase()),
+            TokenKind::Value(name) if names_variable(translated.last()) => TokenKind::Value(name.to_ascii_uppercase()),
+            kind => kind,
+        };
+        translated.push(Token { kind, span: token.span });
+    }
+    translated
+}
+
+/// What the tokens inside a pair of brackets are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Brackets {
+    /// Statements, as in the block of a REPEAT.
+    Block,
+    /// The symbols and blocks of an LSYSTEM's actions.
+    Actions,
+    /// A list of values.
+    Data,
+}
+
+/// Marks each of `tokens` that is part of a list of values rather than a
+/// block of statements, counting the list's own brackets. A `[` opens a
+/// block only where a statement such as IF or REPEAT expects one, after its
+/// arguments.
+fn data_lists(tokens: &[Token]) -> Vec<bool>
+{
+    let mut opened: HashMap<usize, Brackets> = HashMap::new();
+    for (index, token) in tokens.iter().enumerate() {
+        let TokenKind::Word(word) = &token.kind else {
+            continue;
+        };
+        let (names, arguments, brackets) = match word.as_str() {
+            "IF" | "WHILE" | "REPEAT" | "ASK" => (0, 1, Brackets::Block),
+            "FOREACH" => (1, 1, Brackets::Block),
+            "LSYSTEM" => (0, 3, Brackets::Actions),
+            _ => continue,
+        };
+        let mut position = Some(index + 1 + names);
+        for _ in 0..arguments {
+            position = position.and_then(|start| Some(start + parser::argument_length(tokens.get(start..)?)?));
+        }
+        if let Some(position) = position.filter(|position| {
+            tokens.get(*position).is_some_and(|token| token.kind == TokenKind::OpenBracket)
+        }) {
+            opened.insert(position, brackets);
+        }
+    }
+
+    let mut open: Vec<Brackets> = Vec::new();
+    let mut data = Vec::with_capacity(tokens.len());
+    for (index, token) in tokens.iter().enumerate() {
+        let inside = open.last().copied().unwrap_or(Brackets::Block);
+        match token.kind {
+            TokenKind::OpenBracket => {
+                let brackets = match inside {
+                    Brackets::Data => Brackets::Data,
+                    Brackets::Actions => Brackets::Block,
+                    Brackets::Block => opened.get(&index).copied().unwrap_or(Brackets::Data),
+                };
+                data.push(brackets == Brackets::Data);
+                open.push(brackets);
+            },
+            TokenKind::CloseBracket => {
+                data.push(inside == Brackets::Data);
+                open.pop();
+            },
+            _ => data.push(inside == Brackets::Data),
+        }
+    }
+    data
+}
+
+/// Replaces `LEFT angle` with `TURN -(angle)` and `RIGHT` with `TURN`, other
+/// than in the tokens `data` marks as part of a list of values.
+fn turns(tokens: Vec<Token>, data: &[bool]) -> Vec<Token>
+{
+    let mut translated = Vec::with_capacity(tokens.len());
+    let mut index = 0;
+    while index < tokens.len() {
+        let token = &tokens[index];
+        index += 1;
+        let left = match &token.kind {
+            _ if data[index - 1] => {
+                translated.push(token.clone());
+                continue;
+            },
+            TokenKind::Word(word) if word == "LEFT" => true,
+            TokenKind::Word(word) if word == "RIGHT" => false,
+            _ => {
+                translated.push(token.clone());
+                continue;
+            },
+        };
+        translated.push(Token { kind: TokenKind::Word("TURN".to_string()), span: token.span });
+        // Without an angle to negate, TURN reports the missing argument
+        let length = match parser::argument_length(&tokens[index..]) {
+            Some(length) if left => length,
+            _ => continue,
+        };
+
+        // The minus is stuck to the bracket so that it negates everything inside
+        let Span { line, column, file, .. } = token.span;
+        let last = tokens[index + length - 1].span;
+        translated.push(Token { kind: TokenKind::Word("-".to_string()), span: Span { line, column, length: 1, file } });
+        translated.push(Token { kind: TokenKind::OpenParen, span: Span { line, column: column + 1, length: 0, file } });
+        translated.extend_from_slice(&tokens[index..index + length]);
+        translated.push(Token { kind: TokenKind::CloseParen, span: Span { column: last.column + last.length, length: 0, ..last } });
+        index += length;
+    }
+    translated
+}
+
+/// Whether the value after `previous` is the name of a variable.
+fn names_variable(previous: Option<&Token>) -> bool
+{
+    matches!(
+        previous.map(|token| &token.kind),
+        Some(TokenKind::Word(word)) if word == "MAKE" || word == "ADDASSIGN" || word == "FOREACH"
+    )
+}
@@ -0,0 +1,265 @@
+use std::fmt;
+use crate::structs::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    // Raised while loading files
+    /// A file named by LOAD could not be read.
+    LoadFailed(String),
+    LoadCycle,
+    LoadNotExpanded,
+
+    // Raised while parsing
+    TooManyArgs,
+    NotEnoughArgs,
+    UnexpectedBracket,
+    UnmatchedCloseBracket,
+    MissingOpenBracket,
+    MissingCloseBracket,
+    UnmatchedCloseParen,
+    MissingCloseParen,
+    ExpectedName,
+    ExpectedExpression,
+    UnknownProcedure,
+    UnknownValue,
+    NestedDefinition,
+    EndWithoutTo,
+    MissingEnd,
+    DuplicateProcedure,
+    RedefinedBuiltin,
+    NestingTooDeep,
+    InvalidLSystemSymbol,
+    InvalidLSystemAction,
+
+    // Found by the checker
+    PossiblyUnknownVariable,
+
+    // Raised while running
+    UnknownVariable,
+    TypeMismatch { expected: &'static str, found: &'static str },
+    IndexOutOfRange,
+    EmptyList,
+    InvalidCount,
+    InvalidArgument,
+    InvalidPenColor,
+    InvalidRgb,
+    InvalidPenSize,
+    FillAlreadyStarted,
+    /// Another turtle, named here, is filling already.
+    FillByAnotherTurtle(String),
+    EndFillWithoutBeginFill,
+    PopStateWithoutPushState,
+    UnknownTurtle,
+    DuplicateTurtle,
+    InvalidLSystemRule,
+    LSystemTooLarge,
+    DivisionByZero,
+    UndefinedProcedure,
+
+    // Limits on running programs
+    RecursionTooDeep,
+    StepLimitExceeded,
+    SegmentLimitExceeded,
+    TimeLimitExceeded,
+    OutOfBounds,
+    PenSizeTooLarge,
+    Draw(String),
+    /// A native procedure failed, with its own message.
+    Native(String),
+}
+
+impl ErrorKind {
+    /// Whether the error stopped a program for going over one of its limits,
+    /// rather than for doing something wrong.
+    pub fn is_limit(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::RecursionTooDeep | ErrorKind::StepLimitExceeded | ErrorKind::SegmentLimitExceeded
+                | ErrorKind::TimeLimitExceeded | ErrorKind::OutOfBounds | ErrorKind::PenSizeTooLarge
+        )
+    }
+
+    /// The variant's name, such as `UnknownVariable`, which stays the same
+    /// when the message is reworded.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::LoadFailed(_) => "LoadFailed",
+            ErrorKind::LoadCycle => "LoadCycle",
+            ErrorKind::LoadNotExpanded => "LoadNotExpanded",
+            ErrorKind::TooManyArgs => "TooManyArgs",
+            ErrorKind::NotEnoughArgs => "NotEnoughArgs",
+            ErrorKind::UnexpectedBracket => "UnexpectedBracket",
+            ErrorKind::UnmatchedCloseBracket => "UnmatchedCloseBracket",
+            ErrorKind::MissingOpenBracket => "MissingOpenBracket",
+            ErrorKind::MissingCloseBracket => "MissingCloseBracket",
+            ErrorKind::UnmatchedCloseParen => "UnmatchedCloseParen",
+            ErrorKind::MissingCloseParen => "MissingCloseParen",
+            ErrorKind::ExpectedName => "ExpectedName",
+            ErrorKind::ExpectedExpression => "ExpectedExpression",
+            ErrorKind::UnknownProcedure => "UnknownProcedure",
+            ErrorKind::UnknownValue => "UnknownValue",
+            ErrorKind::NestedDefinition => "NestedDefinition",
+            ErrorKind::EndWithoutTo => "EndWithoutTo",
+            ErrorKind::MissingEnd => "MissingEnd",
+            ErrorKind::DuplicateProcedure => "DuplicateProcedure",
+            ErrorKind::RedefinedBuiltin => "RedefinedBuiltin",
+            ErrorKind::NestingTooDeep => "NestingTooDeep",
+            ErrorKind::InvalidLSystemSymbol => "InvalidLSystemSymbol",
+            ErrorKind::InvalidLSystemAction => "InvalidLSystemAction",
+            ErrorKind::PossiblyUnknownVariable => "PossiblyUnknownVariable",
+            ErrorKind::UnknownVariable => "UnknownVariable",
+            ErrorKind::TypeMismatch { .. } => "TypeMismatch",
+            ErrorKind::IndexOutOfRange => "IndexOutOfRange",
+            ErrorKind::EmptyList => "EmptyList",
+            ErrorKind::InvalidCount => "InvalidCount",
+            ErrorKind::InvalidArgument => "InvalidArgument",
+            ErrorKind::InvalidPenColor => "InvalidPenColor",
+            ErrorKind::InvalidRgb => "InvalidRgb",
+            ErrorKind::InvalidPenSize => "InvalidPenSize",
+            ErrorKind::FillAlreadyStarted => "FillAlreadyStarted",
+            ErrorKind::FillByAnotherTurtle(_) => "FillByAnotherTurtle",
+            ErrorKind::EndFillWithoutBeginFill => "EndFillWithoutBeginFill",
+            ErrorKind::PopStateWithoutPushState => "PopStateWithoutPushState",
+            ErrorKind::UnknownTurtle => "UnknownTurtle",
+            ErrorKind::DuplicateTurtle => "DuplicateTurtle",
+            ErrorKind::InvalidLSystemRule => "InvalidLSystemRule",
+            ErrorKind::LSystemTooLarge => "LSystemTooLarge",
+            ErrorKind::DivisionByZero => "DivisionByZero",
+            ErrorKind::UndefinedProcedure => "UndefinedProcedure",
+            ErrorKind::RecursionTooDeep => "RecursionTooDeep",
+            ErrorKind::StepLimitExceeded => "StepLimitExceeded",
+            ErrorKind::SegmentLimitExceeded => "SegmentLimitExceeded",
+            ErrorKind::TimeLimitExceeded => "TimeLimitExceeded",
+            ErrorKind::OutOfBounds => "OutOfBounds",
+            ErrorKind::PenSizeTooLarge => "PenSizeTooLarge",
+            ErrorKind::Draw(_) => "Draw",
+            ErrorKind::Native(_) => "Native",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::LoadFailed(message) => write!(f, "Could not load file ({message})"),
+            ErrorKind::LoadCycle => write!(f, "File loads itself"),
+            ErrorKind::LoadNotExpanded => write!(f, "LOAD can only be used in a program loaded from a file"),
+            ErrorKind::TooManyArgs => write!(f, "Too many args"),
+            ErrorKind::NotEnoughArgs => write!(f, "Not enough args"),
+            ErrorKind::UnexpectedBracket => write!(f, "Unexpected '['"),
+            ErrorKind::UnmatchedCloseBracket => write!(f, "']' without matching '['"),
+            ErrorKind::MissingOpenBracket => write!(f, "Expected '[' to open block"),
+            ErrorKind::MissingCloseBracket => write!(f, "No matching bracket found"),
+            ErrorKind::UnmatchedCloseParen => write!(f, "')' without matching '('"),
+            ErrorKind::MissingCloseParen => write!(f, "Expected ')' to close '('"),
+            ErrorKind::ExpectedName => write!(f, "Expected a quoted name"),
+            ErrorKind::ExpectedExpression => write!(f, "Expected a value"),
+            ErrorKind::UnknownProcedure => write!(f, "Unknown procedure"),
+            ErrorKind::UnknownValue => write!(f, "Value not found"),
+            ErrorKind::NestedDefinition => write!(f, "Procedures can only be defined at the top level"),
+            ErrorKind::EndWithoutTo => write!(f, "END without matching TO"),
+            ErrorKind::MissingEnd => write!(f, "Missing END for procedure"),
+            ErrorKind::DuplicateProcedure => write!(f, "Procedure is already defined"),
+            ErrorKind::RedefinedBuiltin => write!(f, "Cannot redefine a built-in"),
+            ErrorKind::NestingTooDeep => write!(f, "Program is nested too deeply"),
+            ErrorKind::InvalidLSystemSymbol => write!(f, "L-system symbols must be single characters given one action each"),
+            ErrorKind::InvalidLSystemAction => write!(f, "L-system actions can only use built-in procedures"),
+            ErrorKind::PossiblyUnknownVariable => write!(f, "Variable might not be made before it is used"),
+            ErrorKind::UnknownVariable => write!(f, "No matching variable found"),
+            ErrorKind::TypeMismatch { expected, found } => write!(f, "Expected a {expected} but found a {found}"),
+            ErrorKind::IndexOutOfRange => write!(f, "Index out of range"),
+            ErrorKind::EmptyList => write!(f, "List is empty"),
+            ErrorKind::InvalidCount => write!(f, "Count must be a whole number that is not negative"),
+            ErrorKind::InvalidArgument => write!(f, "Argument out of range"),
+            ErrorKind::InvalidPenColor => write!(f, "Pen color must be a whole number from 0 to 15"),
+            ErrorKind::InvalidRgb => write!(f, "RGB colors must be three whole numbers from 0 to 255"),
+            ErrorKind::InvalidPenSize => write!(f, "Pen size must be greater than zero"),
+            ErrorKind::FillAlreadyStarted => write!(f, "BEGINFILL while already filling"),
+            ErrorKind::FillByAnotherTurtle(name) => {
+                write!(f, "BEGINFILL while turtle {name} is filling, and only one turtle can fill at a time")
+            },
+            ErrorKind::EndFillWithoutBeginFill => write!(f, "ENDFILL without matching BEGINFILL"),
+            ErrorKind::PopStateWithoutPushState => write!(f, "POPSTATE without matching PUSHSTATE"),
+            ErrorKind::UnknownTurtle => write!(f, "No turtle with that name"),
+            ErrorKind::DuplicateTurtle => write!(f, "There is already a turtle with that name"),
+            ErrorKind::InvalidLSystemRule => write!(f, "L-system rules must pair single symbols with what replaces them"),
+            ErrorKind::LSystemTooLarge => write!(f, "L-system grows past the expansion limit"),
+            ErrorKind::DivisionByZero => write!(f, "Division by zero"),
+            ErrorKind::UndefinedProcedure => write!(f, "Procedure is not defined"),
+            ErrorKind::RecursionTooDeep => write!(f, "Too many nested procedure calls"),
+            ErrorKind::StepLimitExceeded => write!(f, "Program ran for too many steps"),
+            ErrorKind::SegmentLimitExceeded => write!(f, "Program drew too many lines"),
+            ErrorKind::TimeLimitExceeded => write!(f, "Program ran for too long"),
+            ErrorKind::OutOfBounds => write!(f, "Turtle moved too far from the canvas"),
+            ErrorKind::PenSizeTooLarge => write!(f, "Pen size is larger than the largest coordinate allowed"),
+            ErrorKind::Draw(message) => write!(f, "Failed to draw: {message}"),
+            ErrorKind::Native(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+/// An error in a Logo program, pointing at the token that caused it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogoError {
+    pub kind: ErrorKind,
+    pub span: Span,
+    pub token: String,
+}
+
+impl LogoError {
+    pub fn new(kind: ErrorKind, span: Span, token: impl Into<String>) -> LogoError {
+        LogoError { kind, span, token: token.into() }
+    }
+
+    /// Formats the error with the offending line of `source` and a caret
+    /// underline, e.g.
+    ///
+    /// ```text
+    /// error[UnknownVariable]: No matching variable found: :angle
+    ///  --> prog.lg:5:6
+    ///   |
+    /// 5 | LEFT :angle
+    ///   |      ^^^^^^
+    /// ```
+    pub fn render(&self, source: &str, path: &str) -> String {
+        self.render_as("error", source, path)
+    }
+
+    /// Like `render`, but labelled as something other than an error, such as
+    /// a warning.
+    pub fn render_as(&self, label: &str, source: &str, path: &str) -> String {
+        let mut out = format!("{label}[{}]: {self}\n", self.kind.name());
+        let line = match self.span.line.checked_sub(1).and_then(|index| source.lines().nth(index)) {
+            Some(line) => line,
+            None => return out,
+        };
+
+        let number = self.span.line.to_string();
+        let gutter = " ".repeat(number.len());
+        // Reuse the line's own whitespace so tabs line up with the caret
+        let padding: String = line.chars()
+            .take(self.span.column.saturating_sub(1))
+            .map(|c| if c.is_whitespace() { c } else { ' ' })
+            .collect();
+        let carets = "^".repeat(self.span.length.max(1));
+
+        out.push_str(&format!("{gutter}--> {path}:{}:{}\n", self.span.line, self.span.column));
+        out.push_str(&format!("{gutter} |\n"));
+        out.push_str(&format!("{number} | {line}\n"));
+        out.push_str(&format!("{gutter} | {padding}{carets}\n"));
+        out
+    }
+}
+
+impl fmt::Display for LogoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.token.is_empty() {
+            write!(f, "{}", self.kind)
+        }
+        else {
+            write!(f, "{}: {}", self.kind, self.token)
+        }
+    }
+}
+
+impl std::error::Error for LogoError {}
@@ -0,0 +1,167 @@
+use std::path::Path;
+use unsvg::Color;
+use crate::canvas::Canvas;
+
+/// Builds an empty canvas to draw on.
+pub type CanvasFactory = dyn Fn() -> Box<dyn Canvas>;
+
+/// Space left around the drawing, as a fraction of the shorter side.
+const MARGIN: f32 = 0.05;
+
+/// Draws on a canvas as usual, but saves the drawing scaled and moved so
+/// that all of it fits inside the canvas, however far the turtle went.
+pub struct FitCanvas {
+    make: Box<CanvasFactory>,
+    /// Drawn on as the program runs, so errors show up where they happen.
+    canvas: Box<dyn Canvas>,
+    operations: Vec<Operation>,
+}
+
+/// Everything drawn, in order, to draw again once the size is known.
+enum Operation {
+    Background(Color),
+    Line { start: (f32, f32), end: (f32, f32), color: Color, width: f32 },
+    BeginFill,
+    EndFill { points: Vec<(f32, f32)>, color: Color },
+    BeginGroup(String),
+    EndGroup,
+}
+
+/// Maps points drawn onto points on the canvas.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Transform {
+    scale: f32,
+    from: (f32, f32),
+    to: (f32, f32),
+}
+
+impl Transform {
+    fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        (
+            self.to.0 + (x - self.from.0) * self.scale,
+            self.to.1 + (y - self.from.1) * self.scale,
+        )
+    }
+}
+
+impl FitCanvas {
+    pub fn new(make: Box<CanvasFactory>) -> FitCanvas {
+        let canvas = make();
+        FitCanvas { make, canvas, operations: Vec::new() }
+    }
+
+    /// The smallest box holding every line and fill, as (min x, min y,
+    /// max x, max y).
+    fn bounds(&self) -> Option<(f32, f32, f32, f32)> {
+        let mut bounds: Option<(f32, f32, f32, f32)> = None;
+        let mut include = |(x, y): (f32, f32)| {
+            let (min_x, min_y, max_x, max_y) = bounds.unwrap_or((x, y, x, y));
+            bounds = Some((min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)));
+        };
+        for operation in &self.operations {
+            match operation {
+                Operation::Line { start, end, .. } => {
+                    include(*start);
+                    include(*end);
+                },
+                Operation::EndFill { points, .. } => {
+                    for point in points {
+                        include(*point);
+                    }
+                },
+                _ => {},
+            }
+        }
+        bounds
+    }
+
+    /// Scales the drawing to fill the canvas less a margin, centred on it.
+    /// Pen sizes stay as they were, so that lines stay visible.
+    fn transform(&self) -> Transform {
+        let (width, height) = self.canvas.dimensions();
+        let (width, height) = (width as f32, height as f32);
+        let to = (width / 2.0, height / 2.0);
+        let Some((min_x, min_y, max_x, max_y)) = self.bounds() else {
+            return Transform { scale: 1.0, from: to, to };
+        };
+
+        let margin = width.min(height) * MARGIN;
+        let from = ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0);
+        let scale_x = (width - 2.0 * margin) / (max_x - min_x);
+        let scale_y = (height - 2.0 * margin) / (max_y - min_y);
+        // A single point, or lines along one axis, only limit the scale one way
+        let scale = match scale_x.min(scale_y) {
+            scale if scale.is_finite() && scale > 0.0 => scale,
+            _ => 1.0,
+        };
+        Transform { scale, from, to }
+    }
+
+    /// A new canvas with everything drawn again to fit.
+    fn fitted(&self) -> Result<Box<dyn Canvas>, String> {
+        let transform = self.transform();
+        let mut canvas = (self.make)();
+        for operation in &self.operations {
+            match operation {
+                Operation::Background(color) => canvas.set_background(*color),
+                Operation::Line { start, end, color, width } => {
+                    canvas.line(transform.apply(*start), transform.apply(*end), *color, *width)?;
+                },
+                Operation::BeginFill => canvas.begin_fill(),
+                Operation::EndFill { points, color } => {
+                    let points: Vec<(f32, f32)> = points.iter().map(|point| transform.apply(*point)).collect();
+                    canvas.end_fill(&points, *color)?;
+                },
+                Operation::BeginGroup(name) => canvas.begin_group(name),
+                Operation::EndGroup => canvas.end_group(),
+            }
+        }
+        Ok(canvas)
+    }
+}
+
+impl Canvas for FitCanvas {
+    fn dimensions(&self) -> (u32, u32) {
+        self.canvas.dimensions()
+    }
+
+    fn set_background(&mut self, color: Color) {
+        self.canvas.set_background(color);
+        self.operations.push(Operation::Background(color));
+    }
+
+    fn line(&mut self, start: (f32, f32), end: (f32, f32), color: Color, width: f32) -> Result<(), String> {
+        self.canvas.line(start, end, color, width)?;
+        self.operations.push(Operation::Line { start, end, color, width });
+        Ok(())
+    }
+
+    fn begin_fill(&mut self) {
+        self.canvas.begin_fill();
+        self.operations.push(Operation::BeginFill);
+    }
+
+    fn end_fill(&mut self, points: &[(f32, f32)], color: Color) -> Result<(), String> {
+        self.canvas.end_fill(points, color)?;
+        self.operations.push(Operation::EndFill { points: points.to_vec(), color });
+        Ok(())
+    }
+
+    fn begin_group(&mut self, name: &str) {
+        self.canvas.begin_group(name);
+        self.operations.push(Operation::BeginGroup(name.to_string()));
+    }
+
+    fn end_group(&mut self) {
+        self.canvas.end_group();
+        self.operations.push(Operation::EndGroup);
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        self.fitted()?.save(path)
+    }
+
+    fn to_text(&self) -> Option<String> {
+        self.fitted().ok()?.to_text()
+    }
+}
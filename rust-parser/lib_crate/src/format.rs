@@ -0,0 +1,424 @@
+use std::collections::{HashMap, HashSet};
+use crate::dialect::Dialect;
+use crate::errors::LogoError;
+use crate::lexer;
+use crate::parser;
+use crate::structs::{Block, Comment, Expression, ExpressionKind, Procedure, Program, Span, StatementKind, Token,
+                     TokenKind};
+
+/// Indentation for each level of nesting.
+const INDENT: &str = "  ";
+
+/// Rewrites a program in the usual layout: one statement per line, keywords
+/// in upper case, and each block indented with its `[` ending the line that
+/// opens it and its `]` on a line of its own. Lists stay on one line.
+///
+/// Comments are kept, as is one blank line wherever there were blank lines
+/// between statements. A program that does not parse is returned as an
+/// error, since there is no telling what its layout should be. `known` has
+/// the procedures the program can call without defining, as for
+/// `parser::parse_with_procedures`.
+///
+/// A program in another dialect stays in that dialect, with only its layout
+/// and the case of its keywords changed.
+pub fn format_source(source: &str, known: &HashMap<String, usize>, dialect: Dialect) -> Result<String, LogoError>
+{
+    let (written, mut comments) = lexer::tokenize_with_comments(&dialect.without_comments(source));
+    comments.extend(dialect.comments(source));
+    comments.sort_by_key(|comment| (comment.span.line, comment.span.column));
+    let tokens = normalize_case(&written, dialect);
+    // LOADs are left as they are rather than replaced by what they load
+    let loads: HashSet<usize> = tokens.windows(2)
+        .enumerate()
+        .filter(|(_, pair)| is_load(&pair[0]) && matches!(pair[1].kind, TokenKind::Value(_)))
+        .flat_map(|(index, _)| [index, index + 1])
+        .collect();
+    let parsed: Vec<Token> = tokens.iter()
+        .enumerate()
+        .filter(|(index, _)| !loads.contains(index))
+        .map(|(_, token)| token.clone())
+        .collect();
+    // Translated tokens keep their spans, which is all the layout goes by
+    let mut parsed = dialect.translate(parsed);
+    dialect.name_calls(&mut parsed, |name| known.contains_key(name));
+    let program = parser::parse_with_procedures(&parsed, known)?;
+
+    let mut layout = Layout::new(&tokens, &program);
+    layout.starts.extend(loads.iter().filter(|index| is_load(&tokens[**index])));
+    let printer = Printer {
+        layout,
+        tokens: &tokens,
+        written: &written,
+        comments: &comments,
+        next_comment: 0,
+        lines: Vec::new(),
+        line: None,
+        indent: 0,
+        groups: Vec::new(),
+        previous: None,
+        last_line: 0,
+    };
+    Ok(printer.print())
+}
+
+/// Upper-cases keywords written in another case, such as `forward`, so that
+/// they parse, along with the dialect's abbreviations. Names given to
+/// procedures with `TO` are left as they are.
+fn normalize_case(tokens: &[Token], dialect: Dialect) -> Vec<Token>
+{
+    let defined: HashSet<&str> = tokens.windows(2)
+        .filter_map(|pair| match (&pair[0].kind, &pair[1].kind) {
+            (TokenKind::Word(to), TokenKind::Word(name)) if to.eq_ignore_ascii_case("TO") => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    tokens.iter()
+        .map(|token| {
+            let kind = match &token.kind {
+                TokenKind::Word(word) if !defined.contains(word.as_str()) && {
+                    let upper = word.to_ascii_uppercase();
+                    is_keyword(&upper) || dialect.is_abbreviation(&upper)
+                } => TokenKind::Word(word.to_ascii_uppercase()),
+                kind => kind.clone(),
+            };
+            Token { kind, span: token.span }
+        })
+        .collect()
+}
+
+fn is_load(token: &Token) -> bool
+{
+    matches!(&token.kind, TokenKind::Word(word) if word == "LOAD")
+}
+
+fn is_keyword(word: &str) -> bool
+{
+    parser::is_reserved(word) || word == "TRUE" || word == "FALSE"
+}
+
+/// How the tokens between a `[` and its `]` are laid out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Group {
+    /// On one line, as for lists.
+    Inline,
+    /// One statement per line, as for the blocks of IF, WHILE, REPEAT,
+    /// FOREACH and ASK.
+    Block,
+    /// One symbol and its actions per line, for the last argument of LSYSTEM.
+    Actions,
+}
+
+/// What parsing the program showed about its tokens, by index.
+struct Layout {
+    /// Tokens that start a statement or a `TO` definition.
+    starts: HashSet<usize>,
+    /// Tokens in list literals, which are data and keep the case they were written in.
+    list_items: HashSet<usize>,
+    /// Every `[` that is not `Group::Inline`.
+    groups: HashMap<usize, Group>,
+    /// Every LSYSTEM statement.
+    lsystems: Vec<usize>,
+    positions: HashMap<(usize, usize), usize>,
+}
+
+impl Layout {
+    fn new(tokens: &[Token], program: &Program) -> Layout {
+        let mut layout = Layout {
+            starts: HashSet::new(),
+            list_items: HashSet::new(),
+            groups: HashMap::new(),
+            lsystems: Vec::new(),
+            positions: tokens.iter()
+                .enumerate()
+                .map(|(index, token)| ((token.span.line, token.span.column), index))
+                .collect(),
+        };
+        for definition in program.procedures.values() {
+            layout.starts.insert(layout.index(definition.span));
+            layout.visit_block(&definition.body);
+        }
+        layout.visit_block(&program.statements);
+
+        for (index, token) in tokens.iter().enumerate() {
+            if token.kind == TokenKind::OpenBracket && layout.starts.contains(&(index + 1)) {
+                layout.groups.insert(index, Group::Block);
+            }
+        }
+        // The actions are the list that ends the statement
+        let opening = match_brackets(tokens);
+        for &start in &layout.lsystems {
+            let end = statement_end(tokens, &layout.starts, start);
+            if let Some(&open) = opening.get(&(end - 1)) {
+                layout.groups.insert(open, Group::Actions);
+            }
+        }
+        layout
+    }
+
+    fn index(&self, span: Span) -> usize {
+        self.positions[&(span.line, span.column)]
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        for statement in block {
+            let index = self.index(statement.span);
+            self.starts.insert(index);
+            match &statement.kind {
+                StatementKind::Procedure(procedure) => self.visit_procedure(procedure),
+                StatementKind::If(expression, body) | StatementKind::While(expression, body)
+                | StatementKind::Repeat(expression, body) | StatementKind::Foreach(_, expression, body)
+                | StatementKind::Ask(expression, body) => {
+                    self.visit_expression(expression);
+                    self.visit_block(body);
+                },
+                StatementKind::LSystem(lsystem) => {
+                    self.lsystems.push(index);
+                    self.visit_expression(&lsystem.axiom);
+                    self.visit_expression(&lsystem.rules);
+                    self.visit_expression(&lsystem.iterations);
+                    for action in &lsystem.actions {
+                        for (procedure, _) in &action.procedures {
+                            self.visit_procedure(procedure);
+                        }
+                    }
+                },
+                StatementKind::Call(_, arguments) => {
+                    for argument in arguments {
+                        self.visit_expression(argument);
+                    }
+                },
+            }
+        }
+    }
+
+    fn visit_procedure(&mut self, procedure: &Procedure) {
+        for argument in procedure.arguments() {
+            self.visit_expression(argument);
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Binary(_, left, right) => {
+                self.visit_expression(left);
+                self.visit_expression(right);
+            },
+            ExpressionKind::Negate(operand) => self.visit_expression(operand),
+            ExpressionKind::Function(_, arguments) => {
+                for argument in arguments {
+                    self.visit_expression(argument);
+                }
+            },
+            ExpressionKind::List(items) => self.visit_list(items),
+            _ => {},
+        }
+    }
+
+    fn visit_list(&mut self, items: &[Expression]) {
+        for item in items {
+            self.list_items.insert(self.index(item.span));
+            if let ExpressionKind::List(items) = &item.kind {
+                self.visit_list(items);
+            }
+        }
+    }
+}
+
+/// Maps the index of every `]` to the index of its `[`.
+fn match_brackets(tokens: &[Token]) -> HashMap<usize, usize>
+{
+    let mut opening = HashMap::new();
+    let mut open = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::OpenBracket => open.push(index),
+            TokenKind::CloseBracket => {
+                if let Some(start) = open.pop() {
+                    opening.insert(index, start);
+                }
+            },
+            _ => {},
+        }
+    }
+    opening
+}
+
+/// The index just past the statement starting at `start`: the next
+/// statement, or the `]` or END that closes the block it is in.
+fn statement_end(tokens: &[Token], starts: &HashSet<usize>, start: usize) -> usize
+{
+    let mut depth = 0;
+    for (index, token) in tokens.iter().enumerate().skip(start + 1) {
+        match &token.kind {
+            TokenKind::OpenBracket => depth += 1,
+            TokenKind::CloseBracket if depth == 0 => return index,
+            TokenKind::CloseBracket => depth -= 1,
+            TokenKind::Word(word) if depth == 0 && word == "END" => return index,
+            _ if depth == 0 && starts.contains(&index) => return index,
+            _ => {},
+        }
+    }
+    tokens.len()
+}
+
+struct Printer<'a> {
+    layout: Layout,
+    /// The tokens with keywords in upper case.
+    tokens: &'a [Token],
+    /// The tokens as written.
+    written: &'a [Token],
+    comments: &'a [Comment],
+    next_comment: usize,
+    lines: Vec<String>,
+    /// The line being written and its indentation, if one is started.
+    line: Option<(usize, String)>,
+    indent: usize,
+    /// The `[`s not yet closed.
+    groups: Vec<Group>,
+    /// Index of the last token printed.
+    previous: Option<usize>,
+    /// Source line of the last token or comment printed.
+    last_line: usize,
+}
+
+impl Printer<'_> {
+    fn print(mut self) -> String {
+        for index in 0..self.tokens.len() {
+            self.print_comments_before(self.tokens[index].span.line);
+            self.print_token(index);
+        }
+        self.print_comments_before(usize::MAX);
+        self.end_line();
+
+        let mut out = self.lines.join("\n");
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out
+    }
+
+    fn print_token(&mut self, index: usize) {
+        let token = &self.tokens[index];
+        // Lists are data, so their words keep the case they were written in
+        let text = if self.layout.list_items.contains(&index) { &self.written[index] } else { token };
+        let text = text.kind.to_string();
+        let starts = self.layout.starts.contains(&index);
+        match &token.kind {
+            TokenKind::OpenBracket => {
+                let empty = self.tokens.get(index + 1).is_some_and(|next| next.kind == TokenKind::CloseBracket);
+                let group = match self.layout.groups.get(&index) {
+                    Some(group) if !empty => *group,
+                    _ => Group::Inline,
+                };
+                self.append(index, &text);
+                if group != Group::Inline {
+                    self.indent += 1;
+                }
+                self.groups.push(group);
+            },
+            TokenKind::CloseBracket => match self.groups.pop() {
+                Some(Group::Block | Group::Actions) => {
+                    self.indent -= 1;
+                    self.new_line();
+                    self.append(index, &text);
+                },
+                _ => self.append(index, &text),
+            },
+            TokenKind::Word(word) if word == "TO" && starts => {
+                self.start_statement(token.span.line);
+                self.append(index, &text);
+                self.indent += 1;
+            },
+            TokenKind::Word(word) if word == "END" && self.groups.is_empty() => {
+                self.indent = self.indent.saturating_sub(1);
+                self.new_line();
+                self.append(index, &text);
+            },
+            _ if starts || self.groups.last() == Some(&Group::Actions) => {
+                self.start_statement(token.span.line);
+                self.append(index, &text);
+            },
+            _ => self.append(index, &text),
+        }
+        self.previous = Some(index);
+        self.last_line = token.span.line;
+    }
+
+    /// Prints the comments on lines before `line`. A comment after code
+    /// stays at the end of its line, and anything after it goes on the next.
+    fn print_comments_before(&mut self, line: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment).filter(|comment| comment.span.line < line) {
+            let trailing = self.previous.is_some_and(|previous| self.tokens[previous].span.line == comment.span.line);
+            match &mut self.line {
+                Some((_, content)) if trailing => {
+                    content.push(' ');
+                    content.push_str(&comment.text);
+                },
+                _ => {
+                    self.start_statement(comment.span.line);
+                    self.append_text(&comment.text);
+                },
+            }
+            self.end_line();
+            self.last_line = comment.span.line;
+            self.next_comment += 1;
+        }
+    }
+
+    /// Starts a line for a statement or comment at `source_line`, after a
+    /// blank line if there was one before it, except at the start of a block.
+    fn start_statement(&mut self, source_line: usize) {
+        self.end_line();
+        let opens_block = self.lines.last().is_none_or(|line| line.is_empty() || line.ends_with('['));
+        if source_line > self.last_line + 1 && !opens_block {
+            self.lines.push(String::new());
+        }
+        self.new_line();
+    }
+
+    fn new_line(&mut self) {
+        self.end_line();
+        self.line = Some((self.indent, String::new()));
+    }
+
+    fn end_line(&mut self) {
+        if let Some((indent, content)) = self.line.take() {
+            if !content.is_empty() {
+                self.lines.push(format!("{}{content}", INDENT.repeat(indent)));
+            }
+        }
+    }
+
+    fn append(&mut self, index: usize, text: &str) {
+        if self.line.as_ref().is_some_and(|(_, content)| !content.is_empty()) && self.spaced(index) {
+            self.append_text(" ");
+        }
+        self.append_text(text);
+    }
+
+    /// Adds to the line being written. Without one, as after a comment in
+    /// the middle of a statement, the text continues the statement on a new
+    /// line, indented one more level.
+    fn append_text(&mut self, text: &str) {
+        let indent = self.indent + 1;
+        self.line.get_or_insert_with(|| (indent, String::new())).1.push_str(text);
+    }
+
+    /// Whether a space goes before the token at `index`. Brackets and
+    /// parentheses hug what they enclose. Whether `-` touches its neighbours
+    /// decides between subtraction and negation, so that is kept as written.
+    fn spaced(&self, index: usize) -> bool {
+        let previous = &self.tokens[index - 1];
+        let token = &self.tokens[index];
+        let minus = |token: &Token| matches!(&token.kind, TokenKind::Word(word) if word == "-");
+        if minus(previous) || minus(token) {
+            let span = previous.span;
+            return span.line != token.span.line || span.column + span.length != token.span.column;
+        }
+        !matches!(
+            (&previous.kind, &token.kind),
+            (TokenKind::OpenBracket | TokenKind::OpenParen, _) | (_, TokenKind::CloseBracket | TokenKind::CloseParen)
+        )
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+use unsvg::Color;
+use crate::bytecode::{self, CompiledProgram};
+use crate::canvas::{Axes, Canvas};
+use crate::errors::{ErrorKind, LogoError};
+use crate::lexer;
+use crate::limits::{Limits, Usage};
+use crate::parser;
+use crate::plotter::{PlotterCanvas, PlotterFormat};
+use crate::structs::{Cursor, Environment, Span, Value};
+use crate::trace::Tracer;
+use crate::utils;
+use crate::vm::Vm;
+
+/// The Rust side of a native procedure: given the turtle and the values of
+/// the arguments, it may move the turtle, or fail.
+pub type NativeFunction = dyn Fn(&mut Turtle, &[Value]) -> Result<(), LogoError>;
+
+/// The turtle as a native procedure sees it. Moves go through the canvas
+/// like FORWARD and SETXY do, so they draw, count towards the program's
+/// limits and are checked against its bounds.
+pub struct Turtle<'a> {
+    pub(crate) canvas: &'a mut dyn Canvas,
+    pub(crate) cursor: &'a mut Cursor,
+    pub(crate) env: &'a mut Environment,
+    pub(crate) span: Span,
+    pub(crate) name: &'a str,
+}
+
+impl Turtle<'_> {
+    pub fn cursor(&self) -> &Cursor {
+        self.cursor
+    }
+
+    /// Moves in a straight line to `(x, y)` on the canvas, drawing if the pen is down.
+    pub fn move_to(&mut self, x: f32, y: f32) -> Result<(), LogoError> {
+        utils::move_to(self.canvas, self.env, self.cursor, (x, y), self.span)
+    }
+
+    /// Moves `distance` along the turtle's heading.
+    pub fn forward(&mut self, distance: f32) -> Result<(), LogoError> {
+        let (x, y) = self.cursor.destination(self.cursor.direction, distance);
+        self.move_to(x, y)
+    }
+
+    pub fn turn(&mut self, degrees: f32) {
+        self.cursor.turn(degrees);
+    }
+
+    /// An error from the native procedure, reported where it was called.
+    pub fn error(&self, message: impl Into<String>) -> LogoError {
+        LogoError::new(ErrorKind::Native(message.into()), self.span, self.name)
+    }
+}
+
+/// A procedure provided by the program embedding the interpreter, called
+/// from Logo like one defined with `TO`.
+pub struct NativeProcedure {
+    pub name: String,
+    pub arity: usize,
+    pub function: Box<NativeFunction>,
+}
+
+pub use crate::plotter::Segment;
+
+/// Runs Logo programs on a canvas, for programs that want to embed Logo.
+///
+/// Each `load` adds to what came before: variables, procedures and the
+/// drawing are kept, as in the REPL.
+///
+/// ```no_run
+/// use lib_crate::canvas::{create_canvas, Format};
+/// use lib_crate::interpreter::Interpreter;
+///
+/// let mut logo = Interpreter::new(create_canvas(Format::Svg, 200, 200, false));
+/// logo.register("LOG", 1, |_, args| {
+///     println!("{}", args[0]);
+///     Ok(())
+/// }).unwrap();
+/// logo.load("PENDOWN FORWARD 50 LOG XCOR").unwrap();
+/// logo.run().unwrap();
+/// assert_eq!(logo.segments().len(), 1);
+/// ```
+pub struct Interpreter {
+    canvas: Recorder,
+    cursor: Cursor,
+    env: Environment,
+    vm: Vm,
+}
+
+impl Interpreter {
+    /// An interpreter with nothing loaded, and the turtle in the middle of `canvas`.
+    pub fn new(canvas: Box<dyn Canvas>) -> Interpreter {
+        let (width, height) = canvas.dimensions();
+        Interpreter {
+            canvas: Recorder { canvas, plot: PlotterCanvas::new(width, height, PlotterFormat::Json) },
+            cursor: Cursor::new((width / 2) as f32, (height / 2) as f32),
+            env: Environment::new(HashMap::new()),
+            vm: Vm::new(Rc::from(Vec::new())),
+        }
+    }
+
+    /// Parses and compiles `source`, ready to `run` or `step` through. Any
+    /// program still running is abandoned.
+    pub fn load(&mut self, source: &str) -> Result<(), LogoError> {
+        let tokens = lexer::tokenize(source);
+        let program = parser::parse_with_procedures(&tokens, &self.env.arities())?;
+        self.load_program(bytecode::compile(program));
+        Ok(())
+    }
+
+    /// Like `load`, for a program that is already compiled.
+    pub fn load_program(&mut self, program: CompiledProgram) {
+        self.vm.abort(&mut self.canvas, &mut self.cursor, &mut self.env);
+        self.env.procedures.extend(program.procedures);
+        self.vm = Vm::new(program.main);
+    }
+
+    /// Runs the loaded program to the end.
+    pub fn run(&mut self) -> Result<(), LogoError> {
+        self.vm.run(&mut self.canvas, &mut self.cursor, &mut self.env)
+    }
+
+    /// Runs the next statement of the loaded program. Returns whether there
+    /// is more to run.
+    pub fn step(&mut self) -> Result<bool, LogoError> {
+        loop {
+            if let Err(err) = self.vm.step(&mut self.canvas, &mut self.cursor, &mut self.env) {
+                self.vm.abort(&mut self.canvas, &mut self.cursor, &mut self.env);
+                return Err(err);
+            }
+            // Carry on through jumps and returns, so the next step starts a statement
+            match self.vm.current() {
+                Some(instruction) if instruction.kind.is_statement() => return Ok(true),
+                _ if self.vm.is_finished() => return Ok(false),
+                _ => {},
+            }
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.vm.is_finished()
+    }
+
+    /// Adds a procedure that Logo code loaded from now on can call with
+    /// `arity` arguments. Procedures defined with `TO` take precedence.
+    /// Built-in names cannot be used.
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Turtle, &[Value]) -> Result<(), LogoError> + 'static,
+    ) -> Result<(), String> {
+        if parser::is_reserved(name) {
+            return Err(format!("{name} is a built-in and cannot be replaced"));
+        }
+        let native = NativeProcedure { name: name.to_string(), arity, function: Box::new(function) };
+        self.env.natives.insert(name.to_string(), Rc::new(native));
+        Ok(())
+    }
+
+    /// Every variable visible where the program is, with locals of the
+    /// procedure call running hiding globals of the same name.
+    pub fn variables(&self) -> HashMap<String, Value> {
+        let mut variables: HashMap<String, Value> = self.env.globals().into_iter().collect();
+        variables.extend(self.env.locals());
+        variables
+    }
+
+    /// The turtle drawing.
+    pub fn cursor(&self) -> &Cursor {
+        &self.cursor
+    }
+
+    /// Every line drawn so far, in order.
+    pub fn segments(&self) -> &[Segment] {
+        &self.canvas.plot.segments
+    }
+
+    pub fn canvas(&self) -> &dyn Canvas {
+        self.canvas.canvas.as_ref()
+    }
+
+    /// Saves the drawing so far to `path`.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        self.canvas.save(path)
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.env.tracer = tracer;
+    }
+
+    /// Makes RANDOM produce the same sequence on every run with this seed.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.env.seed_random(seed);
+    }
+
+    /// Sets how XCOR, YCOR, SETX and SETY map onto the canvas.
+    pub fn set_axes(&mut self, axes: Axes) {
+        self.env.axes = axes;
+    }
+
+    /// Sets new limits, counting usage from zero.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.env.usage = Usage::new(limits);
+    }
+}
+
+/// Passes everything through to another canvas, recording each line on a
+/// plotter canvas as well.
+struct Recorder {
+    canvas: Box<dyn Canvas>,
+    plot: PlotterCanvas,
+}
+
+impl Canvas for Recorder {
+    fn dimensions(&self) -> (u32, u32) {
+        self.canvas.dimensions()
+    }
+
+    fn set_background(&mut self, color: Color) {
+        self.canvas.set_background(color);
+    }
+
+    fn line(&mut self, start: (f32, f32), end: (f32, f32), color: Color, width: f32) -> Result<(), String> {
+        self.canvas.line(start, end, color, width)?;
+        self.plot.line(start, end, color, width)
+    }
+
+    fn begin_fill(&mut self) {
+        self.canvas.begin_fill();
+    }
+
+    fn end_fill(&mut self, points: &[(f32, f32)], color: Color) -> Result<(), String> {
+        self.canvas.end_fill(points, color)?;
+        self.plot.end_fill(points, color)
+    }
+
+    fn begin_group(&mut self, name: &str) {
+        self.canvas.begin_group(name);
+    }
+
+    fn end_group(&mut self) {
+        self.canvas.end_group();
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        self.canvas.save(path)
+    }
+
+    fn to_text(&self) -> Option<String> {
+        self.canvas.to_text()
+    }
+}
@@ -0,0 +1,89 @@
+use crate::structs::{Comment, Span, Token, TokenKind};
+
+/// Splits a Logo program into tokens. Whitespace separates words, brackets
+/// and parentheses are always tokens of their own and `//` starts a comment
+/// running to the end of the line. A `-` stuck to the front of a word that is
+/// not a number, as in `-:x`, becomes a token of its own so that the parser
+/// can read it as unary minus.
+pub fn tokenize(source: &str) -> Vec<Token>
+{
+    tokenize_with_comments(source).0
+}
+
+/// Like `tokenize`, but also returns the comments, for tools that write the
+/// program back out.
+pub fn tokenize_with_comments(source: &str) -> (Vec<Token>, Vec<Comment>)
+{
+    let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut position = 0usize;
+        while position < chars.len() {
+            let c = chars[position];
+            if c.is_whitespace() {
+                position += 1;
+                continue;
+            }
+
+            if c == '/' && chars.get(position + 1) == Some(&'/') {
+                let text: String = chars[position..].iter().collect();
+                let span = Span { line: line_index + 1, column: position + 1, length: chars.len() - position, file: 0 };
+                comments.push(Comment { text: text.trim_end().to_string(), span });
+                break;
+            }
+            if let Some(kind) = bracket_kind(c) {
+                tokens.push(Token { kind, span: Span { line: line_index + 1, column: position + 1, length: 1, file: 0 } });
+                position += 1;
+                continue;
+            }
+
+            let mut start = position;
+            while position < chars.len() && !is_delimiter(chars[position]) {
+                position += 1;
+            }
+            if c == '-' && position - start > 1 && !is_number(&chars[start..position]) {
+                tokens.push(Token {
+                    kind: TokenKind::Word("-".to_string()),
+                    span: Span { line: line_index + 1, column: start + 1, length: 1, file: 0 },
+                });
+                start += 1;
+            }
+            let word: String = chars[start..position].iter().collect();
+            let kind = if let Some(value) = word.strip_prefix('"') {
+                TokenKind::Value(value.to_string())
+            }
+            else if let Some(name) = word.strip_prefix(':') {
+                TokenKind::Variable(name.to_string())
+            }
+            else {
+                TokenKind::Word(word)
+            };
+            tokens.push(Token { kind, span: Span { line: line_index + 1, column: start + 1, length: position - start, file: 0 } });
+        }
+    }
+
+    (tokens, comments)
+}
+
+fn bracket_kind(c: char) -> Option<TokenKind>
+{
+    match c {
+        '[' => Some(TokenKind::OpenBracket),
+        ']' => Some(TokenKind::CloseBracket),
+        '(' => Some(TokenKind::OpenParen),
+        ')' => Some(TokenKind::CloseParen),
+        _ => None,
+    }
+}
+
+fn is_delimiter(c: char) -> bool
+{
+    c.is_whitespace() || bracket_kind(c).is_some()
+}
+
+fn is_number(chars: &[char]) -> bool
+{
+    chars.iter().collect::<String>().parse::<f32>().is_ok()
+}
@@ -1,2 +1,24 @@
 pub mod structs;
-pub mod utils;
\ No newline at end of file
+pub mod utils;
+pub mod lexer;
+pub mod parser;
+pub mod errors;
+pub mod trace;
+pub mod canvas;
+pub mod svg;
+pub mod ascii;
+pub mod plotter;
+pub mod animate;
+pub mod fit;
+pub mod bytecode;
+pub mod vm;
+pub mod interpreter;
+pub mod check;
+pub mod format;
+pub mod lsystem;
+pub mod limits;
+pub mod load;
+pub mod dialect;
+
+#[cfg(test)]
+mod test_support;
@@ -0,0 +1,150 @@
+use std::time::{Duration, Instant};
+use crate::errors::{ErrorKind, LogoError};
+use crate::structs::Span;
+
+/// How often, in steps, the clock is read to check the time limit.
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+/// Bounds on what a program may do while it runs, so that a program from
+/// someone else cannot run forever or use up memory. `None` is no limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    /// Instructions run, counting each symbol drawn by an L-system as one.
+    pub max_steps: Option<u64>,
+    /// Procedure calls in progress at once.
+    pub max_depth: usize,
+    /// Lines drawn, including the short lines making up arcs and circles.
+    pub max_segments: Option<u64>,
+    /// Largest distance from zero the turtle may reach on either axis.
+    pub max_coordinate: f32,
+    pub max_time: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_steps: None,
+            max_depth: 1000,
+            max_segments: None,
+            // Well before coordinates lose all precision as f32s
+            max_coordinate: 1_000_000.0,
+            max_time: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Limits suited to running programs that are not trusted, generous
+    /// enough for any of the examples.
+    pub fn sandbox() -> Limits {
+        Limits {
+            max_steps: Some(10_000_000),
+            max_depth: 1000,
+            max_segments: Some(1_000_000),
+            max_coordinate: 100_000.0,
+            max_time: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+/// How much of its limits a running program has used.
+#[derive(Debug)]
+pub struct Usage {
+    pub limits: Limits,
+    steps: u64,
+    segments: u64,
+    started: Instant,
+}
+
+impl Default for Usage {
+    fn default() -> Usage {
+        Usage::new(Limits::default())
+    }
+}
+
+impl Usage {
+    /// Starts counting, and the clock, from now.
+    pub fn new(limits: Limits) -> Usage {
+        Usage { limits, steps: 0, segments: 0, started: Instant::now() }
+    }
+
+    /// Counts one step run at `span`, failing if there have been too many or
+    /// the time is up.
+    pub fn step(&mut self, span: Span) -> Result<(), LogoError> {
+        self.steps += 1;
+        if self.limits.max_steps.is_some_and(|max| self.steps > max) {
+            return Err(LogoError::new(ErrorKind::StepLimitExceeded, span, ""));
+        }
+        let checking_time = self.steps.is_multiple_of(TIME_CHECK_INTERVAL);
+        if checking_time && self.limits.max_time.is_some_and(|max| self.started.elapsed() > max) {
+            return Err(LogoError::new(ErrorKind::TimeLimitExceeded, span, ""));
+        }
+        Ok(())
+    }
+
+    /// Counts one line drawn at `span`, failing if there have been too many.
+    pub fn segment(&mut self, span: Span) -> Result<(), LogoError> {
+        self.segments += 1;
+        if self.limits.max_segments.is_some_and(|max| self.segments > max) {
+            return Err(LogoError::new(ErrorKind::SegmentLimitExceeded, span, ""));
+        }
+        Ok(())
+    }
+
+    /// Fails if any of `points` is further from the origin than allowed.
+    pub fn check_bounds(&self, points: &[(f32, f32)], span: Span) -> Result<(), LogoError> {
+        let max = self.limits.max_coordinate;
+        let in_bounds = |coord: f32| coord.abs() < max;
+        if !points.iter().all(|(x, y)| in_bounds(*x) && in_bounds(*y)) {
+            return Err(LogoError::new(ErrorKind::OutOfBounds, span, ""));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPAN: Span = Span { line: 3, column: 1, length: 7, file: 0 };
+
+    #[test]
+    fn steps_and_segments_stop_just_past_their_limit()
+    {
+        let mut usage = Usage::new(Limits { max_steps: Some(2), max_segments: Some(1), ..Limits::default() });
+        assert!(usage.step(SPAN).is_ok() && usage.step(SPAN).is_ok());
+        let err = usage.step(SPAN).unwrap_err();
+        assert_eq!((err.kind, err.span), (ErrorKind::StepLimitExceeded, SPAN));
+        assert!(usage.segment(SPAN).is_ok());
+        assert_eq!(usage.segment(SPAN).unwrap_err().kind, ErrorKind::SegmentLimitExceeded);
+    }
+
+    #[test]
+    fn no_limit_is_unlimited()
+    {
+        let mut usage = Usage::default();
+        for _ in 0..10_000 {
+            usage.step(SPAN).unwrap();
+            usage.segment(SPAN).unwrap();
+        }
+    }
+
+    #[test]
+    fn time_is_checked_every_so_many_steps()
+    {
+        let mut usage = Usage::new(Limits { max_time: Some(Duration::ZERO), ..Limits::default() });
+        std::thread::sleep(Duration::from_millis(1));
+        for _ in 1..TIME_CHECK_INTERVAL {
+            usage.step(SPAN).unwrap();
+        }
+        assert_eq!(usage.step(SPAN).unwrap_err().kind, ErrorKind::TimeLimitExceeded);
+    }
+
+    #[test]
+    fn points_must_stay_within_the_largest_coordinate()
+    {
+        let usage = Usage::new(Limits { max_coordinate: 100.0, ..Limits::default() });
+        assert!(usage.check_bounds(&[(-99.5, 99.5), (0.0, 0.0)], SPAN).is_ok());
+        assert_eq!(usage.check_bounds(&[(0.0, 0.0), (0.0, -100.0)], SPAN).unwrap_err().kind, ErrorKind::OutOfBounds);
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use crate::dialect::Dialect;
+use crate::errors::{ErrorKind, LogoError};
+use crate::lexer;
+use crate::structs::{Token, TokenKind};
+
+/// Procedures available to every program unless it asks not to have them.
+/// A program's own definitions take the place of these.
+pub const PRELUDE: &str = include_str!("prelude.lg");
+
+/// The name errors in the prelude are reported against.
+pub const PRELUDE_PATH: &str = "<prelude>";
+
+/// A file that tokens were read from.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: String,
+    pub text: String,
+}
+
+/// Every file a program was loaded from, numbered as in `Span::file`, so
+/// that errors can be shown on the line they are about.
+#[derive(Debug, Clone, Default)]
+pub struct Sources {
+    files: Vec<SourceFile>,
+}
+
+impl Sources {
+    /// Adds a file, returning its number.
+    pub fn add(&mut self, path: impl Into<String>, text: impl Into<String>) -> usize {
+        self.files.push(SourceFile { path: path.into(), text: text.into() });
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, file: usize) -> Option<&SourceFile> {
+        self.files.get(file)
+    }
+
+    /// Like `LogoError::render`, showing the line from whichever file the
+    /// error is in.
+    pub fn render(&self, err: &LogoError) -> String {
+        self.render_as("error", err)
+    }
+
+    pub fn render_as(&self, label: &str, err: &LogoError) -> String {
+        match self.get(err.span.file) {
+            Some(file) => err.render_as(label, &file.text, &file.path),
+            None => err.render_as(label, "", ""),
+        }
+    }
+}
+
+/// Tokenizes `source`, read from `path`, with each `LOAD "file` replaced by
+/// the tokens of that file, which is found relative to the directory of the
+/// file loading it. Files already loaded are not loaded again, and a file
+/// that ends up loading itself is an error. With `prelude` set, the
+/// prelude's procedures come first. Every file is written in `dialect`,
+/// apart from the prelude, and can call procedures from any of the others.
+///
+/// Every file is added to `sources`, starting with the program itself, which
+/// is file 0 if `sources` starts out empty.
+pub fn expand(source: &str, path: &Path, prelude: bool, dialect: Dialect, sources: &mut Sources)
+    -> Result<Vec<Token>, LogoError>
+{
+    let mut loader = Loader { sources, dialect, loading: Vec::new(), loaded: HashSet::new() };
+    let mut tokens = loader.load(source, path)?;
+    if prelude {
+        let file = loader.sources.add(PRELUDE_PATH, PRELUDE);
+        let defined = defined_names(&tokens);
+        tokens.splice(0..0, without_definitions(numbered(PRELUDE, file), &defined));
+    }
+    let defined = defined_names(&tokens);
+    dialect.name_calls(&mut tokens, |name| defined.contains(name));
+    Ok(tokens)
+}
+
+struct Loader<'a> {
+    sources: &'a mut Sources,
+    dialect: Dialect,
+    /// The files being loaded, the innermost last, to catch cycles.
+    loading: Vec<PathBuf>,
+    loaded: HashSet<PathBuf>,
+}
+
+impl Loader<'_> {
+    fn load(&mut self, source: &str, path: &Path) -> Result<Vec<Token>, LogoError> {
+        let file = self.sources.add(path.display().to_string(), source);
+        let this = identity(path);
+        self.loaded.insert(this.clone());
+        self.loading.push(this);
+
+        let mut tokens = Vec::new();
+        let mut input = self.dialect.translate(numbered(&self.dialect.without_comments(source), file)).into_iter();
+        while let Some(token) = input.next() {
+            if !matches!(&token.kind, TokenKind::Word(word) if word == "LOAD") {
+                tokens.push(token);
+                continue;
+            }
+            let (name, span) = match input.next() {
+                Some(Token { kind: TokenKind::Value(name), span }) => (name, span),
+                Some(other) => return Err(LogoError::new(ErrorKind::ExpectedName, other.span, other.kind.to_string())),
+                None => return Err(LogoError::new(ErrorKind::NotEnoughArgs, token.span, "LOAD")),
+            };
+
+            let target = path.parent().unwrap_or(Path::new("")).join(&name);
+            let text = std::fs::read_to_string(&target)
+                .map_err(|err| LogoError::new(ErrorKind::LoadFailed(err.to_string()), span, format!("\"{name}")))?;
+            let that = identity(&target);
+            if self.loading.contains(&that) {
+                return Err(LogoError::new(ErrorKind::LoadCycle, span, format!("\"{name}")));
+            }
+            if self.loaded.contains(&that) {
+                continue;
+            }
+            tokens.extend(self.load(&text, &target)?);
+        }
+
+        self.loading.pop();
+        Ok(tokens)
+    }
+}
+
+/// The same path for every way of naming a file, where it exists.
+fn identity(path: &Path) -> PathBuf
+{
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Tokenizes `source` as file number `file`.
+fn numbered(source: &str, file: usize) -> Vec<Token>
+{
+    let mut tokens = lexer::tokenize(source);
+    for token in &mut tokens {
+        token.span.file = file;
+    }
+    tokens
+}
+
+/// The names given to procedures with `TO`.
+fn defined_names(tokens: &[Token]) -> HashSet<String>
+{
+    tokens.windows(2)
+        .filter_map(|pair| match (&pair[0].kind, &pair[1].kind) {
+            (TokenKind::Word(to), TokenKind::Word(name)) if to == "TO" => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `tokens` without the definitions of any procedure named in `names`, or
+/// of any procedure calling one of those, since the program's own version
+/// might take different arguments.
+fn without_definitions(tokens: Vec<Token>, names: &HashSet<String>) -> Vec<Token>
+{
+    // Each definition's name, and where it starts and ends
+    let mut definitions = Vec::new();
+    let mut index = 0;
+    while index < tokens.len() {
+        if let [Token { kind: TokenKind::Word(to), .. }, Token { kind: TokenKind::Word(name), .. }, ..] = &tokens[index..] {
+            if to == "TO" {
+                let length = tokens[index..].iter()
+                    .position(|token| matches!(&token.kind, TokenKind::Word(word) if word == "END"))
+                    .map_or(tokens.len() - index, |end| end + 1);
+                definitions.push((name.clone(), index..index + length));
+                index += length;
+                continue;
+            }
+        }
+        index += 1;
+    }
+
+    let mut dropped = names.clone();
+    loop {
+        let calling: Vec<String> = definitions.iter()
+            .filter(|(name, range)| {
+                !dropped.contains(name) && tokens[range.clone()].iter()
+                    .any(|token| matches!(&token.kind, TokenKind::Word(word) if dropped.contains(word)))
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        if calling.is_empty() {
+            break;
+        }
+        dropped.extend(calling);
+    }
+
+    tokens.into_iter()
+        .enumerate()
+        .filter(|(index, _)| !definitions.iter().any(|(name, range)| dropped.contains(name) && range.contains(index)))
+        .map(|(_, token)| token)
+        .collect()
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use crate::canvas::Canvas;
+use crate::errors::{ErrorKind, LogoError};
+use crate::structs::{Cursor, Environment, Expression, LSystem, Procedure, Span, Value};
+use crate::utils::{evaluate, evaluate_list, evaluate_number, execute_procedure};
+
+/// Most rewrites of an L-system allowed, even if its rules never grow it.
+const MAX_ITERATIONS: f32 = 64.0;
+
+/// Most symbols an L-system may expand to.
+const MAX_SYMBOLS: usize = 1_000_000;
+
+/// One step of an expanded L-system.
+#[derive(Debug, Clone, PartialEq)]
+enum Symbol {
+    Char(char),
+    /// The start of a branch, which saves the turtle.
+    Push,
+    /// The end of a branch, which puts the turtle back.
+    Pop,
+}
+
+/// Expands an L-system and draws it. Each symbol runs its actions through
+/// `execute_procedure`, and branches run PUSHSTATE and POPSTATE around
+/// them. Symbols without actions draw nothing.
+pub fn execute_lsystem(canvas: &mut dyn Canvas, lsystem: &LSystem, span: Span, cursor: &mut Cursor, env: &mut Environment) -> Result<(), LogoError>
+{
+    let axiom = symbols(&evaluate(&lsystem.axiom, cursor, env)?);
+    let rules = evaluate_rules(&lsystem.rules, cursor, env)?;
+    let iterations = evaluate_number(&lsystem.iterations, cursor, env)?;
+    if iterations.fract() != 0.0 || iterations < 0.0 {
+        return Err(LogoError::new(ErrorKind::InvalidCount, lsystem.iterations.span, lsystem.iterations.token()));
+    }
+    let too_large = || LogoError::new(ErrorKind::LSystemTooLarge, lsystem.iterations.span, lsystem.iterations.token());
+    if iterations > MAX_ITERATIONS {
+        return Err(too_large());
+    }
+    let expanded = expand(axiom, &rules, iterations as usize).ok_or_else(too_large)?;
+
+    let actions: HashMap<char, &[(Procedure, Span)]> = lsystem.actions.iter()
+        .map(|action| (action.symbol, action.procedures.as_slice()))
+        .collect();
+    for symbol in expanded {
+        env.usage.step(span)?;
+        match symbol {
+            Symbol::Char(c) => {
+                for (procedure, span) in actions.get(&c).copied().unwrap_or_default() {
+                    execute_procedure(canvas, procedure, *span, cursor, env)?;
+                }
+            },
+            Symbol::Push => execute_procedure(canvas, &Procedure::PUSHSTATE, span, cursor, env)?,
+            Symbol::Pop => execute_procedure(canvas, &Procedure::POPSTATE, span, cursor, env)?,
+        }
+    }
+    Ok(())
+}
+
+/// Every character of a word is a symbol. The items of a list follow one
+/// another, with any nested list a branch.
+fn symbols(value: &Value) -> Vec<Symbol>
+{
+    let mut out = Vec::new();
+    match value {
+        Value::List(items) => {
+            for item in items {
+                push_symbols(item, &mut out);
+            }
+        },
+        other => push_symbols(other, &mut out),
+    }
+    out
+}
+
+fn push_symbols(value: &Value, out: &mut Vec<Symbol>)
+{
+    match value {
+        Value::List(items) => {
+            out.push(Symbol::Push);
+            for item in items {
+                push_symbols(item, out);
+            }
+            out.push(Symbol::Pop);
+        },
+        other => out.extend(other.to_string().chars().map(Symbol::Char)),
+    }
+}
+
+/// Reads a list such as `[F [F + F] X "FX]` into the replacement for each symbol.
+fn evaluate_rules(expression: &Expression, cursor: &Cursor, env: &mut Environment) -> Result<HashMap<char, Vec<Symbol>>, LogoError>
+{
+    let invalid = || LogoError::new(ErrorKind::InvalidLSystemRule, expression.span, expression.token());
+    let items = evaluate_list(expression, cursor, env)?;
+    if items.len() % 2 != 0 {
+        return Err(invalid());
+    }
+
+    let mut rules = HashMap::new();
+    for pair in items.chunks(2) {
+        let symbol = match &pair[0] {
+            Value::List(_) => return Err(invalid()),
+            other => other.to_string(),
+        };
+        let mut chars = symbol.chars();
+        let (Some(symbol), None) = (chars.next(), chars.next()) else {
+            return Err(invalid());
+        };
+        if rules.insert(symbol, symbols(&pair[1])).is_some() {
+            return Err(invalid());
+        }
+    }
+    Ok(rules)
+}
+
+/// Rewrites every symbol that has a rule, `iterations` times over. Returns
+/// `None` if the result would be longer than `MAX_SYMBOLS`.
+fn expand(axiom: Vec<Symbol>, rules: &HashMap<char, Vec<Symbol>>, iterations: usize) -> Option<Vec<Symbol>>
+{
+    let mut current = axiom;
+    for _ in 0..iterations {
+        let mut next = Vec::with_capacity(current.len());
+        for symbol in current {
+            match &symbol {
+                Symbol::Char(c) if rules.contains_key(c) => next.extend_from_slice(&rules[c]),
+                _ => next.push(symbol),
+            }
+            if next.len() > MAX_SYMBOLS {
+                return None;
+            }
+        }
+        current = next;
+    }
+    (current.len() <= MAX_SYMBOLS).then_some(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fail, run};
+
+    fn chars(word: &str) -> Vec<Symbol>
+    {
+        word.chars().map(Symbol::Char).collect()
+    }
+
+    #[test]
+    fn nested_lists_are_branches()
+    {
+        let word = |word: &str| Value::Word(word.to_string());
+        let value = Value::List(vec![word("F"), Value::List(vec![word("+F")]), word("X")]);
+        let branch = [Symbol::Push, Symbol::Char('+'), Symbol::Char('F'), Symbol::Pop];
+        assert_eq!(symbols(&value), [chars("F"), branch.to_vec(), chars("X")].concat());
+    }
+
+    #[test]
+    fn rules_rewrite_every_symbol_at_once()
+    {
+        let rules = HashMap::from([('A', chars("AB")), ('B', chars("A"))]);
+        assert_eq!(expand(chars("A"), &rules, 0), Some(chars("A")));
+        assert_eq!(expand(chars("A"), &rules, 4), Some(chars("ABAABABA")));
+        let doubling = HashMap::from([('F', chars("FF"))]);
+        assert_eq!(expand(chars("F"), &doubling, 19).map(|symbols| symbols.len()), Some(1 << 19));
+        assert_eq!(expand(chars("F"), &doubling, 20), None);
+    }
+
+    #[test]
+    fn symbols_draw_through_their_actions()
+    {
+        let run = run("PENDOWN LSYSTEM \"F [F [F [+ F] F]] 1 [\n  F [FORWARD 10]\n  + [TURN 90]\n]");
+        assert_eq!(run.paths(), ["M 100 100 L 100 90", "M 100 90 L 110 90", "M 100 90 L 100 80"]);
+    }
+
+    #[test]
+    fn runaway_and_malformed_systems_are_errors()
+    {
+        let lsystem = |rules: &str, iterations: &str| fail(&format!("LSYSTEM \"F {rules} {iterations} [\n]")).kind;
+        assert_eq!(lsystem("[F \"FF]", "30"), ErrorKind::LSystemTooLarge);
+        assert_eq!(lsystem("[F \"F]", "65"), ErrorKind::LSystemTooLarge);
+        assert_eq!(lsystem("[F \"F]", "1.5"), ErrorKind::InvalidCount);
+        assert_eq!(lsystem("[F]", "1"), ErrorKind::InvalidLSystemRule);
+        assert_eq!(lsystem("[FF \"F]", "1"), ErrorKind::InvalidLSystemRule);
+        assert_eq!(lsystem("[F \"F F \"FF]", "1"), ErrorKind::InvalidLSystemRule);
+    }
+}
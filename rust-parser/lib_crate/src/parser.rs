@@ -0,0 +1,774 @@
+use std::collections::HashMap;
+use crate::errors::{ErrorKind, LogoError};
+use crate::structs::{Block, Expression, ExpressionKind, Function, LSystem, LSystemAction, Operator, Procedure,
+                     ProcedureDefinition, Program, Query, Span, Statement, StatementKind, Token, TokenKind};
+
+const BUILTINS: [&str; 34] = [
+    "PENUP", "PENDOWN", "FORWARD", "BACK", "LEFT", "RIGHT", "SETPENCOLOR", "TURN", "SETHEADING",
+    "SETX", "SETY", "MAKE", "ADDASSIGN", "IF", "WHILE", "TO", "END", "ARC", "CIRCLE", "HOME",
+    "REPEAT", "FOREACH", "SETPENRGB", "SETPENSIZE", "SETBACKGROUND", "BEGINFILL", "ENDFILL",
+    "PUSHSTATE", "POPSTATE", "NEWTURTLE", "ASK", "LSYSTEM", "LOAD", "RERANDOM",
+];
+
+/// Deepest nesting of blocks and expressions accepted, so that evaluating
+/// the tree cannot overflow the stack.
+const MAX_NESTING: usize = 256;
+
+/// Builds the AST for a whole program from its tokens.
+pub fn parse(tokens: &[Token]) -> Result<Program, LogoError>
+{
+    parse_with_procedures(tokens, &HashMap::new())
+}
+
+/// Like `parse`, but calls may also refer to procedures defined by an earlier
+/// program, such as a previous line typed into the REPL. `known` maps their
+/// names to their parameter counts.
+pub fn parse_with_procedures(tokens: &[Token], known: &HashMap<String, usize>) -> Result<Program, LogoError>
+{
+    Parser::new(tokens, known, false).parse_program()
+}
+
+/// Parses as much of a program as possible, collecting every error instead
+/// of stopping at the first. After an error the rest of the failed statement
+/// is skipped, so the program returned holds only the statements that parsed.
+pub fn parse_recovering(tokens: &[Token]) -> (Program, Vec<LogoError>)
+{
+    let mut parser = Parser::new(tokens, &HashMap::new(), true);
+    let program = parser.parse_program().expect("Errors are collected while recovering");
+    (program, parser.errors.unwrap_or_default())
+}
+
+/// Parses a single expression on its own, such as one typed into the
+/// debugger. The whole input must be one expression.
+pub fn parse_expression(tokens: &[Token]) -> Result<Expression, LogoError>
+{
+    let mut parser = Parser::new(tokens, &HashMap::new(), false);
+    let expression = parser.parse_infix(1)?;
+    match parser.peek() {
+        Some(token) => Err(LogoError::new(ErrorKind::TooManyArgs, token.span, token.kind.to_string())),
+        None => Ok(expression),
+    }
+}
+
+/// How many tokens the argument at the start of `tokens` takes up, read as
+/// a command such as FORWARD reads it, or `None` if it does not parse.
+pub fn argument_length(tokens: &[Token]) -> Option<usize>
+{
+    let mut parser = Parser::new(tokens, &HashMap::new(), false);
+    parser.parse_infix(1).ok()?;
+    Some(parser.position)
+}
+
+/// Finds the parameter count of every `TO` definition so that calls can be
+/// parsed before (or inside) the definition of the procedure they call.
+fn collect_arities(tokens: &[Token]) -> HashMap<String, usize>
+{
+    let mut arities = HashMap::new();
+    for (index, token) in tokens.iter().enumerate() {
+        if !is_word(token, "TO") {
+            continue;
+        }
+        if let Some(Token { kind: TokenKind::Word(name), .. }) = tokens.get(index + 1) {
+            let parameters = tokens[index + 2..].iter()
+                .take_while(|token| matches!(token.kind, TokenKind::Value(_)))
+                .count();
+            arities.insert(name.clone(), parameters);
+        }
+    }
+    arities
+}
+
+/// Whether `name` is taken by the language, so no procedure can be given it.
+pub fn is_reserved(name: &str) -> bool
+{
+    BUILTINS.contains(&name) || Query::from_name(name).is_some() || Operator::from_name(name).is_some()
+        || Function::from_name(name).is_some()
+}
+
+fn is_word(token: &Token, word: &str) -> bool
+{
+    matches!(&token.kind, TokenKind::Word(name) if name == word)
+}
+
+/// Bare numbers such as `10` or `-2.5`, and `TRUE` and `FALSE`, can be
+/// written without a quote.
+fn is_literal(word: &str) -> bool
+{
+    word == "TRUE" || word == "FALSE" || is_number(word)
+}
+
+fn is_number(word: &str) -> bool
+{
+    word.parse::<f32>().is_ok_and(|number| number.is_finite())
+}
+
+/// Whether `second` starts right where `first` ends, with no space between.
+fn adjacent(first: &Token, second: &Token) -> bool
+{
+    first.span.file == second.span.file && first.span.line == second.span.line
+        && first.span.column + first.span.length == second.span.column
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    depth: usize,
+    arities: HashMap<String, usize>,
+    /// The errors found so far, if parsing carries on after an error.
+    errors: Option<Vec<LogoError>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token], known: &HashMap<String, usize>, recover: bool) -> Parser<'a> {
+        let mut arities = known.clone();
+        arities.extend(collect_arities(tokens));
+        Parser {
+            tokens,
+            position: 0,
+            depth: 0,
+            arities,
+            errors: recover.then(Vec::new),
+        }
+    }
+
+    fn parse_program(&mut self) -> Result<Program, LogoError> {
+        let mut program = Program::default();
+        while let Some(token) = self.peek() {
+            if !is_word(token, "TO") {
+                self.parse_into(&mut program.statements)?;
+                continue;
+            }
+            let definition = match self.parse_definition() {
+                Ok(definition) => definition,
+                Err(err) => {
+                    // Errors in the body were already recovered from, so this
+                    // one is in the header. Skip the body rather than parse it
+                    // as top level statements.
+                    self.recover(err)?;
+                    while let Some(token) = self.next() {
+                        if is_word(token, "END") {
+                            break;
+                        }
+                    }
+                    continue;
+                },
+            };
+            if program.procedures.contains_key(&definition.name) {
+                let err = LogoError::new(ErrorKind::DuplicateProcedure, definition.span, definition.name);
+                self.recover(err)?;
+                continue;
+            }
+            program.procedures.insert(definition.name.clone(), definition);
+        }
+        Ok(program)
+    }
+
+    /// Parses a statement onto the end of `block`. When recovering, a
+    /// statement that fails is recorded and skipped instead.
+    fn parse_into(&mut self, block: &mut Block) -> Result<(), LogoError> {
+        let depth = self.depth;
+        match self.parse_statement() {
+            Ok(statement) => block.push(statement),
+            Err(err) => {
+                self.depth = depth;
+                self.recover(err)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Returns `err` unless recovering, in which case it is recorded and the
+    /// rest of the failed statement is skipped.
+    fn recover(&mut self, err: LogoError) -> Result<(), LogoError> {
+        match &mut self.errors {
+            Some(errors) => errors.push(err),
+            None => return Err(err),
+        }
+        self.synchronize();
+        Ok(())
+    }
+
+    /// Skips to the next word that starts a statement, or to a `]` that
+    /// closes the enclosing block. Whole `[ ... ]` blocks are skipped over.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match &token.kind {
+                TokenKind::OpenBracket => depth += 1,
+                TokenKind::CloseBracket if depth == 0 => return,
+                TokenKind::CloseBracket => depth -= 1,
+                TokenKind::Word(word) if depth == 0
+                    && (BUILTINS.contains(&word.as_str()) || self.arities.contains_key(word)) => return,
+                _ => {},
+            }
+            self.next();
+        }
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    /// Span used for errors at the end of the input.
+    fn end_span(&self) -> Span {
+        self.tokens.last().map(|token| token.span).unwrap_or_default()
+    }
+
+    fn enter(&mut self, token: &Token) -> Result<(), LogoError> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(LogoError::new(ErrorKind::NestingTooDeep, token.span, token.kind.to_string()));
+        }
+        Ok(())
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, LogoError> {
+        let token = match self.next() {
+            Some(token) => token,
+            None => return Err(LogoError::new(ErrorKind::ExpectedExpression, self.end_span(), "")),
+        };
+        self.enter(token)?;
+        let statement = self.parse_statement_kind(token);
+        self.depth -= 1;
+        Ok(Statement { kind: statement?, span: token.span })
+    }
+
+    fn parse_statement_kind(&mut self, token: &Token) -> Result<StatementKind, LogoError> {
+        let span = token.span;
+        let name = match &token.kind {
+            TokenKind::Word(name) => name.as_str(),
+            TokenKind::Value(_) | TokenKind::Variable(_) => {
+                return Err(LogoError::new(ErrorKind::TooManyArgs, span, token.kind.to_string()));
+            },
+            TokenKind::OpenBracket => return Err(LogoError::new(ErrorKind::UnexpectedBracket, span, "[")),
+            TokenKind::CloseBracket => return Err(LogoError::new(ErrorKind::UnmatchedCloseBracket, span, "]")),
+            TokenKind::OpenParen => return Err(LogoError::new(ErrorKind::TooManyArgs, span, "(")),
+            TokenKind::CloseParen => return Err(LogoError::new(ErrorKind::UnmatchedCloseParen, span, ")")),
+        };
+        if is_literal(name) {
+            return Err(LogoError::new(ErrorKind::TooManyArgs, span, name));
+        }
+
+        let kind = match name {
+            "PENUP" => StatementKind::Procedure(Procedure::PENUP),
+            "PENDOWN" => StatementKind::Procedure(Procedure::PENDOWN),
+            "FORWARD" => StatementKind::Procedure(Procedure::FORWARD(self.parse_argument(token)?)),
+            "BACK" => StatementKind::Procedure(Procedure::BACK(self.parse_argument(token)?)),
+            "LEFT" => StatementKind::Procedure(Procedure::LEFT(self.parse_argument(token)?)),
+            "RIGHT" => StatementKind::Procedure(Procedure::RIGHT(self.parse_argument(token)?)),
+            "SETPENCOLOR" => StatementKind::Procedure(Procedure::SETPENCOLOR(self.parse_argument(token)?)),
+            "TURN" => StatementKind::Procedure(Procedure::TURN(self.parse_argument(token)?)),
+            "SETHEADING" => StatementKind::Procedure(Procedure::SETHEADING(self.parse_argument(token)?)),
+            "SETX" => StatementKind::Procedure(Procedure::SETX(self.parse_argument(token)?)),
+            "SETY" => StatementKind::Procedure(Procedure::SETY(self.parse_argument(token)?)),
+            "ARC" => {
+                let angle = self.parse_argument(token)?;
+                StatementKind::Procedure(Procedure::ARC(angle, self.parse_argument(token)?))
+            },
+            "CIRCLE" => StatementKind::Procedure(Procedure::CIRCLE(self.parse_argument(token)?)),
+            "HOME" => StatementKind::Procedure(Procedure::HOME),
+            "SETPENRGB" => {
+                let red = self.parse_argument(token)?;
+                let green = self.parse_argument(token)?;
+                StatementKind::Procedure(Procedure::SETPENRGB(red, green, self.parse_argument(token)?))
+            },
+            "SETPENSIZE" => StatementKind::Procedure(Procedure::SETPENSIZE(self.parse_argument(token)?)),
+            "SETBACKGROUND" => StatementKind::Procedure(Procedure::SETBACKGROUND(self.parse_argument(token)?)),
+            "BEGINFILL" => StatementKind::Procedure(Procedure::BEGINFILL),
+            "ENDFILL" => StatementKind::Procedure(Procedure::ENDFILL),
+            "PUSHSTATE" => StatementKind::Procedure(Procedure::PUSHSTATE),
+            "POPSTATE" => StatementKind::Procedure(Procedure::POPSTATE),
+            "NEWTURTLE" => StatementKind::Procedure(Procedure::NEWTURTLE(self.parse_argument(token)?)),
+            "RERANDOM" => StatementKind::Procedure(Procedure::RERANDOM(self.parse_argument(token)?)),
+            "MAKE" => {
+                let name = self.parse_name(token)?;
+                StatementKind::Procedure(Procedure::MAKE(name, self.parse_argument(token)?))
+            },
+            "ADDASSIGN" => {
+                let name = self.parse_name(token)?;
+                StatementKind::Procedure(Procedure::ADDASSIGN(name, self.parse_argument(token)?))
+            },
+            "IF" => {
+                let condition = self.parse_argument(token)?;
+                StatementKind::If(condition, self.parse_block(token)?)
+            },
+            "WHILE" => {
+                let condition = self.parse_argument(token)?;
+                StatementKind::While(condition, self.parse_block(token)?)
+            },
+            "REPEAT" => {
+                let count = self.parse_argument(token)?;
+                StatementKind::Repeat(count, self.parse_block(token)?)
+            },
+            "FOREACH" => {
+                let name = self.parse_name(token)?;
+                let list = self.parse_argument(token)?;
+                StatementKind::Foreach(name, list, self.parse_block(token)?)
+            },
+            "ASK" => {
+                let turtle = self.parse_argument(token)?;
+                StatementKind::Ask(turtle, self.parse_block(token)?)
+            },
+            "LSYSTEM" => StatementKind::LSystem(self.parse_lsystem(token)?),
+            // Replaced by the file it names before parsing, by `load::expand`
+            "LOAD" => return Err(LogoError::new(ErrorKind::LoadNotExpanded, span, name)),
+            "TO" => return Err(LogoError::new(ErrorKind::NestedDefinition, span, name)),
+            "END" => return Err(LogoError::new(ErrorKind::EndWithoutTo, span, name)),
+            _ => {
+                let arity = match self.arities.get(name) {
+                    Some(arity) => *arity,
+                    None => return Err(LogoError::new(ErrorKind::UnknownProcedure, span, name)),
+                };
+                let mut arguments = Vec::new();
+                for _ in 0..arity {
+                    arguments.push(self.parse_argument(token)?);
+                }
+                StatementKind::Call(name.to_string(), arguments)
+            },
+        };
+        Ok(kind)
+    }
+
+    /// Parses `TO name "param ... END`.
+    fn parse_definition(&mut self) -> Result<ProcedureDefinition, LogoError> {
+        let span = self.next().expect("Caller checked for TO").span;
+        let name = match self.next() {
+            Some(Token { kind: TokenKind::Word(name), span }) => {
+                if is_reserved(name) {
+                    return Err(LogoError::new(ErrorKind::RedefinedBuiltin, *span, name));
+                }
+                name.clone()
+            },
+            Some(token) => return Err(LogoError::new(ErrorKind::ExpectedName, token.span, token.kind.to_string())),
+            None => return Err(LogoError::new(ErrorKind::ExpectedName, span, "TO")),
+        };
+
+        let mut parameters = Vec::new();
+        while let Some(Token { kind: TokenKind::Value(parameter), .. }) = self.peek() {
+            parameters.push(parameter.clone());
+            self.next();
+        }
+
+        let mut body = Vec::new();
+        loop {
+            match self.peek() {
+                Some(token) if is_word(token, "END") => {
+                    self.next();
+                    break;
+                },
+                Some(_) => self.parse_into(&mut body)?,
+                None => return Err(LogoError::new(ErrorKind::MissingEnd, span, name)),
+            }
+        }
+
+        Ok(ProcedureDefinition { name, parameters, body, span })
+    }
+
+    /// Parses `[ statements... ]` following an IF, WHILE, REPEAT, FOREACH or ASK header.
+    fn parse_block(&mut self, owner: &Token) -> Result<Block, LogoError> {
+        match self.next() {
+            Some(Token { kind: TokenKind::OpenBracket, .. }) => {},
+            Some(token) => {
+                return Err(LogoError::new(ErrorKind::MissingOpenBracket, token.span, token.kind.to_string()));
+            },
+            None => return Err(LogoError::new(ErrorKind::MissingOpenBracket, owner.span, owner.kind.to_string())),
+        }
+
+        let mut statements = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token { kind: TokenKind::CloseBracket, .. }) => {
+                    self.next();
+                    return Ok(statements);
+                },
+                Some(_) => self.parse_into(&mut statements)?,
+                None => {
+                    return Err(LogoError::new(ErrorKind::MissingCloseBracket, owner.span, owner.kind.to_string()));
+                },
+            }
+        }
+    }
+
+    /// Parses the arguments of LSYSTEM. The actions are written as pairs of
+    /// a symbol and a block, such as `[F [FORWARD 5] + [TURN 90]]`.
+    fn parse_lsystem(&mut self, owner: &Token) -> Result<LSystem, LogoError> {
+        let axiom = self.parse_argument(owner)?;
+        let rules = self.parse_argument(owner)?;
+        let iterations = self.parse_argument(owner)?;
+        match self.next() {
+            Some(Token { kind: TokenKind::OpenBracket, .. }) => {},
+            Some(token) => {
+                return Err(LogoError::new(ErrorKind::MissingOpenBracket, token.span, token.kind.to_string()));
+            },
+            None => return Err(LogoError::new(ErrorKind::MissingOpenBracket, owner.span, owner.kind.to_string())),
+        }
+
+        // Bad symbols and actions are only reported once the whole list is
+        // read, so that the parser does not lose its place in it
+        let mut actions: Vec<LSystemAction> = Vec::new();
+        let mut invalid = None;
+        loop {
+            let token = match self.next() {
+                Some(Token { kind: TokenKind::CloseBracket, .. }) => break,
+                Some(token) => token,
+                None => {
+                    return Err(LogoError::new(ErrorKind::MissingCloseBracket, owner.span, owner.kind.to_string()));
+                },
+            };
+            let symbol = match &token.kind {
+                TokenKind::Word(word) | TokenKind::Value(word) if word.chars().count() == 1 => word.chars().next(),
+                _ => None,
+            };
+            let symbol = match symbol {
+                Some(symbol) if actions.iter().all(|action| action.symbol != symbol) => symbol,
+                _ => {
+                    let err = LogoError::new(ErrorKind::InvalidLSystemSymbol, token.span, token.kind.to_string());
+                    invalid.get_or_insert(err);
+                    ' '
+                },
+            };
+
+            let mut procedures = Vec::new();
+            for statement in self.parse_block(token)? {
+                match statement.kind {
+                    StatementKind::Procedure(procedure) => procedures.push((procedure, statement.span)),
+                    other => {
+                        let err = LogoError::new(ErrorKind::InvalidLSystemAction, statement.span, other.name());
+                        invalid.get_or_insert(err);
+                    },
+                }
+            }
+            actions.push(LSystemAction { symbol, procedures });
+        }
+        if let Some(err) = invalid {
+            return Err(err);
+        }
+        Ok(LSystem { axiom, rules, iterations, actions })
+    }
+
+    /// Parses the quoted variable name taken by MAKE, ADDASSIGN and FOREACH.
+    fn parse_name(&mut self, owner: &Token) -> Result<String, LogoError> {
+        match self.next() {
+            Some(Token { kind: TokenKind::Value(name), .. }) => Ok(name.clone()),
+            Some(token) => Err(LogoError::new(ErrorKind::ExpectedName, token.span, token.kind.to_string())),
+            None => Err(LogoError::new(ErrorKind::NotEnoughArgs, owner.span, owner.kind.to_string())),
+        }
+    }
+
+    /// Parses one argument of `owner`, reporting a missing argument against it.
+    /// Arguments may be written infix, as in `FORWARD (:size + 2) * 3`.
+    fn parse_argument(&mut self, owner: &Token) -> Result<Expression, LogoError> {
+        self.expect_operand(owner)?;
+        self.parse_infix(1)
+    }
+
+    /// Parses one operand of a prefix operator or function. Operands do not
+    /// extend infix, so `+ :a - :b :c` keeps its prefix meaning.
+    fn parse_operand(&mut self, owner: &Token) -> Result<Expression, LogoError> {
+        self.expect_operand(owner)?;
+        self.parse_expression()
+    }
+
+    fn expect_operand(&self, owner: &Token) -> Result<(), LogoError> {
+        let missing = match self.peek() {
+            None => true,
+            Some(Token { kind: TokenKind::Word(word), .. }) => {
+                BUILTINS.contains(&word.as_str()) || self.arities.contains_key(word)
+            },
+            Some(Token { kind: TokenKind::CloseParen, .. }) => true,
+            Some(_) => false,
+        };
+        if missing {
+            return Err(LogoError::new(ErrorKind::NotEnoughArgs, owner.span, owner.kind.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Parses an expression followed by any infix operators binding at least
+    /// as tightly as `min_precedence`.
+    fn parse_infix(&mut self, min_precedence: u8) -> Result<Expression, LogoError> {
+        let depth = self.depth;
+        let expression = self.parse_infix_chain(min_precedence);
+        self.depth = depth;
+        expression
+    }
+
+    fn parse_infix_chain(&mut self, min_precedence: u8) -> Result<Expression, LogoError> {
+        let mut left = self.parse_expression()?;
+        while let Some((operator, token)) = self.peek_infix(min_precedence) {
+            self.next();
+            // Each operator adds a level to the tree, so long chains count
+            // towards the nesting limit too
+            self.enter(token)?;
+            self.expect_operand(token)?;
+            let precedence = operator.precedence();
+            let next = if operator.is_right_associative() { precedence } else { precedence + 1 };
+            let right = self.parse_infix(next)?;
+            left = Expression {
+                kind: ExpressionKind::Binary(operator, Box::new(left), Box::new(right)),
+                span: token.span,
+            };
+        }
+        Ok(left)
+    }
+
+    /// The next token if it is an infix operator binding at least as tightly
+    /// as `min_precedence`. A `-` stuck to the following token but not the
+    /// previous one, as in `:a -:b`, is unary minus and ends the expression.
+    fn peek_infix(&self, min_precedence: u8) -> Option<(Operator, &'a Token)> {
+        let token = self.peek()?;
+        let operator = match &token.kind {
+            TokenKind::Word(word) => Operator::from_name(word)?,
+            _ => return None,
+        };
+        if operator.precedence() < min_precedence {
+            return None;
+        }
+        if matches!(operator, Operator::SUB) && self.is_unary_minus(token) {
+            let previous = &self.tokens[self.position - 1];
+            if !adjacent(previous, token) {
+                return None;
+            }
+        }
+        Some((operator, token))
+    }
+
+    /// Whether a `-` at the current position is directly followed by its operand.
+    fn is_unary_minus(&self, minus: &Token) -> bool {
+        match self.tokens.get(self.position + 1) {
+            Some(next) => adjacent(minus, next),
+            None => false,
+        }
+    }
+
+    /// Parses a single operand: a literal, variable, query, list, parenthesised
+    /// expression, or a prefix operator or function with its operands.
+    fn parse_expression(&mut self) -> Result<Expression, LogoError> {
+        let token = match self.next() {
+            Some(token) => token,
+            None => return Err(LogoError::new(ErrorKind::ExpectedExpression, self.end_span(), "")),
+        };
+        self.enter(token)?;
+        let kind = self.parse_expression_kind(token);
+        self.depth -= 1;
+        Ok(Expression { kind: kind?, span: token.span })
+    }
+
+    fn parse_expression_kind(&mut self, token: &Token) -> Result<ExpressionKind, LogoError> {
+        let kind = match &token.kind {
+            TokenKind::Value(value) => ExpressionKind::Value(value.clone()),
+            TokenKind::Variable(name) => ExpressionKind::Variable(name.clone()),
+            TokenKind::Word(word) => {
+                if is_literal(word) {
+                    ExpressionKind::Value(word.clone())
+                }
+                else if let Some(query) = Query::from_name(word) {
+                    ExpressionKind::Query(query)
+                }
+                else if word == "-" && self.peek().is_some_and(|next| adjacent(token, next)) {
+                    ExpressionKind::Negate(Box::new(self.parse_operand(token)?))
+                }
+                else if let Some(operator) = Operator::from_name(word) {
+                    let left = self.parse_operand(token)?;
+                    let right = self.parse_operand(token)?;
+                    ExpressionKind::Binary(operator, Box::new(left), Box::new(right))
+                }
+                else if let Some(function) = Function::from_name(word) {
+                    let mut arguments = Vec::new();
+                    for _ in 0..function.arity() {
+                        arguments.push(self.parse_operand(token)?);
+                    }
+                    ExpressionKind::Function(function, arguments)
+                }
+                else {
+                    return Err(LogoError::new(ErrorKind::UnknownValue, token.span, word));
+                }
+            },
+            TokenKind::OpenBracket => ExpressionKind::List(self.parse_list(token)?),
+            TokenKind::OpenParen => {
+                self.expect_operand(token)?;
+                let inner = self.parse_infix(1)?;
+                match self.next() {
+                    Some(Token { kind: TokenKind::CloseParen, .. }) => inner.kind,
+                    _ => return Err(LogoError::new(ErrorKind::MissingCloseParen, token.span, "(")),
+                }
+            },
+            TokenKind::CloseBracket | TokenKind::CloseParen => {
+                return Err(LogoError::new(ErrorKind::ExpectedExpression, token.span, token.kind.to_string()));
+            },
+        };
+        Ok(kind)
+    }
+
+    /// Parses the items of a `[ ... ]` list literal after its opening bracket.
+    /// Items are kept as written, so words are never treated as procedures.
+    fn parse_list(&mut self, open: &Token) -> Result<Vec<Expression>, LogoError> {
+        let mut items = Vec::new();
+        loop {
+            let token = match self.next() {
+                Some(token) => token,
+                None => return Err(LogoError::new(ErrorKind::MissingCloseBracket, open.span, "[")),
+            };
+            let kind = match &token.kind {
+                TokenKind::CloseBracket => return Ok(items),
+                TokenKind::Word(word) | TokenKind::Value(word) => ExpressionKind::Value(word.clone()),
+                TokenKind::OpenParen | TokenKind::CloseParen => ExpressionKind::Value(token.kind.to_string()),
+                TokenKind::Variable(name) => ExpressionKind::Variable(name.clone()),
+                TokenKind::OpenBracket => {
+                    self.enter(token)?;
+                    let list = self.parse_list(token);
+                    self.depth -= 1;
+                    ExpressionKind::List(list?)
+                },
+            };
+            items.push(Expression { kind, span: token.span });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+
+    fn parse_source(source: &str) -> Result<Program, LogoError>
+    {
+        parse(&lexer::tokenize(source))
+    }
+
+    /// The expression `MAKE` is given in `source`, written back out in
+    /// prefix form.
+    fn prefix(source: &str) -> String
+    {
+        let program = parse_source(&format!("MAKE \"e {source}")).unwrap();
+        let StatementKind::Procedure(Procedure::MAKE(_, expression)) = &program.statements[0].kind else {
+            panic!("{:?}", program.statements)
+        };
+        write_prefix(expression)
+    }
+
+    fn write_prefix(expression: &Expression) -> String
+    {
+        let (head, operands): (String, Vec<&Expression>) = match &expression.kind {
+            ExpressionKind::Binary(operator, left, right) => (operator.to_string(), vec![left, right]),
+            ExpressionKind::Function(function, arguments) => (format!("{function:?}"), arguments.iter().collect()),
+            ExpressionKind::Negate(operand) => return format!("-{}", write_prefix(operand)),
+            _ => return expression.token(),
+        };
+        let operands: Vec<String> = operands.into_iter().map(write_prefix).collect();
+        format!("{head} {}", operands.join(" "))
+    }
+
+    /// What `source` fails to parse with, and where.
+    fn error(source: &str) -> (ErrorKind, usize, usize)
+    {
+        let err = parse_source(source).expect_err("program should not parse");
+        (err.kind, err.span.line, err.span.column)
+    }
+
+    fn starts(block: &Block) -> Vec<(usize, usize)>
+    {
+        block.iter().map(|statement| (statement.span.line, statement.span.column)).collect()
+    }
+
+    #[test]
+    fn tokens_split_on_brackets_comments_and_unary_minus()
+    {
+        let kinds: Vec<TokenKind> = lexer::tokenize("IF EQ :x \"1 [FORWARD :x] // done\n  TURN \"5")
+            .into_iter()
+            .map(|token| token.kind)
+            .collect();
+        let word = |word: &str| TokenKind::Word(word.to_string());
+        assert_eq!(kinds, [
+            word("IF"), word("EQ"), TokenKind::Variable("x".to_string()), TokenKind::Value("1".to_string()),
+            TokenKind::OpenBracket, word("FORWARD"), TokenKind::Variable("x".to_string()), TokenKind::CloseBracket,
+            word("TURN"), TokenKind::Value("5".to_string()),
+        ]);
+        let kinds: Vec<String> = lexer::tokenize("TURN -:a -5 (-2)").iter()
+            .map(|token| token.kind.to_string())
+            .collect();
+        assert_eq!(kinds, ["TURN", "-", ":a", "-5", "(", "-2", ")"]);
+    }
+
+    #[test]
+    fn statements_are_not_tied_to_lines()
+    {
+        let block = parse_source("PENDOWN FORWARD \"10 TURN \"90\nSETX\n  + \"1\n  \"2\n").unwrap().statements;
+        assert_eq!(starts(&block), [(1, 1), (1, 9), (1, 21), (2, 1)]);
+        let StatementKind::Procedure(Procedure::SETX(expression)) = &block[3].kind else { panic!("{block:?}") };
+        assert!(matches!(&expression.kind, ExpressionKind::Binary(Operator::ADD, _, _)), "{expression:?}");
+        assert_eq!((expression.span.line, expression.span.column), (3, 3));
+    }
+
+    #[test]
+    fn blocks_open_and_close_anywhere()
+    {
+        let source = "IF \"TRUE [FORWARD \"1] IF \"FALSE\n[\nBACK \"2 ]\nWHILE \"FALSE [\n]";
+        let block = parse_source(source).unwrap().statements;
+        let lengths: Vec<usize> = block.iter()
+            .map(|statement| match &statement.kind {
+                StatementKind::If(_, body) | StatementKind::While(_, body) => body.len(),
+                other => panic!("{other:?}"),
+            })
+            .collect();
+        assert_eq!(lengths, [1, 1, 0]);
+        assert_eq!(starts(&block), [(1, 1), (1, 23), (4, 1)]);
+    }
+
+    #[test]
+    fn misplaced_brackets_are_errors()
+    {
+        assert_eq!(error("FORWARD \"1 ]"), (ErrorKind::UnmatchedCloseBracket, 1, 12));
+        assert_eq!(error("PENDOWN\nIF \"TRUE [ FORWARD \"1"), (ErrorKind::MissingCloseBracket, 2, 1));
+        assert_eq!(error("IF \"TRUE FORWARD \"1"), (ErrorKind::MissingOpenBracket, 1, 10));
+        assert_eq!(error("[ FORWARD \"1 ]"), (ErrorKind::UnexpectedBracket, 1, 1));
+        assert_eq!(error("FORWARD"), (ErrorKind::NotEnoughArgs, 1, 1));
+        assert_eq!(error("FORWARD \"1 \"2"), (ErrorKind::TooManyArgs, 1, 12));
+        assert_eq!(error("FORWARD (1 + 2"), (ErrorKind::MissingCloseParen, 1, 9));
+        assert_eq!(error("FORWARD 1 )"), (ErrorKind::UnmatchedCloseParen, 1, 11));
+    }
+
+    #[test]
+    fn procedures_are_defined_apart_from_the_statements()
+    {
+        let program = parse_source("Box \"5\nTO Box \"size\n  FORWARD :size\nEND\nTO Nothing\nEND\n").unwrap();
+        let StatementKind::Call(name, arguments) = &program.statements[0].kind else {
+            panic!("{:?}", program.statements)
+        };
+        assert_eq!((name.as_str(), arguments.len()), ("Box", 1));
+        let definition = &program.procedures["Box"];
+        assert_eq!(definition.parameters, ["size"]);
+        assert_eq!((definition.body.len(), definition.span.line), (1, 2));
+        assert!(program.procedures["Nothing"].body.is_empty());
+    }
+
+    #[test]
+    fn misplaced_definitions_are_errors()
+    {
+        assert_eq!(error("TO A\n  TO B\n  END\nEND"), (ErrorKind::NestedDefinition, 2, 3));
+        assert_eq!(error("FORWARD \"1\nEND"), (ErrorKind::EndWithoutTo, 2, 1));
+        assert_eq!(error("TO A\n  FORWARD \"1\n"), (ErrorKind::MissingEnd, 1, 1));
+        assert_eq!(error("TO A\nEND\nTO A\nEND"), (ErrorKind::DuplicateProcedure, 3, 1));
+        assert_eq!(error("TO FORWARD \"x\nEND"), (ErrorKind::RedefinedBuiltin, 1, 4));
+        assert_eq!(error("TO\n"), (ErrorKind::ExpectedName, 1, 1));
+        assert_eq!(error("A \"1"), (ErrorKind::UnknownProcedure, 1, 1));
+        assert_eq!(error("TO A \"x\nEND\nA \"1 \"2"), (ErrorKind::TooManyArgs, 3, 6));
+    }
+
+    #[test]
+    fn infix_follows_precedence_and_prefix_is_kept()
+    {
+        assert_eq!(prefix("(:x + 2) * 3"), "* + :x \"2 \"3");
+        assert_eq!(prefix(":x + 2 * 3"), "+ :x * \"2 \"3");
+        assert_eq!(prefix("2 ^ 3 ^ 2 - 1"), "- ^ \"2 ^ \"3 \"2 \"1");
+        assert_eq!(prefix("-:x % 4 GE 1 AND NOT EQ :y 2"), "AND GE % -:x \"4 \"1 NOT EQ :y \"2");
+        assert_eq!(prefix("* + :x \"2 3"), "* + :x \"2 \"3");
+        assert_eq!(prefix("SQRT :x + 1"), "+ SQRT :x \"1");
+    }
+}
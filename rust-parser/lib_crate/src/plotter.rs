@@ -0,0 +1,153 @@
+use std::fmt::Write;
+use std::path::Path;
+use serde_json::json;
+use unsvg::Color;
+use crate::canvas::{hex, Canvas};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlotterFormat {
+    Json,
+    Gcode,
+}
+
+/// A line the turtle drew.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub start: (f32, f32),
+    pub end: (f32, f32),
+    pub color: Color,
+    pub width: f32,
+}
+
+/// A polygon the turtle filled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilledPolygon {
+    pub points: Vec<(f32, f32)>,
+    pub color: Color,
+}
+
+/// Records every line and fill in the order they were drawn, for pen
+/// plotters and other tools.
+///
+/// The JSON output lists the segments and fills as drawn. The G-code output
+/// moves a pen over each segment, lifting it between segments that do not
+/// join up and pausing for a pen change whenever the color changes. It uses
+/// millimetres, one per pixel, with y flipped so that the drawing is the
+/// right way up on a plotter whose origin is at the bottom left. Fills and
+/// line widths cannot be plotted, so they are left out of the G-code.
+pub struct PlotterCanvas {
+    width: u32,
+    height: u32,
+    format: PlotterFormat,
+    background: Color,
+    pub segments: Vec<Segment>,
+    pub fills: Vec<FilledPolygon>,
+}
+
+impl PlotterCanvas {
+    pub fn new(width: u32, height: u32, format: PlotterFormat) -> PlotterCanvas {
+        PlotterCanvas {
+            width,
+            height,
+            format,
+            background: Color::black(),
+            segments: Vec::new(),
+            fills: Vec::new(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let segments: Vec<_> = self.segments.iter()
+            .map(|segment| json!({
+                "from": [segment.start.0, segment.start.1],
+                "to": [segment.end.0, segment.end.1],
+                "color": hex(segment.color),
+                "width": segment.width,
+            }))
+            .collect();
+        let fills: Vec<_> = self.fills.iter()
+            .map(|fill| json!({
+                "points": fill.points.iter().map(|(x, y)| [*x, *y]).collect::<Vec<_>>(),
+                "color": hex(fill.color),
+            }))
+            .collect();
+        let log = json!({
+            "width": self.width,
+            "height": self.height,
+            "background": hex(self.background),
+            "segments": segments,
+            "fills": fills,
+        });
+        format!("{log}\n")
+    }
+
+    pub fn to_gcode(&self) -> String {
+        let mut out = String::new();
+        out.push_str("; rslogo plot\n");
+        out.push_str("G21 ; millimetres\n");
+        out.push_str("G90 ; absolute positions\n");
+        out.push_str("G0 Z5 ; pen up\n");
+
+        let mut position: Option<(f32, f32)> = None;
+        let mut color: Option<Color> = None;
+        for segment in &self.segments {
+            if color != Some(segment.color) {
+                if position.is_some() {
+                    out.push_str("G0 Z5\n");
+                    position = None;
+                }
+                let _ = writeln!(out, "M0 ; change to pen {}", hex(segment.color));
+                color = Some(segment.color);
+            }
+            if position != Some(segment.start) {
+                if position.is_some() {
+                    out.push_str("G0 Z5\n");
+                }
+                let (x, y) = self.plotter_point(segment.start);
+                let _ = writeln!(out, "G0 X{x} Y{y}");
+                out.push_str("G1 Z0 ; pen down\n");
+            }
+            let (x, y) = self.plotter_point(segment.end);
+            let _ = writeln!(out, "G1 X{x} Y{y}");
+            position = Some(segment.end);
+        }
+
+        out.push_str("G0 Z5 ; pen up\n");
+        out
+    }
+
+    fn plotter_point(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        (x, self.height as f32 - y)
+    }
+}
+
+impl Canvas for PlotterCanvas {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn set_background(&mut self, color: Color) {
+        self.background = color;
+    }
+
+    fn line(&mut self, start: (f32, f32), end: (f32, f32), color: Color, width: f32) -> Result<(), String> {
+        self.segments.push(Segment { start, end, color, width });
+        Ok(())
+    }
+
+    fn end_fill(&mut self, points: &[(f32, f32)], color: Color) -> Result<(), String> {
+        self.fills.push(FilledPolygon { points: points.to_vec(), color });
+        Ok(())
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_text().unwrap_or_default()).map_err(|e| e.to_string())
+    }
+
+    fn to_text(&self) -> Option<String> {
+        match self.format {
+            PlotterFormat::Json => Some(self.to_json()),
+            PlotterFormat::Gcode => Some(self.to_gcode()),
+        }
+    }
+}
@@ -1,48 +1,656 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
 use unsvg::{Color, COLORS};
+use rand::{RngExt, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use crate::bytecode::CompiledProcedure;
+use crate::canvas::Axes;
+use crate::interpreter::NativeProcedure;
+use crate::limits::Usage;
+use crate::trace::Tracer;
+
+/// Position of a token in the source file. Lines and columns start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    /// Which file the token is in, as numbered by `load::Sources`. The
+    /// program run is file 0.
+    pub file: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// A bare word, e.g. `FORWARD`, `XCOR` or `+`.
+    Word(String),
+    /// A quoted value, e.g. `"50` or `"TRUE`. The quote is stripped.
+    Value(String),
+    /// A variable reference, e.g. `:size`. The colon is stripped.
+    Variable(String),
+    OpenBracket,
+    CloseBracket,
+    OpenParen,
+    CloseParen,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// A `//` comment. The parser never sees comments, but the formatter keeps them.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    /// The comment, starting with `//`, or with `;` in the UCB dialect.
+    pub text: String,
+    pub span: Span,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Word(word) => write!(f, "{word}"),
+            TokenKind::Value(value) => write!(f, "\"{value}"),
+            TokenKind::Variable(name) => write!(f, ":{name}"),
+            TokenKind::OpenBracket => write!(f, "["),
+            TokenKind::CloseBracket => write!(f, "]"),
+            TokenKind::OpenParen => write!(f, "("),
+            TokenKind::CloseParen => write!(f, ")"),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum Procedure {
     PENUP,
     PENDOWN,
-    FORWARD(f32),
-    BACK(f32),
-    LEFT(f32),
-    RIGHT(f32),
-    SETPENCOLOR(f32),
-    TURN(f32),
-    SETHEADING(f32),
-    SETX(f32),
-    SETY(f32),
-    MAKE(String, f32),
+    FORWARD(Expression),
+    BACK(Expression),
+    LEFT(Expression),
+    RIGHT(Expression),
+    SETPENCOLOR(Expression),
+    TURN(Expression),
+    SETHEADING(Expression),
+    SETX(Expression),
+    SETY(Expression),
+    MAKE(String, Expression),
+    ADDASSIGN(String, Expression),
+    /// `ARC angle radius`: an arc centred on the cursor, clockwise from its heading.
+    ARC(Expression, Expression),
+    /// `CIRCLE radius`: a full circle centred on the cursor.
+    CIRCLE(Expression),
+    HOME,
+    /// `SETPENRGB red green blue`, each a whole number from 0 to 255.
+    SETPENRGB(Expression, Expression, Expression),
+    SETPENSIZE(Expression),
+    /// `SETBACKGROUND color`, a color index or a `[red green blue]` list.
+    SETBACKGROUND(Expression),
+    /// Starts recording the turtle's path as a polygon to fill.
+    BEGINFILL,
+    /// Fills the polygon recorded since BEGINFILL with the pen color.
+    ENDFILL,
+    /// Saves the turtle's position, heading and pen to restore with POPSTATE.
+    PUSHSTATE,
+    POPSTATE,
+    /// `NEWTURTLE name`: adds a turtle at the home position, for ASK to use.
+    NEWTURTLE(Expression),
+    /// `RERANDOM seed`: restarts RANDOM and PICK from a whole number seed.
+    RERANDOM(Expression),
+}
+
+impl Procedure {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Procedure::PENUP => "PENUP",
+            Procedure::PENDOWN => "PENDOWN",
+            Procedure::FORWARD(_) => "FORWARD",
+            Procedure::BACK(_) => "BACK",
+            Procedure::LEFT(_) => "LEFT",
+            Procedure::RIGHT(_) => "RIGHT",
+            Procedure::SETPENCOLOR(_) => "SETPENCOLOR",
+            Procedure::TURN(_) => "TURN",
+            Procedure::SETHEADING(_) => "SETHEADING",
+            Procedure::SETX(_) => "SETX",
+            Procedure::SETY(_) => "SETY",
+            Procedure::MAKE(_, _) => "MAKE",
+            Procedure::ADDASSIGN(_, _) => "ADDASSIGN",
+            Procedure::ARC(_, _) => "ARC",
+            Procedure::CIRCLE(_) => "CIRCLE",
+            Procedure::HOME => "HOME",
+            Procedure::SETPENRGB(_, _, _) => "SETPENRGB",
+            Procedure::SETPENSIZE(_) => "SETPENSIZE",
+            Procedure::SETBACKGROUND(_) => "SETBACKGROUND",
+            Procedure::BEGINFILL => "BEGINFILL",
+            Procedure::ENDFILL => "ENDFILL",
+            Procedure::PUSHSTATE => "PUSHSTATE",
+            Procedure::POPSTATE => "POPSTATE",
+            Procedure::NEWTURTLE(_) => "NEWTURTLE",
+            Procedure::RERANDOM(_) => "RERANDOM",
+        }
+    }
+
+    /// The expressions passed to the procedure, in order.
+    pub fn arguments(&self) -> Vec<&Expression> {
+        match self {
+            Procedure::PENUP | Procedure::PENDOWN | Procedure::HOME | Procedure::BEGINFILL | Procedure::ENDFILL
+            | Procedure::PUSHSTATE | Procedure::POPSTATE => Vec::new(),
+            Procedure::FORWARD(e) | Procedure::BACK(e) | Procedure::LEFT(e) | Procedure::RIGHT(e)
+            | Procedure::SETPENCOLOR(e) | Procedure::TURN(e) | Procedure::SETHEADING(e) | Procedure::SETX(e)
+            | Procedure::SETY(e) | Procedure::CIRCLE(e) | Procedure::SETPENSIZE(e) | Procedure::SETBACKGROUND(e)
+            | Procedure::NEWTURTLE(e) | Procedure::RERANDOM(e) | Procedure::MAKE(_, e) | Procedure::ADDASSIGN(_, e) => {
+                vec![e]
+            },
+            Procedure::ARC(angle, radius) => vec![angle, radius],
+            Procedure::SETPENRGB(red, green, blue) => vec![red, green, blue],
+        }
+    }
+}
+
+impl fmt::Display for Procedure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.name();
+        match self {
+            Procedure::PENUP | Procedure::PENDOWN | Procedure::HOME | Procedure::BEGINFILL | Procedure::ENDFILL
+            | Procedure::PUSHSTATE | Procedure::POPSTATE => write!(f, "{name}"),
+            Procedure::FORWARD(e) | Procedure::BACK(e) | Procedure::LEFT(e) | Procedure::RIGHT(e)
+            | Procedure::SETPENCOLOR(e) | Procedure::TURN(e) | Procedure::SETHEADING(e) | Procedure::SETX(e)
+            | Procedure::SETY(e) | Procedure::CIRCLE(e) | Procedure::SETPENSIZE(e) | Procedure::SETBACKGROUND(e)
+            | Procedure::NEWTURTLE(e) | Procedure::RERANDOM(e) => {
+                write!(f, "{name} {e}")
+            },
+            Procedure::MAKE(variable, e) | Procedure::ADDASSIGN(variable, e) => write!(f, "{name} \"{variable} {e}"),
+            Procedure::ARC(angle, radius) => write!(f, "{name} {angle} {radius}"),
+            Procedure::SETPENRGB(red, green, blue) => write!(f, "{name} {red} {green} {blue}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Query {
     XCOR,
     YCOR,
     HEADING,
     COLOR,
-    ADDASSIGN(String, f32),
 }
 
-#[derive(Debug)]
+/// Built-in functions that take arguments and produce a value.
+#[derive(Debug, Clone, Copy)]
+pub enum Function {
+    FIRST,
+    BUTFIRST,
+    ITEM,
+    COUNT,
+    FPUT,
+    LPUT,
+    SQRT,
+    SIN,
+    COS,
+    ARCTAN,
+    ABS,
+    INT,
+    RANDOM,
+    /// `PICK list`: an item of the list chosen at random.
+    PICK,
+    NOT,
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Function> {
+        match name {
+            "FIRST" => Some(Function::FIRST),
+            "BUTFIRST" => Some(Function::BUTFIRST),
+            "ITEM" => Some(Function::ITEM),
+            "COUNT" => Some(Function::COUNT),
+            "FPUT" => Some(Function::FPUT),
+            "LPUT" => Some(Function::LPUT),
+            "SQRT" => Some(Function::SQRT),
+            "SIN" => Some(Function::SIN),
+            "COS" => Some(Function::COS),
+            "ARCTAN" => Some(Function::ARCTAN),
+            "ABS" => Some(Function::ABS),
+            "INT" => Some(Function::INT),
+            "RANDOM" => Some(Function::RANDOM),
+            "PICK" => Some(Function::PICK),
+            "NOT" => Some(Function::NOT),
+            _ => None,
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        match self {
+            Function::ITEM | Function::FPUT | Function::LPUT => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum Operator {
+    ADD,
+    SUB,
+    MUL,
+    DIV,
+    MOD,
+    POW,
     EQ,
     NE,
     GT,
     LT,
+    GE,
+    LE,
     AND,
     OR,
 }
 
-pub struct Token {
-    procedure: Option<Procedure>,
-    variable: Option<String>,
-    value: Option<String>,
+#[derive(Debug)]
+pub enum ExpressionKind {
+    /// A quoted literal. Numbers and booleans are resolved when evaluated.
+    Value(String),
+    Variable(String),
+    Query(Query),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+    /// Unary minus, written `-` directly before its operand as in `-:x`.
+    Negate(Box<Expression>),
+    Function(Function, Vec<Expression>),
+    /// A `[ ... ]` list literal. Its items are taken as written rather than
+    /// evaluated, so `:name` inside a list is the word ":name".
+    List(Vec<Expression>),
+}
+
+#[derive(Debug)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+impl Expression {
+    /// The source text of the token this expression starts with.
+    pub fn token(&self) -> String {
+        match &self.kind {
+            ExpressionKind::Value(value) => format!("\"{value}"),
+            ExpressionKind::Variable(name) => format!(":{name}"),
+            ExpressionKind::Query(query) => format!("{query:?}"),
+            ExpressionKind::Binary(operator, _, _) => operator.to_string(),
+            ExpressionKind::Negate(_) => "-".to_string(),
+            ExpressionKind::Function(function, _) => format!("{function:?}"),
+            ExpressionKind::List(_) => "[".to_string(),
+        }
+    }
+}
+
+/// Writes the expression back out in prefix form, as it could be written in
+/// a program.
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExpressionKind::Binary(operator, left, right) => write!(f, "{operator} {left} {right}"),
+            ExpressionKind::Negate(operand) => write!(f, "-{operand}"),
+            ExpressionKind::Function(function, arguments) => {
+                write!(f, "{function:?}")?;
+                for argument in arguments {
+                    write!(f, " {argument}")?;
+                }
+                Ok(())
+            },
+            ExpressionKind::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    match &item.kind {
+                        ExpressionKind::Value(word) => write!(f, "{word}")?,
+                        _ => write!(f, "{item}")?,
+                    }
+                }
+                write!(f, "]")
+            },
+            _ => write!(f, "{}", self.token()),
+        }
+    }
+}
+
+pub type Block = Vec<Statement>;
+
+#[derive(Debug)]
+pub enum StatementKind {
+    Procedure(Procedure),
+    If(Expression, Block),
+    While(Expression, Block),
+    /// `REPEAT count [ ... ]`
+    Repeat(Expression, Block),
+    /// `FOREACH "name list [ ... ]`, binding each item of the list to `name`.
+    Foreach(String, Expression, Block),
+    /// `ASK turtle [ ... ]`, running the block with another turtle.
+    Ask(Expression, Block),
+    LSystem(LSystem),
+    /// A call to a procedure defined with `TO`, with one expression per parameter.
+    Call(String, Vec<Expression>),
+}
+
+impl StatementKind {
+    /// The word the statement starts with.
+    pub fn name(&self) -> &str {
+        match self {
+            StatementKind::Procedure(procedure) => procedure.name(),
+            StatementKind::If(..) => "IF",
+            StatementKind::While(..) => "WHILE",
+            StatementKind::Repeat(..) => "REPEAT",
+            StatementKind::Foreach(..) => "FOREACH",
+            StatementKind::Ask(..) => "ASK",
+            StatementKind::LSystem(_) => "LSYSTEM",
+            StatementKind::Call(name, _) => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+/// `LSYSTEM axiom rules iterations [ symbol [ ... ] ... ]`: rewrites the
+/// axiom with the rules, then draws the result by running the actions given
+/// for each symbol. Nested lists in the axiom and rules are branches.
+#[derive(Debug)]
+pub struct LSystem {
+    pub axiom: Expression,
+    /// A list pairing each symbol with the word or list that replaces it.
+    pub rules: Expression,
+    pub iterations: Expression,
+    pub actions: Vec<LSystemAction>,
+}
+
+/// The built-in procedures run for one symbol of an L-system.
+#[derive(Debug)]
+pub struct LSystemAction {
+    pub symbol: char,
+    pub procedures: Vec<(Procedure, Span)>,
+}
+
+impl fmt::Display for LSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LSYSTEM {} {} {} [", self.axiom, self.rules, self.iterations)?;
+        for (i, action) in self.actions.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{} [", action.symbol)?;
+            for (j, (procedure, _)) in action.procedures.iter().enumerate() {
+                if j > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{procedure}")?;
+            }
+            write!(f, "]")?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// A value a Logo expression can produce.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f32),
+    Bool(bool),
+    Word(String),
+    List(Vec<Value>),
+}
+
+impl Value {
+    /// Interprets a literal word: `TRUE` and `FALSE` are booleans, anything
+    /// that parses as a finite number is a number, and the rest are words.
+    pub fn from_literal(word: &str) -> Value {
+        match word {
+            "TRUE" => Value::Bool(true),
+            "FALSE" => Value::Bool(false),
+            _ => match word.parse::<f32>() {
+                Ok(number) if number.is_finite() => Value::Number(number),
+                _ => Value::Word(word.to_string()),
+            },
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Bool(_) => "boolean",
+            Value::Word(_) => "word",
+            Value::List(_) => "list",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{number}"),
+            Value::Bool(true) => write!(f, "TRUE"),
+            Value::Bool(false) => write!(f, "FALSE"),
+            Value::Word(word) => write!(f, "{word}"),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            },
+        }
+    }
+}
+
+/// A `TO name "param ... END` definition.
+#[derive(Debug)]
+pub struct ProcedureDefinition {
+    pub name: String,
+    pub parameters: Vec<String>,
+    pub body: Block,
+    pub span: Span,
+}
+
+/// A parsed program: its top level statements plus every procedure it defines.
+#[derive(Debug, Default)]
+pub struct Program {
+    pub procedures: HashMap<String, ProcedureDefinition>,
+    pub statements: Block,
+}
+
+impl Program {
+    /// The number of parameters of each procedure defined.
+    pub fn arities(&self) -> HashMap<String, usize> {
+        self.procedures.values().map(|definition| (definition.name.clone(), definition.parameters.len())).collect()
+    }
+}
+
+/// Name of the turtle every program starts with.
+pub const MAIN_TURTLE: &str = "main";
+
+/// Variables and procedures visible while a program runs.
+///
+/// Each procedure call pushes a fresh scope holding its parameters. Lookups
+/// check the innermost scope and then the global one; `MAKE` always binds in
+/// the innermost scope.
+pub struct Environment {
+    scopes: Vec<HashMap<String, Value>>,
+    pub procedures: HashMap<String, Rc<CompiledProcedure>>,
+    /// Procedures written in Rust by a program embedding the interpreter.
+    pub natives: HashMap<String, Rc<NativeProcedure>>,
+    pub tracer: Tracer,
+    /// The program's limits, and how much of them it has used.
+    pub usage: Usage,
+    /// How the coordinates the program sees map onto the canvas.
+    pub axes: Axes,
+    rng: ChaCha8Rng,
+    /// Every turtle other than the one drawing, which is held by the caller.
+    turtles: HashMap<String, Cursor>,
+    /// Name of the turtle drawing.
+    turtle: String,
+}
+
+impl Environment {
+    pub fn new(procedures: HashMap<String, Rc<CompiledProcedure>>) -> Environment {
+        Environment {
+            scopes: vec![HashMap::new()],
+            procedures,
+            natives: HashMap::new(),
+            tracer: Tracer::default(),
+            usage: Usage::default(),
+            axes: Axes::default(),
+            rng: rand::make_rng(),
+            turtles: HashMap::new(),
+            turtle: MAIN_TURTLE.to_string(),
+        }
+    }
+
+    /// The number of arguments taken by every procedure that can be called,
+    /// for parsing more code to run in this environment.
+    pub fn arities(&self) -> HashMap<String, usize> {
+        let natives = self.natives.values().map(|native| (native.name.clone(), native.arity));
+        let procedures = self.procedures.values().map(|procedure| (procedure.name.clone(), procedure.parameters.len()));
+        natives.chain(procedures).collect()
+    }
+
+    /// Makes RANDOM and PICK produce the same sequence on every run with this seed.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+    }
+
+    /// A whole number from 0 up to, but not including, `limit`.
+    pub fn random(&mut self, limit: u32) -> u32 {
+        self.rng.random_range(0..limit)
+    }
+
+    /// Runs `f`, then puts RANDOM and PICK back as they were, so that a
+    /// debugger looking at values does not change what the program draws.
+    pub fn keeping_random<T>(&mut self, f: impl FnOnce(&mut Environment) -> T) -> T {
+        let rng = self.rng.clone();
+        let result = f(self);
+        self.rng = rng;
+        result
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        let local = self.scopes.last().and_then(|scope| scope.get(name));
+        local.or_else(|| self.scopes[0].get(name))
+    }
+
+    pub fn make(&mut self, name: &str, value: Value) {
+        self.tracer.record_change(name, &value);
+        let scope = self.scopes.last_mut().expect("The global scope is never popped");
+        scope.insert(name.to_string(), value);
+    }
+
+    /// Overwrites an existing variable, wherever `get` would find it.
+    /// Returns false if there is no such variable.
+    pub fn assign(&mut self, name: &str, value: Value) -> bool {
+        let depth = self.scopes.len() - 1;
+        let scope = if self.scopes[depth].contains_key(name) { depth } else { 0 };
+        match self.scopes[scope].get_mut(name) {
+            Some(variable) => {
+                self.tracer.record_change(name, &value);
+                *variable = value;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Every variable in the global scope.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        self.scopes[0].iter().map(|(name, value)| (name.clone(), value.clone())).collect()
+    }
+
+    /// Adds a turtle named `name`. Returns false if there already is one.
+    pub fn add_turtle(&mut self, name: &str, turtle: Cursor) -> bool {
+        if name == self.turtle || self.turtles.contains_key(name) {
+            return false;
+        }
+        self.turtles.insert(name.to_string(), turtle);
+        true
+    }
+
+    /// The name of a turtle other than the one drawing that is between
+    /// BEGINFILL and ENDFILL. Canvases keep one fill at a time, so there
+    /// must be none when a fill begins.
+    pub fn filling_turtle(&self) -> Option<&str> {
+        self.turtles.iter().find(|(_, turtle)| turtle.fill.is_some()).map(|(name, _)| name.as_str())
+    }
+
+    pub fn turtle(&self) -> &str {
+        &self.turtle
+    }
+
+    /// Makes `name` the turtle drawing, swapping it into `cursor` and putting
+    /// the one that was there away. Returns false if there is no such turtle.
+    pub fn select_turtle(&mut self, cursor: &mut Cursor, name: &str) -> bool {
+        if name == self.turtle {
+            return true;
+        }
+        let Some(next) = self.turtles.remove(name) else {
+            return false;
+        };
+        let previous = std::mem::replace(cursor, next);
+        let previous_name = std::mem::replace(&mut self.turtle, name.to_string());
+        self.turtles.insert(previous_name, previous);
+        true
+    }
+
+    /// Every variable in the scope of the procedure call running, or none at
+    /// the top level.
+    pub fn locals(&self) -> Vec<(String, Value)> {
+        match self.scopes.len() {
+            1 => Vec::new(),
+            n => self.scopes[n - 1].iter().map(|(name, value)| (name.clone(), value.clone())).collect(),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.scopes.len() - 1
+    }
+
+    pub fn push_scope(&mut self, scope: HashMap<String, Value>) {
+        self.scopes.push(scope);
+    }
+
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
 }
 
 pub struct Cursor {
     pub pen_status: PenStatus,
     pub pen_color: Color,
+    /// Width of the lines drawn, in pixels.
+    pub pen_size: f32,
     pub x_coord: f32,
     pub y_coord: f32,
-    pub direction: i32,
+    /// Heading in degrees, clockwise from straight up, kept within [0, 360).
+    pub direction: f32,
+    /// Where the cursor started, which HOME returns to.
+    pub home: (f32, f32),
+    /// The corners of the polygon being recorded between BEGINFILL and ENDFILL.
+    pub fill: Option<Vec<(f32, f32)>>,
+    /// States saved by PUSHSTATE, most recent last.
+    pub saved: Vec<TurtleState>,
+}
+
+/// What PUSHSTATE saves and POPSTATE restores.
+#[derive(Debug, Clone)]
+pub struct TurtleState {
+    pub position: (f32, f32),
+    pub direction: f32,
+    pub pen_status: PenStatus,
+    pub pen_color: Color,
+    pub pen_size: f32,
 }
 
 impl Cursor {
@@ -50,11 +658,69 @@ impl Cursor {
         Cursor{
             pen_status: PenStatus::PENUP,
             pen_color: Color::white(),
+            pen_size: 1.0,
             x_coord: x,
             y_coord: y,
-            direction: 0,
+            direction: 0.0,
+            home: (x, y),
+            fill: None,
+            saved: Vec::new(),
         }
     }
+
+    pub fn push_state(&mut self) {
+        self.saved.push(TurtleState {
+            position: (self.x_coord, self.y_coord),
+            direction: self.direction,
+            pen_status: self.pen_status,
+            pen_color: self.pen_color,
+            pen_size: self.pen_size,
+        });
+    }
+
+    /// Restores the state last saved, without drawing anything. Returns false
+    /// if nothing is saved.
+    pub fn pop_state(&mut self) -> bool {
+        let Some(state) = self.saved.pop() else {
+            return false;
+        };
+        (self.x_coord, self.y_coord) = state.position;
+        self.direction = state.direction;
+        self.pen_status = state.pen_status;
+        self.pen_color = state.pen_color;
+        self.pen_size = state.pen_size;
+        self.add_fill_point(state.position);
+        true
+    }
+
+    /// Adds a corner to the polygon being filled, if there is one.
+    pub fn add_fill_point(&mut self, point: (f32, f32)) {
+        if let Some(fill) = &mut self.fill {
+            fill.push(point);
+        }
+    }
+
+    pub fn set_heading(&mut self, degrees: f32) {
+        self.direction = normalize_degrees(degrees);
+    }
+
+    pub fn turn(&mut self, degrees: f32) {
+        self.set_heading(self.direction + degrees);
+    }
+
+    /// Where the cursor ends up after moving `length` units towards
+    /// `direction` degrees (clockwise from straight up).
+    ///
+    /// Coordinates are rounded to 1/256 of a unit, as unsvg does, so that
+    /// moves along the axes land exactly and queries like `EQ XCOR "10`
+    /// behave predictably.
+    pub fn destination(&self, direction: f32, length: f32) -> (f32, f32) {
+        let radians = (direction as f64 - 90.0).to_radians();
+        let x = self.x_coord as f64 + radians.cos() * length as f64;
+        let y = self.y_coord as f64 + radians.sin() * length as f64;
+        (quantize(x), quantize(y))
+    }
+
     pub fn penup(&mut self) {
         self.pen_status = PenStatus::PENUP
     }
@@ -64,35 +730,104 @@ impl Cursor {
     }
 
     pub fn isdown(&self) -> bool {
-        return self.pen_status == PenStatus::PENDOWN
+        self.pen_status == PenStatus::PENDOWN
     }
 
     pub fn color_as_f32(&self) -> f32 {
-        let mut i = 0;
-        for color in COLORS {
-            if self.pen_color == color {
-                return i as f32;
-            }
-            i = i + 1;
+        COLORS.iter()
+            .position(|color| *color == self.pen_color)
+            .unwrap_or(COLORS.len()) as f32
+    }
+}
+
+impl Query {
+    pub fn from_name(name: &str) -> Option<Query> {
+        match name {
+            "XCOR" => Some(Query::XCOR),
+            "YCOR" => Some(Query::YCOR),
+            "HEADING" => Some(Query::HEADING),
+            "COLOR" => Some(Query::COLOR),
+            _ => None,
         }
-        return i as f32;
     }
 }
 
-impl Procedure {
-    pub fn name_to_procedure(name: &str) -> Procedure {
+impl Operator {
+    pub fn from_name(name: &str) -> Option<Operator> {
         match name {
-            "XCOR" => Procedure::XCOR,
-            "YCOR" => Procedure::YCOR,
-            "HEADING" => Procedure::HEADING,
-            "COLOR" => Procedure::COLOR,
-            _ => Procedure::PENUP,
+            "+" => Some(Operator::ADD),
+            "-" => Some(Operator::SUB),
+            "*" => Some(Operator::MUL),
+            "/" => Some(Operator::DIV),
+            "%" => Some(Operator::MOD),
+            "^" => Some(Operator::POW),
+            "EQ" | "=" => Some(Operator::EQ),
+            "NE" | "<>" => Some(Operator::NE),
+            "GT" | ">" => Some(Operator::GT),
+            "LT" | "<" => Some(Operator::LT),
+            "GE" | ">=" => Some(Operator::GE),
+            "LE" | "<=" => Some(Operator::LE),
+            "AND" => Some(Operator::AND),
+            "OR" => Some(Operator::OR),
+            _ => None,
+        }
+    }
+
+    /// How tightly the operator binds when written infix. Higher binds tighter.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Operator::OR => 1,
+            Operator::AND => 2,
+            Operator::EQ | Operator::NE | Operator::GT | Operator::LT | Operator::GE | Operator::LE => 3,
+            Operator::ADD | Operator::SUB => 4,
+            Operator::MUL | Operator::DIV | Operator::MOD => 5,
+            Operator::POW => 6,
         }
     }
+
+    /// `^` groups from the right, so `2 ^ 3 ^ 2` is `2 ^ 9`.
+    pub fn is_right_associative(&self) -> bool {
+        matches!(self, Operator::POW)
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Operator::ADD => "+",
+            Operator::SUB => "-",
+            Operator::MUL => "*",
+            Operator::DIV => "/",
+            Operator::MOD => "%",
+            Operator::POW => "^",
+            Operator::EQ => "EQ",
+            Operator::NE => "NE",
+            Operator::GT => "GT",
+            Operator::LT => "LT",
+            Operator::GE => "GE",
+            Operator::LE => "LE",
+            Operator::AND => "AND",
+            Operator::OR => "OR",
+        };
+        write!(f, "{name}")
+    }
 }
 
-#[derive(PartialEq)]
+/// Maps an angle in degrees onto [0, 360).
+pub fn normalize_degrees(degrees: f32) -> f32
+{
+    let normalized = degrees.rem_euclid(360.0);
+    // rem_euclid can round up to exactly 360 for tiny negative inputs
+    if normalized >= 360.0 { 0.0 } else { normalized }
+}
+
+fn quantize(coord: f64) -> f32
+{
+    ((coord * 256.0).round() / 256.0) as f32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PenStatus {
     PENUP,
     PENDOWN
-}
\ No newline at end of file
+}
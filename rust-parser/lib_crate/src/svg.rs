@@ -0,0 +1,212 @@
+use std::fmt::Write;
+use std::path::Path;
+use resvg::usvg::{self, TreeParsing};
+use resvg::tiny_skia;
+use unsvg::Color;
+use crate::canvas::{hex, Canvas};
+
+/// Writes SVG directly, with every procedure call wrapped in a `<g>` named
+/// after the procedure. With `layers` set, each pen color also gets its own
+/// Inkscape layer, so that plotter software can draw one color at a time.
+/// Layers change the stacking order, so they are off by default.
+///
+/// With `png` set, the SVG is rendered to a PNG when saved.
+pub struct SvgCanvas {
+    width: u32,
+    height: u32,
+    background: Color,
+    elements: Vec<Element>,
+    /// Names of the procedure calls currently running, outermost first.
+    groups: Vec<String>,
+    /// Where the fill being recorded goes. Only one turtle fills at a time.
+    fill_start: Option<usize>,
+    pub layers: bool,
+    pub png: bool,
+}
+
+/// One shape, along with the procedure calls that drew it.
+struct Element {
+    color: Color,
+    groups: Vec<String>,
+    markup: String,
+}
+
+impl SvgCanvas {
+    pub fn new(width: u32, height: u32) -> SvgCanvas {
+        SvgCanvas {
+            width,
+            height,
+            background: Color::black(),
+            elements: Vec::new(),
+            groups: Vec::new(),
+            fill_start: None,
+            layers: false,
+            png: false,
+        }
+    }
+
+    pub fn to_svg(&self) -> String {
+        let mut out = String::new();
+        write_header(&mut out, self.width, self.height, self.background);
+
+        if self.layers {
+            let mut colors: Vec<Color> = Vec::new();
+            for element in &self.elements {
+                if !colors.contains(&element.color) {
+                    colors.push(element.color);
+                }
+            }
+            for (index, color) in colors.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    r#"    <g id="layer{}" inkscape:groupmode="layer" inkscape:label="{}">"#,
+                    index + 1, hex(*color),
+                );
+                let elements = self.elements.iter().filter(|element| element.color == *color);
+                write_elements(&mut out, elements, 2);
+                out.push_str("    </g>\n");
+            }
+        }
+        else {
+            write_elements(&mut out, self.elements.iter(), 1);
+        }
+
+        out.push_str("</svg>\n");
+        out
+    }
+
+    fn push(&mut self, color: Color, markup: String) {
+        self.elements.push(Element { color, groups: self.groups.clone(), markup });
+    }
+}
+
+/// Opens an `<svg>` element and fills it with the background color.
+pub fn write_header(out: &mut String, width: u32, height: u32, background: Color)
+{
+    let _ = writeln!(
+        out,
+        r#"<svg width="{0}" height="{1}" viewBox="0 0 {0} {1}" xmlns="http://www.w3.org/2000/svg" xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape">"#,
+        width, height,
+    );
+    let _ = writeln!(out, r#"    <rect width="{width}" height="{height}" fill="{}"/>"#, hex(background));
+}
+
+/// The `<path>` tag for a line, left open so that children can be added.
+pub fn line_tag(start: (f32, f32), end: (f32, f32), color: Color, width: f32) -> String
+{
+    let mut tag = format!(
+        r#"<path d="M {} {} L {} {}" fill="none" stroke="{}""#,
+        start.0, start.1, end.0, end.1, hex(color),
+    );
+    // Round ends join the segments of thick lines without gaps
+    if width != 1.0 {
+        let _ = write!(tag, r#" stroke-width="{width}" stroke-linecap="round""#);
+    }
+    tag
+}
+
+/// The `<path>` tag for a filled polygon, left open like `line_tag`.
+pub fn fill_tag(points: &[(f32, f32)], color: Color) -> String
+{
+    let mut path = String::new();
+    for (index, (x, y)) in points.iter().enumerate() {
+        let command = if index == 0 { "M" } else { " L" };
+        let _ = write!(path, "{command} {x} {y}");
+    }
+    format!(r#"<path d="{path} Z" fill="{}" stroke="none""#, hex(color))
+}
+
+/// Renders a whole SVG document to an image.
+pub fn render_png(svg: &str) -> Result<tiny_skia::Pixmap, String>
+{
+    let tree = usvg::Tree::from_str(svg, &usvg::Options::default()).map_err(|e| e.to_string())?;
+    let tree = resvg::Tree::from_usvg(&tree);
+    let size = tree.size.to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height()).ok_or("Could not create image")?;
+    tree.render(tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    Ok(pixmap)
+}
+
+/// Writes each element, opening and closing `<g>` tags as the procedure
+/// calls that drew them change.
+fn write_elements<'a>(out: &mut String, elements: impl Iterator<Item = &'a Element>, depth: usize)
+{
+    let mut open: &[String] = &[];
+    for element in elements {
+        let shared = open.iter().zip(&element.groups).take_while(|(a, b)| a == b).count();
+        for level in (shared..open.len()).rev() {
+            let _ = writeln!(out, "{}</g>", indent(depth + level));
+        }
+        for (level, name) in element.groups.iter().enumerate().skip(shared) {
+            let _ = writeln!(out, r#"{}<g class="{}">"#, indent(depth + level), escape(name));
+        }
+        let _ = writeln!(out, "{}{}", indent(depth + element.groups.len()), element.markup);
+        open = &element.groups;
+    }
+    for level in (0..open.len()).rev() {
+        let _ = writeln!(out, "{}</g>", indent(depth + level));
+    }
+}
+
+/// Procedure names can contain any character other than whitespace and brackets.
+fn escape(text: &str) -> String
+{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn indent(depth: usize) -> String
+{
+    "    ".repeat(depth)
+}
+
+impl Canvas for SvgCanvas {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn set_background(&mut self, color: Color) {
+        self.background = color;
+    }
+
+    fn line(&mut self, start: (f32, f32), end: (f32, f32), color: Color, width: f32) -> Result<(), String> {
+        let markup = line_tag(start, end, color, width) + "/>";
+        self.push(color, markup);
+        Ok(())
+    }
+
+    fn begin_fill(&mut self) {
+        self.fill_start = Some(self.elements.len());
+    }
+
+    fn end_fill(&mut self, points: &[(f32, f32)], color: Color) -> Result<(), String> {
+        let start = self.fill_start.take().unwrap_or(self.elements.len());
+        // Fewer than three corners encloses nothing, so there is nothing to draw
+        if points.len() < 3 {
+            return Ok(());
+        }
+        let markup = fill_tag(points, color) + "/>";
+        self.elements.insert(start, Element { color, groups: self.groups.clone(), markup });
+        Ok(())
+    }
+
+    fn begin_group(&mut self, name: &str) {
+        self.groups.push(name.to_string());
+    }
+
+    fn end_group(&mut self) {
+        self.groups.pop();
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        if self.png {
+            render_png(&self.to_svg())?.save_png(path).map_err(|e| e.to_string())
+        }
+        else {
+            std::fs::write(path, self.to_svg()).map_err(|e| e.to_string())
+        }
+    }
+
+    fn to_text(&self) -> Option<String> {
+        (!self.png).then(|| self.to_svg())
+    }
+}
@@ -0,0 +1,60 @@
+//! Runs whole programs for the unit tests.
+
+use crate::canvas::{create_canvas, Canvas, Format};
+use crate::errors::LogoError;
+use crate::structs::{Cursor, Environment};
+use crate::{bytecode, lexer, parser, vm};
+
+/// What is left once a program has run.
+pub struct Run {
+    pub canvas: Box<dyn Canvas>,
+    pub cursor: Cursor,
+    pub env: Environment,
+}
+
+impl Run {
+    /// The `d` of each path drawn, in order.
+    pub fn paths(&self) -> Vec<String> {
+        let svg = self.canvas.to_text().expect("SVG canvases are text");
+        svg.split("<path d=\"").skip(1)
+            .map(|path| path[..path.find('"').expect("Attributes are quoted")].to_string())
+            .collect()
+    }
+}
+
+/// Runs `source` on a 200 by 200 SVG canvas, or returns the error it stops with.
+pub fn try_run(source: &str) -> Result<Run, LogoError>
+{
+    try_run_with(source, |_| {})
+}
+
+/// Runs `source`, which should run to the end.
+pub fn run(source: &str) -> Run
+{
+    try_run(source).unwrap_or_else(|err| panic!("{source:?} failed: {err}"))
+}
+
+/// Runs `source` with RANDOM seeded with `seed`.
+pub fn run_seeded(source: &str, seed: u64) -> Run
+{
+    try_run_with(source, |env| env.seed_random(seed)).unwrap_or_else(|err| panic!("{source:?} failed: {err}"))
+}
+
+/// What running `source` fails with.
+pub fn fail(source: &str) -> LogoError
+{
+    try_run(source).err().unwrap_or_else(|| panic!("{source:?} should fail"))
+}
+
+/// Runs `source` once `setup` has had a chance to change the environment,
+/// e.g. to set limits.
+pub fn try_run_with(source: &str, setup: impl FnOnce(&mut Environment)) -> Result<Run, LogoError>
+{
+    let program = bytecode::compile(parser::parse(&lexer::tokenize(source))?);
+    let mut canvas = create_canvas(Format::Svg, 200, 200, false);
+    let mut cursor = Cursor::new(100.0, 100.0);
+    let mut env = Environment::new(program.procedures);
+    setup(&mut env);
+    vm::execute(&program.main, canvas.as_mut(), &mut cursor, &mut env)?;
+    Ok(Run { canvas, cursor, env })
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+use std::str::FromStr;
+use serde_json::{json, Map, Value};
+use crate::structs::{Cursor, Span, Value as LogoValue};
+
+/// How much internal detail to log, from least to most verbose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!("unknown log level '{s}', expected error, warn, info, debug or trace")),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TraceFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(TraceFormat::Text),
+            "json" => Ok(TraceFormat::Json),
+            _ => Err(format!("unknown trace format '{s}', expected text or json")),
+        }
+    }
+}
+
+/// Writes log messages and per-statement execution events to stderr.
+///
+/// Log messages are filtered by `level`. Statement events are only written
+/// when `statements` is set, one per executed statement, listing the cursor
+/// state afterwards and any variables the statement changed.
+pub struct Tracer {
+    pub level: Level,
+    pub format: TraceFormat,
+    pub statements: bool,
+    changes: Vec<(String, LogoValue)>,
+}
+
+impl Default for Tracer {
+    fn default() -> Tracer {
+        Tracer::new(Level::Warn, TraceFormat::Text, false)
+    }
+}
+
+impl Tracer {
+    pub fn new(level: Level, format: TraceFormat, statements: bool) -> Tracer {
+        Tracer { level, format, statements, changes: Vec::new() }
+    }
+
+    pub fn enabled(&self, level: Level) -> bool {
+        level <= self.level
+    }
+
+    pub fn log(&self, level: Level, message: fmt::Arguments) {
+        if !self.enabled(level) {
+            return;
+        }
+        match self.format {
+            TraceFormat::Text => eprintln!("[{level}] {message}"),
+            TraceFormat::Json => {
+                eprintln!("{}", json!({ "level": level.to_string(), "message": message.to_string() }));
+            },
+        }
+    }
+
+    /// Notes a variable assignment for the next statement event.
+    pub fn record_change(&mut self, name: &str, value: &LogoValue) {
+        if self.statements {
+            self.changes.push((name.to_string(), value.clone()));
+        }
+    }
+
+    /// Emits the event for a statement that just ran. `outcome` holds the
+    /// result of an IF or WHILE condition.
+    pub fn statement(&mut self, name: &str, span: Span, depth: usize, cursor: &Cursor, outcome: Option<bool>) {
+        if !self.statements {
+            return;
+        }
+        let changes = std::mem::take(&mut self.changes);
+        let pen = if cursor.isdown() { "down" } else { "up" };
+        match self.format {
+            TraceFormat::Text => {
+                let mut line = format!(
+                    "[exec] line {}: {name} x={} y={} heading={} pen={pen} color={}",
+                    span.line, cursor.x_coord, cursor.y_coord, cursor.direction, cursor.color_as_f32(),
+                );
+                if let Some(outcome) = outcome {
+                    line.push_str(&format!(" condition={outcome}"));
+                }
+                for (name, value) in &changes {
+                    line.push_str(&format!(" :{name}={value}"));
+                }
+                eprintln!("{line}");
+            },
+            TraceFormat::Json => {
+                let changes: Map<String, Value> = changes.into_iter()
+                    .map(|(name, value)| (name, to_json(&value)))
+                    .collect();
+                let mut event = json!({
+                    "line": span.line,
+                    "column": span.column,
+                    "statement": name,
+                    "depth": depth,
+                    "cursor": {
+                        "x": cursor.x_coord,
+                        "y": cursor.y_coord,
+                        "heading": cursor.direction,
+                        "pen": pen,
+                        "color": cursor.color_as_f32(),
+                    },
+                    "changes": changes,
+                });
+                if let Some(outcome) = outcome {
+                    event["condition"] = json!(outcome);
+                }
+                eprintln!("{event}");
+            },
+        }
+    }
+}
+
+fn to_json(value: &LogoValue) -> Value
+{
+    match value {
+        LogoValue::Number(number) => json!(number),
+        LogoValue::Bool(bool) => json!(bool),
+        LogoValue::Word(word) => json!(word),
+        LogoValue::List(items) => Value::Array(items.iter().map(to_json).collect()),
+    }
+}
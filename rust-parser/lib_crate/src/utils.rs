@@ -1,525 +1,676 @@
-use std::collections::HashMap;
-use std::hash::Hash;
-use unsvg::{Image, get_end_coordinates, COLORS};
-use crate::structs::{Cursor, Procedure, Operator};
+use unsvg::{Color, COLORS};
+use crate::canvas::{Axes, Canvas};
+use crate::errors::{ErrorKind, LogoError};
+use crate::structs::{Cursor, Environment, Procedure, Operator, Expression, ExpressionKind, Function, Query, Span, Value};
+use crate::trace::Level;
 
-pub fn handle_line(line: &str, image: &mut Image, cursor: &mut Cursor, variables: &mut HashMap<String, f32>) -> Result<usize, String>
+pub fn check_condition(condition: &Expression, cursor: &Cursor, env: &mut Environment) -> Result<bool, LogoError>
 {
-    if line.starts_with("//") {
-        println!("Skipping... comment");
-        return Ok(0);
-    }
-
-    // TOKEN PROCESSING
-    let tokens: Vec<& str> = line.split_whitespace().collect();
-    let mut iter = tokens.iter().peekable();
-    let mut advance_by = 0usize;
-    while let Some(token) = iter.nth(advance_by) {
-        println!("Processing token {token}");
-        match *token {
-            "PENUP" => {
-                execute_procedure(image, Procedure::PENUP, cursor, variables);
-            },
-            "PENDOWN" => {
-                execute_procedure(image, Procedure::PENDOWN, cursor, variables);
-            },
-            "FORWARD" | "BACK" | "LEFT" | "RIGHT" | "SETPENCOLOR" |
-            "TURN" | "SETHEADING" | "SETX" | "SETY" => {
-                if let Some(maybe_value) = iter.next() {
-                    let value = match get_value(maybe_value, &tokens, cursor, variables) {
-                        Ok((value, adv)) => {
-                            advance_by = adv;
-                            let procedure = parse_procedure(token, None, value)
-                                .expect("Should be a valid command");
-                            execute_procedure(image, procedure, cursor, variables);
-                        },
-                        Err(err) => return Err(err),
-                    };
-                }
-                else {
-                    return Err("Not enough args!".to_string());
-                }
-            },
-            "MAKE" | "ADDASSIGN" => {
-                if let Some(name) = iter.next() {
-                    if name.starts_with('"') {
-                        if let Ok(stripped_name) = name.trim_matches('"').parse::<String>() {
-                            // MATH PARSING
-                            match *(iter.next().expect("Argument expected!")) {
-                                "EQ" | "NE" | "GT" | "LT" | "AND" | "OR" => {
-
-                                    // STRIP OF MAKE/ADDASIGN COMPARISON
-                                    let mut stripped_line = line.clone().strip_prefix(token).expect("");
-                                    stripped_line = stripped_line.strip_prefix(" ").expect("");
-                                    stripped_line = stripped_line.strip_prefix(name).expect("");
-
-                                    match check_condition(stripped_line, cursor, variables) {
-                                        Ok((true, adv)) => {
-                                            advance_by = adv;
-                                            let procedure = parse_procedure(token, Some(stripped_name), 1.0)
-                                                .expect("Should be a valid command");
-                                            execute_procedure(image, procedure, cursor, variables);
-                                            break;
-                                        }
-                                        Ok((false, adv)) => {
-                                            advance_by = adv;
-                                            let procedure = parse_procedure(token, Some(stripped_name), 0.0)
-                                                .expect("Should be a valid command");
-                                            execute_procedure(image, procedure, cursor, variables);
-                                            break;
-                                        }
-                                        Err(err) => return Err(err)
-                                    };
-                                },
-                                maybe_value => {
-                                    match get_value(maybe_value, &tokens, cursor, variables) {
-                                        Ok((value, adv)) => {
-                                            advance_by = adv;
-                                            let procedure = parse_procedure(token, Some(stripped_name), value)
-                                                .expect("Should be a valid command");
-                                            execute_procedure(image, procedure, cursor, variables);
-                                        },
-                                        Err(err) => return Err(err),
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                else {
-                    return Err("Expected arg!".to_string())
-                };
-            }
-            value => {
-                if value.starts_with('"') {
-                    value.trim_matches('"').parse::<f32>();
-                    return Err("Too many args!".to_string());
-                }
-                else {
-                    return Err("Not implemented yet!".to_string());
-                }
-            }
-        }
-    }
-    Ok(0)
+    let outcome = evaluate_bool(condition, cursor, env)?;
+    env.tracer.log(Level::Debug, format_args!("Condition {} on line {} is {outcome}", condition.token(), condition.span.line));
+    Ok(outcome)
 }
 
-pub fn check_condition(line: &str, cursor: &mut Cursor, variables: &mut HashMap<String, f32>) -> Result<(bool, usize), String>
+pub fn evaluate(expression: &Expression, cursor: &Cursor, env: &mut Environment) -> Result<Value, LogoError>
 {
-    let mut tokens: Vec<& str> = line.split_whitespace().collect();
-
-    println!("Checking condition!");
-
-    // Remove trailing {
-    match tokens.pop() {
-        Some("]") => {},
-        Some(last) => tokens.push(last),
-        None => {},
-    };
-
-    match tokens[0] {
-        "EQ" | "NE" | "GT" | "LT" | "AND" | "OR" => {
-            let operator = parse_operator(tokens[0]).unwrap();
-            let operands = get_operands(&tokens, 1usize, cursor, variables).unwrap();
-            match compare(operator, (operands.0, operands.1)).unwrap() {
-                0.0 => Ok((false, operands.2)),
-                1.0 => Ok((true, operands.2)),
-                _ => return Err("Failed to evalatue condition!".to_string())
+    match &expression.kind {
+        ExpressionKind::Value(value) => Ok(Value::from_literal(value)),
+        ExpressionKind::Variable(name) => {
+            match env.get(name) {
+                Some(value) => Ok(value.clone()),
+                None => Err(LogoError::new(ErrorKind::UnknownVariable, expression.span, expression.token())),
             }
         },
-        _ => {
-            match get_value(tokens[0], &tokens, cursor, variables) {
-                Ok((1.0, adv))=> Ok((true, adv)),
-                Ok((0.0, adv))=> Ok((false, adv)),
-                _ => return Err("Not a valid boolean!".to_string())
-            }
+        ExpressionKind::Query(query) => Ok(Value::Number(get_query(*query, cursor, &env.axes))),
+        ExpressionKind::Binary(operator, left, right) => {
+            apply_operator(*operator, left, right, expression, cursor, env)
+        },
+        ExpressionKind::Negate(operand) => Ok(Value::Number(-evaluate_number(operand, cursor, env)?)),
+        ExpressionKind::Function(function, arguments) => {
+            apply_function(*function, arguments, expression, cursor, env)
         },
+        ExpressionKind::List(_) => Ok(list_literal(expression)),
     }
-
 }
 
-pub fn jump_to_matching_bracket(mut line_number: usize, lines: &Vec<String>) -> Result<usize, String>
+pub fn evaluate_number(expression: &Expression, cursor: &Cursor, env: &mut Environment) -> Result<f32, LogoError>
 {
-    let mut condition_count = 1;
-    while condition_count != 0 && line_number < lines.len() {
-        let line = lines[line_number].trim();
-        if line.ends_with("[") {
-            condition_count = condition_count + 1
-        }
-        else if line.starts_with("]") {
-            condition_count = condition_count - 1;
-        }
-        println!("Condition count: {condition_count}");
-        println!("line_number: {line_number}");
-        println!("line: {line}");
-
-        line_number = line_number + 1;
+    match evaluate(expression, cursor, env)? {
+        Value::Number(number) => Ok(number),
+        other => Err(type_mismatch("number", &other, expression)),
     }
-    if condition_count > 0 {
-        return Err("No matching bracket found!".to_string());
+}
+
+pub fn evaluate_bool(expression: &Expression, cursor: &Cursor, env: &mut Environment) -> Result<bool, LogoError>
+{
+    match evaluate(expression, cursor, env)? {
+        Value::Bool(bool) => Ok(bool),
+        other => Err(type_mismatch("boolean", &other, expression)),
     }
-    Ok(line_number)
 }
 
-fn parse_procedure(token: &str, name: Option<String>, value: f32) -> Option<Procedure>
+pub fn evaluate_list(expression: &Expression, cursor: &Cursor, env: &mut Environment) -> Result<Vec<Value>, LogoError>
 {
-    match token {
-        "FORWARD" => Some(Procedure::FORWARD(value)),
-        "BACK" => Some(Procedure::BACK(value)),
-        "LEFT" => Some(Procedure::LEFT(value)),
-        "RIGHT" => Some(Procedure::RIGHT(value)),
-        "SETPENCOLOR" => Some(Procedure::SETPENCOLOR(value)),
-        "TURN" => Some(Procedure::TURN(value)),
-        "SETHEADING" => Some(Procedure::SETHEADING(value)),
-        "SETX" => Some(Procedure::SETX(value)),
-        "SETY" => Some(Procedure::SETY(value)),
-        "MAKE" => Some(Procedure::MAKE(name.unwrap(), value)),
-        "XCOR" => Some(Procedure::XCOR),
-        "YCOR" => Some(Procedure::YCOR),
-        "HEADING" => Some(Procedure::HEADING),
-        "COLOR" => Some(Procedure::COLOR),
-        "ADDASSIGN" => Some(Procedure::ADDASSIGN(name.unwrap(), value)),
-        _ => None
+    match evaluate(expression, cursor, env)? {
+        Value::List(items) => Ok(items),
+        other => Err(type_mismatch("list", &other, expression)),
     }
 }
 
-fn parse_operator(token: &str) -> Option<Operator>
+/// Evaluates the name of a turtle, which may be a word or a number.
+pub fn evaluate_name(expression: &Expression, cursor: &Cursor, env: &mut Environment) -> Result<String, LogoError>
 {
-    match token {
-        "EQ" => Some(Operator::EQ),
-        "NE" => Some(Operator::NE),
-        "GT" => Some(Operator::GT),
-        "LT" => Some(Operator::LT),
-        "AND" => Some(Operator::AND),
-        "OR" => Some(Operator::OR),
-        _ => None,
+    match evaluate(expression, cursor, env)? {
+        value @ (Value::Word(_) | Value::Number(_)) => Ok(value.to_string()),
+        other => Err(type_mismatch("word", &other, expression)),
     }
+}
 
+fn type_mismatch(expected: &'static str, found: &Value, expression: &Expression) -> LogoError
+{
+    let kind = ErrorKind::TypeMismatch { expected, found: found.type_name() };
+    LogoError::new(kind, expression.span, expression.token())
 }
 
-fn execute_procedure(image: &mut Image, procedure: Procedure, cursor: &mut Cursor, variables: &mut HashMap<String, f32>) -> Result<(), String>
+/// List literals are data, so their words are taken as written rather than
+/// looked up or evaluated.
+fn list_literal(expression: &Expression) -> Value
 {
-    println!("Procedure is {:?}", procedure);
+    match &expression.kind {
+        ExpressionKind::List(items) => Value::List(items.iter().map(list_literal).collect()),
+        ExpressionKind::Value(word) => Value::from_literal(word),
+        _ => Value::Word(expression.token()),
+    }
+}
+
+/// Runs one built-in procedure such as FORWARD or MAKE.
+pub fn execute_procedure(canvas: &mut dyn Canvas, procedure: &Procedure, span: Span, cursor: &mut Cursor, env: &mut Environment) -> Result<(), LogoError>
+{
+    env.tracer.log(Level::Debug, format_args!("Executing {} on line {}", procedure.name(), span.line));
     match procedure {
         Procedure::PENUP => {
             cursor.penup();
         },
         Procedure::PENDOWN => {
-            println!("Putting pen down");
             cursor.pendown();
         },
         Procedure::FORWARD(value) => {
-            move_cursor(image, cursor, cursor.direction, value);
+            let value = evaluate_number(value, cursor, env)?;
+            move_cursor(canvas, env, cursor, cursor.direction, value, span)?;
         },
         Procedure::BACK(value) => {
-            move_cursor(image, cursor, cursor.direction + 180, value);
+            let value = evaluate_number(value, cursor, env)?;
+            move_cursor(canvas, env, cursor, cursor.direction + 180.0, value, span)?;
         },
         Procedure::LEFT(value) => {
-            move_cursor(image, cursor, cursor.direction + 270, value);
+            let value = evaluate_number(value, cursor, env)?;
+            move_cursor(canvas, env, cursor, cursor.direction + 270.0, value, span)?;
         },
         Procedure::RIGHT(value) => {
-            move_cursor(image, cursor, cursor.direction + 90, value);
+            let value = evaluate_number(value, cursor, env)?;
+            move_cursor(canvas, env, cursor, cursor.direction + 90.0, value, span)?;
         },
-        Procedure::SETPENCOLOR(value) => {
+        Procedure::SETPENCOLOR(expression) => {
+            let value = evaluate_number(expression, cursor, env)?;
             // Error if not integer or between 0 and 15
-            if value.fract() != 0.0 || value < 0.0 || value > 15.0 {
-                return Err("Pen Color not valid".to_string());
+            if value.fract() != 0.0 || !(0.0..=15.0).contains(&value) {
+                return Err(LogoError::new(ErrorKind::InvalidPenColor, expression.span, expression.token()));
             }
             cursor.pen_color = COLORS[value as usize];
         },
-        Procedure::TURN(value) => {
-            if value.fract() != 0.0 {
-                return Err("Turn Value must be i32".to_string());
+        Procedure::SETPENRGB(red, green, blue) => {
+            cursor.pen_color = Color {
+                red: evaluate_channel(red, cursor, env)?,
+                green: evaluate_channel(green, cursor, env)?,
+                blue: evaluate_channel(blue, cursor, env)?,
+            };
+        },
+        Procedure::SETPENSIZE(expression) => {
+            let size = evaluate_number(expression, cursor, env)?;
+            if size <= 0.0 {
+                return Err(LogoError::new(ErrorKind::InvalidPenSize, expression.span, expression.token()));
+            }
+            if size > env.usage.limits.max_coordinate {
+                return Err(LogoError::new(ErrorKind::PenSizeTooLarge, expression.span, expression.token()));
             }
-            cursor.direction += value as i32;
+            cursor.pen_size = size;
         },
-        Procedure::SETHEADING(value) => {
-            if value.fract() != 0.0 {
-                return Err("Set Heading Value must be i32".to_string());
+        Procedure::SETBACKGROUND(expression) => {
+            canvas.set_background(evaluate_color(expression, cursor, env)?);
+        },
+        Procedure::BEGINFILL => {
+            if cursor.fill.is_some() {
+                return Err(LogoError::new(ErrorKind::FillAlreadyStarted, span, "BEGINFILL"));
+            }
+            if let Some(other) = env.filling_turtle() {
+                return Err(LogoError::new(ErrorKind::FillByAnotherTurtle(other.to_string()), span, "BEGINFILL"));
             }
-            cursor.direction = value as i32;
+            cursor.fill = Some(vec![(cursor.x_coord, cursor.y_coord)]);
+            canvas.begin_fill();
+        },
+        Procedure::ENDFILL => {
+            let points = match cursor.fill.take() {
+                Some(points) => points,
+                None => return Err(LogoError::new(ErrorKind::EndFillWithoutBeginFill, span, "ENDFILL")),
+            };
+            canvas.end_fill(&points, cursor.pen_color)
+                .map_err(|err| LogoError::new(ErrorKind::Draw(err), span, ""))?;
+        },
+        Procedure::TURN(value) => {
+            let value = evaluate_number(value, cursor, env)?;
+            cursor.turn(value);
+        },
+        Procedure::SETHEADING(value) => {
+            let value = evaluate_number(value, cursor, env)?;
+            cursor.set_heading(value);
         },
         Procedure::SETX(value) => {
-            cursor.x_coord = value;
+            let x = evaluate_number(value, cursor, env)?;
+            cursor.x_coord = env.axes.x_to_canvas(x);
+            cursor.add_fill_point((cursor.x_coord, cursor.y_coord));
         },
         Procedure::SETY(value) => {
-            cursor.y_coord = value;
-        },
-        Procedure::ADDASSIGN(name, value) => {
-            println!("Updating variables...");
-            println!("Adding {value} to {name}");
-            match variables.get_mut(&name) {
-                Some(val) => {
-                    println!("old {val}");
-                    *val = *val + value;
-                    println!("new {val}");
+            let y = evaluate_number(value, cursor, env)?;
+            cursor.y_coord = env.axes.y_to_canvas(y);
+            cursor.add_fill_point((cursor.x_coord, cursor.y_coord));
+        },
+        Procedure::ADDASSIGN(name, expression) => {
+            let current = match env.get(name) {
+                Some(Value::Number(number)) => *number,
+                Some(other) => {
+                    let kind = ErrorKind::TypeMismatch { expected: "number", found: other.type_name() };
+                    return Err(LogoError::new(kind, span, format!("\"{name}")));
                 },
-                None => {
-                    return Err("Variable does not exist".to_string());
-                }
-            }
+                None => return Err(LogoError::new(ErrorKind::UnknownVariable, span, format!("\"{name}"))),
+            };
+            let value = evaluate_number(expression, cursor, env)?;
+            env.assign(name, Value::Number(current + value));
         },
         Procedure::MAKE(name, value) => {
-            println!("Updating variables...");
-            println!("Adding {name}, {value}");
-            variables.insert(name, value);
-        }
-        _ => { todo!()}
+            let value = evaluate(value, cursor, env)?;
+            env.make(name, value);
+        },
+        Procedure::ARC(angle, radius) => {
+            let angle = evaluate_number(angle, cursor, env)?;
+            let radius = evaluate_number(radius, cursor, env)?;
+            draw_arc(canvas, env, cursor, angle, radius, span)?;
+        },
+        Procedure::CIRCLE(radius) => {
+            let radius = evaluate_number(radius, cursor, env)?;
+            draw_arc(canvas, env, cursor, 360.0, radius, span)?;
+        },
+        Procedure::HOME => {
+            let (x, y) = cursor.home;
+            move_to(canvas, env, cursor, (x, y), span)?;
+            cursor.set_heading(0.0);
+        },
+        Procedure::PUSHSTATE => cursor.push_state(),
+        Procedure::POPSTATE => {
+            if !cursor.pop_state() {
+                return Err(LogoError::new(ErrorKind::PopStateWithoutPushState, span, "POPSTATE"));
+            }
+        },
+        Procedure::NEWTURTLE(expression) => {
+            let name = evaluate_name(expression, cursor, env)?;
+            let (x, y) = cursor.home;
+            if !env.add_turtle(&name, Cursor::new(x, y)) {
+                return Err(LogoError::new(ErrorKind::DuplicateTurtle, expression.span, expression.token()));
+            }
+        },
+        Procedure::RERANDOM(expression) => {
+            let seed = evaluate_number(expression, cursor, env)?;
+            if seed.fract() != 0.0 || !(0.0..=u32::MAX as f32).contains(&seed) {
+                return Err(LogoError::new(ErrorKind::InvalidArgument, expression.span, expression.token()));
+            }
+            env.seed_random(seed as u64);
+        },
     };
     Ok(())
 }
 
-fn move_cursor(image: &mut Image, cursor: &mut Cursor, direction: i32, length: f32)
+fn move_cursor(canvas: &mut dyn Canvas, env: &mut Environment, cursor: &mut Cursor, direction: f32, length: f32, span: Span) -> Result<(), LogoError>
 {
-    println!("In move cursor, cursor down {}", cursor.isdown());
+    let end = cursor.destination(direction, length);
+    move_to(canvas, env, cursor, end, span)
+}
+
+/// Moves the cursor in a straight line to `end`, drawing a line if the pen is down.
+pub fn move_to(canvas: &mut dyn Canvas, env: &mut Environment, cursor: &mut Cursor, end: (f32, f32), span: Span) -> Result<(), LogoError>
+{
+    let start = (cursor.x_coord, cursor.y_coord);
     if cursor.isdown() {
-        println!("Drawing!");
-        image.draw_simple_line(cursor.x_coord, cursor.y_coord, direction, length, cursor.pen_color);
+        draw_line(canvas, env, cursor, start, end, span)?;
+    }
+    else {
+        env.usage.check_bounds(&[start, end], span)?;
     }
-    let coords = get_end_coordinates(cursor.x_coord, cursor.y_coord, direction, length);
-    cursor.x_coord = coords.0;
-    cursor.y_coord = coords.1;
+    cursor.add_fill_point(end);
+    cursor.x_coord = end.0;
+    cursor.y_coord = end.1;
+    Ok(())
 }
 
-fn get_value(token: &str, tokens: &Vec<&str>, cursor: &mut Cursor, variables: &mut HashMap<String, f32>) -> Result<(f32, usize), String>
+/// Draws an arc of `angle` degrees around the cursor, clockwise from its
+/// heading, as a run of short lines. The cursor itself does not move, but
+/// the arc still counts towards a fill in progress.
+fn draw_arc(canvas: &mut dyn Canvas, env: &mut Environment, cursor: &mut Cursor, angle: f32, radius: f32, span: Span) -> Result<(), LogoError>
 {
-    // VALUE CASE
-    if token.starts_with('"')
-    {
-        let trimmed_token = token.trim_matches('"');
-        match get_bool_as_f32(trimmed_token) {
-            Some(bool) => Ok((bool, 0usize)),
-            None => {
-                match trimmed_token.parse::<f32>(){
-                    Ok(value) => Ok((value, 0usize)),
-                    Err(_) => return Err("Invalid value!".to_string()),
-                }
-            }
-        }
+    if !cursor.isdown() && cursor.fill.is_none() {
+        return Ok(());
     }
-
-    // VARIABLE CASE
-    else if token.starts_with(':')
-    {
-        let name = token.trim_matches(':').to_string();
-        match variables.get(&name) {
-            Some(value) => Ok((*value, 0usize)),
-            None => return Err("No matching variable found".to_string()),
+    // One line for every 5 degrees keeps circles smooth at typical sizes
+    let steps = ((angle.abs() / 5.0).ceil() as usize).clamp(1, 72 * 100);
+    let step = angle / steps as f32;
+    let mut previous = cursor.destination(cursor.direction, radius);
+    cursor.add_fill_point(previous);
+    for i in 1..=steps {
+        let next = cursor.destination(cursor.direction + step * i as f32, radius);
+        if cursor.isdown() {
+            draw_line(canvas, env, cursor, previous, next, span)?;
         }
-    }
-
-    //  PREFIX OR QUERY CASE
-    else
-    {
-        match token {
-            "+" | "-" | "*" | "/" => {
-                let position = tokens.iter().position(|&x| x == token)
-                    .expect("This should work");
-                let res = match process_prefix(&tokens, position, cursor, variables) {
-                    Ok(res) => res,
-                    Err(err) => return Err(err),
-                };
-                Ok((res.0, res.1))
-            }
-            _ => {
-                match get_query(token, cursor) {
-                    Some(value) => Ok((value, 0usize)),
-                    None => return Err("Value not found!".to_string())
-                }
-            }
+        else {
+            env.usage.check_bounds(&[previous, next], span)?;
         }
+        cursor.add_fill_point(next);
+        previous = next;
     }
+    Ok(())
 }
 
-fn get_query(query: &str, cursor: &mut Cursor) -> Option<f32>
+/// Draws a line between two points in the cursor's pen color and size.
+fn draw_line(canvas: &mut dyn Canvas, env: &mut Environment, cursor: &Cursor, start: (f32, f32), end: (f32, f32), span: Span) -> Result<(), LogoError>
 {
-    match query {
-        "XCOR" => Some(cursor.x_coord),
-        "YCOR" => Some(cursor.y_coord),
-        "HEADING" => Some(cursor.direction as f32),
-        "COLOR" => Some(cursor.color_as_f32()),
-        _ => None,
-    }
+    env.usage.check_bounds(&[start, end], span)?;
+    env.usage.segment(span)?;
+
+    env.tracer.log(Level::Trace, format_args!("Drawing from ({}, {}) to ({}, {})", start.0, start.1, end.0, end.1));
+    canvas.line(start, end, cursor.pen_color, cursor.pen_size)
+        .map_err(|err| LogoError::new(ErrorKind::Draw(err), span, ""))
 }
 
-fn get_bool_as_f32(value: &str) -> Option<f32>
+/// Evaluates one red, green or blue component, a whole number from 0 to 255.
+fn evaluate_channel(expression: &Expression, cursor: &Cursor, env: &mut Environment) -> Result<u8, LogoError>
 {
-    if value == "TRUE" {
-        Some(1.0)
-    }
-    else if value == "FALSE" {
-        Some(0.0)
-    }
-    else {
-        None
+    let value = evaluate_number(expression, cursor, env)?;
+    if value.fract() != 0.0 || !(0.0..=255.0).contains(&value) {
+        return Err(LogoError::new(ErrorKind::InvalidRgb, expression.span, expression.token()));
     }
+    Ok(value as u8)
 }
 
-fn process_prefix(tokens: & Vec<&str>, position: usize, cursor: &mut Cursor, variables: &mut HashMap<String, f32>) -> Result<(f32, usize), String>
+/// Evaluates a color given either as an index into the 16 Logo colors or as
+/// a `[red green blue]` list.
+fn evaluate_color(expression: &Expression, cursor: &Cursor, env: &mut Environment) -> Result<Color, LogoError>
 {
-    println!("In prefix!");
-    let mut num_ops = 0usize;
-    let mut cur = position;
-    while cur < tokens.len() {
-        match tokens[cur] {
-            "+" | "-" | "*" | "/" => {
-                num_ops += 1;
-                cur += 1;
+    let error = |kind| LogoError::new(kind, expression.span, expression.token());
+    match evaluate(expression, cursor, env)? {
+        Value::Number(index) => {
+            if index.fract() != 0.0 || !(0.0..=15.0).contains(&index) {
+                return Err(error(ErrorKind::InvalidPenColor));
             }
-            _ => {
-                break;
+            Ok(COLORS[index as usize])
+        },
+        Value::List(channels) => {
+            let channels: Option<Vec<u8>> = channels.iter()
+                .map(|channel| match channel {
+                    Value::Number(n) if n.fract() == 0.0 && (0.0..=255.0).contains(n) => Some(*n as u8),
+                    _ => None,
+                })
+                .collect();
+            match channels.as_deref() {
+                Some(&[red, green, blue]) => Ok(Color { red, green, blue }),
+                _ => Err(error(ErrorKind::InvalidRgb)),
             }
-        }
+        },
+        other => Err(type_mismatch("number", &other, expression)),
     }
+}
 
-    println!("Num ops: {num_ops}");
-
-    let mut stack = Vec::new();
-    let end_pos = cur + num_ops;
-    let advance_by = 2 * num_ops + 1;
-    // PUSH VALUES ONTO STACK IN REVERSE
-    for i in 0..(num_ops + 1) {
-        let value = match get_value(tokens[end_pos - i], &tokens, cursor, variables) {
-            Ok(value) => value.0,
-            Err(err) => return Err(err),
-        };
-        stack.push(value);
-        println!("pushing {:?} to stack", value)
-    }
-
-    // APPLY OPERATORS TO STACK VALUES
-    for i in 1..=num_ops {
-        let op1 = stack.pop().expect("Stack shouldn't be empty");
-        let op2 = stack.pop().expect("Stack shouldn't be empty");
-        let token = tokens[cur - i];
-        let res = match tokens[cur - i] {
-            "+" => op1 + op2,
-            "-" => op1 - op2,
-            "*" => op1 * op2,
-            "/" => {
-                if op2 == 0.0 { return Err("Division by zero!".to_string()) }
-                op1 / op2
-            },
-            _ => return Err("Invalid token!".to_string())
-        };
-        stack.push(res);
+fn get_query(query: Query, cursor: &Cursor, axes: &Axes) -> f32
+{
+    match query {
+        Query::XCOR => axes.x_from_canvas(cursor.x_coord),
+        Query::YCOR => axes.y_from_canvas(cursor.y_coord),
+        Query::HEADING => cursor.direction,
+        Query::COLOR => cursor.color_as_f32(),
     }
-
-    if stack.len() > 1 { return Err("Invalid prefix expression!".to_string()) }
-
-    let res = stack.pop().expect("Stack shouldn't be empty");
-
-    Ok((res, advance_by))
 }
 
-fn compare(operator: Operator, operands: (f32, f32)) -> Result<f32, String>
+/// Applies an operator to its two arguments. Arithmetic and ordering need
+/// numbers and AND/OR need booleans, while EQ and NE compare any two values
+/// (values of different types are never equal). Results that overflow to
+/// infinity or are not a number are errors, as in `^ "-8 "0.5`.
+fn apply_operator(operator: Operator, left: &Expression, right: &Expression, expression: &Expression, cursor: &Cursor, env: &mut Environment) -> Result<Value, LogoError>
 {
-    println!("Comparing! Operator is {:?}", operator);
-    let mut res;
-    match operator {
-        Operator::EQ => {
-            res = (operands.0 == operands.1) as i32 as f32;
-        },
-        Operator::NE => {
-            res = (operands.0 != operands.1) as i32 as f32;
-        }
-        Operator::GT => {
-            res = (operands.0 > operands.1) as i32 as f32;
-        },
-        Operator::LT => {
-            res = (operands.0 < operands.1) as i32 as f32;
-        },
-        Operator::AND => {
-            res = (operands.0 == 1.0 && operands.1 == 1.0) as i32 as f32;
+    let value = match operator {
+        Operator::EQ => Value::Bool(evaluate(left, cursor, env)? == evaluate(right, cursor, env)?),
+        Operator::NE => Value::Bool(evaluate(left, cursor, env)? != evaluate(right, cursor, env)?),
+        Operator::AND | Operator::OR => {
+            let left = evaluate_bool(left, cursor, env)?;
+            let right = evaluate_bool(right, cursor, env)?;
+            match operator {
+                Operator::AND => Value::Bool(left && right),
+                _ => Value::Bool(left || right),
+            }
         },
-        Operator::OR => {
-            res = (operands.0 == 1.0 || operands.1 == 1.0) as i32 as f32;
+        _ => {
+            let left = evaluate_number(left, cursor, env)?;
+            let right = evaluate_number(right, cursor, env)?;
+            let error = |kind| LogoError::new(kind, expression.span, expression.token());
+            match operator {
+                Operator::ADD => number(left + right).ok_or_else(|| error(ErrorKind::InvalidArgument))?,
+                Operator::SUB => number(left - right).ok_or_else(|| error(ErrorKind::InvalidArgument))?,
+                Operator::MUL => number(left * right).ok_or_else(|| error(ErrorKind::InvalidArgument))?,
+                Operator::DIV | Operator::MOD => {
+                    if right == 0.0 {
+                        return Err(error(ErrorKind::DivisionByZero));
+                    }
+                    let result = if matches!(operator, Operator::DIV) { left / right } else { left % right };
+                    number(result).ok_or_else(|| error(ErrorKind::InvalidArgument))?
+                },
+                Operator::POW => number(left.powf(right)).ok_or_else(|| error(ErrorKind::InvalidArgument))?,
+                Operator::GT => Value::Bool(left > right),
+                Operator::LT => Value::Bool(left < right),
+                Operator::GE => Value::Bool(left >= right),
+                _ => Value::Bool(left <= right),
+            }
         },
-        _ => { return Err("Invalid operator!".to_string()) }
-    }
-    println!("Res is {res}");
-    Ok(res)
+    };
+    Ok(value)
 }
 
-fn get_operands(tokens: & Vec<&str>, position: usize, cursor: &mut Cursor, variables: &mut HashMap<String, f32>) -> Result<(f32, f32, usize), String>
+fn apply_function(function: Function, arguments: &[Expression], expression: &Expression, cursor: &Cursor, env: &mut Environment) -> Result<Value, LogoError>
 {
-    let mut split: usize;
-    let end_pos: usize;
-
-    println!("Position is {position}");
-    let operand_1 = match tokens[position] {
-        "EQ" | "NE" | "GT" | "LT" | "AND" | "OR" => {
-            let res = match get_operands(&tokens, position + 1, cursor, variables) {
-                Ok(res) => res,
-                Err(_) => return Err("Failed to get operands!".to_string()),
-            };
-            split = res.2;
-            let first = res.0;
-            let second = res.1;
-            let comparison = tokens[position];
-            println!("Comparison is {comparison}");
-            println!("first operand {first}");
-            println!("second operand {second}");
-            match compare(parse_operator(tokens[position]).expect("Operator should be valid"),
-        (res.0, res.1)) {
-                Ok(res) => res,
-                Err(err) => return Err(err)
+    let error = |kind| LogoError::new(kind, expression.span, expression.token());
+    match function {
+        Function::FIRST => {
+            let list = evaluate_list(&arguments[0], cursor, env)?;
+            list.into_iter().next().ok_or_else(|| error(ErrorKind::EmptyList))
+        },
+        Function::BUTFIRST => {
+            let mut list = evaluate_list(&arguments[0], cursor, env)?;
+            if list.is_empty() {
+                return Err(error(ErrorKind::EmptyList));
             }
+            list.remove(0);
+            Ok(Value::List(list))
         },
-        "+" | "-" | "*" | "/" => {
-            let res = match process_prefix(&tokens, position, cursor, variables) {
-                Ok(res)=> res,
-                Err(err) => return Err(err),
-            };
-            split = position + res.1;
-            println!("split idx is: {split}");
-            println!("tokens are: {:?}", tokens);
-            res.0
+        Function::ITEM => {
+            // Lists are indexed from 1, as in other Logos
+            let index = evaluate_number(&arguments[0], cursor, env)?;
+            let list = evaluate_list(&arguments[1], cursor, env)?;
+            if index.fract() != 0.0 || index < 1.0 || index as usize > list.len() {
+                return Err(error(ErrorKind::IndexOutOfRange));
+            }
+            Ok(list[index as usize - 1].clone())
         },
-        _ => {
-            let (res, advance_by) = match get_value(tokens[position], &tokens, cursor, variables) {
-                Ok(res)=> res,
-                Err(err) => return Err(err),
-            };
-            split = position + advance_by + 1;
-            res
+        Function::COUNT => {
+            let list = evaluate_list(&arguments[0], cursor, env)?;
+            Ok(Value::Number(list.len() as f32))
         },
-    };
-    println!("Operand 1 is {operand_1}");
-    println!("Split is {split}");
-
-    let operand_2 = match tokens[split] {
-        "EQ" | "NE" | "GT" | "LT" | "AND" | "OR" => {
-            let tok = tokens[split];
-            println!("Processing token {tok}");
-            let res = match get_operands(&tokens, split + 1, cursor, variables) {
-                Ok(res)=> res,
-                Err(_) => return Err("Failed to get operands!".to_string()),
-            };
-            end_pos = res.2;
-            match compare(parse_operator(tokens[split]).expect("should work"), (res.0, res.1)) {
-                Ok(res) => res,
-                Err(err) => return Err(err),
+        Function::FPUT => {
+            let item = evaluate(&arguments[0], cursor, env)?;
+            let mut list = evaluate_list(&arguments[1], cursor, env)?;
+            list.insert(0, item);
+            Ok(Value::List(list))
+        },
+        Function::LPUT => {
+            let item = evaluate(&arguments[0], cursor, env)?;
+            let mut list = evaluate_list(&arguments[1], cursor, env)?;
+            list.push(item);
+            Ok(Value::List(list))
+        },
+        Function::NOT => Ok(Value::Bool(!evaluate_bool(&arguments[0], cursor, env)?)),
+        Function::RANDOM => {
+            let limit = evaluate_number(&arguments[0], cursor, env)?;
+            if limit.fract() != 0.0 || !(1.0..=u32::MAX as f32).contains(&limit) {
+                return Err(error(ErrorKind::InvalidArgument));
             }
-        }
-        "+" | "-" | "*" | "/" => {
-            let res = match process_prefix(&tokens, split, cursor, variables) {
-                Ok(res)=> res,
-                Err(err) => return Err(err),
-            };
-            end_pos = position + res.1 + 1;
-            res.0
-        }
+            Ok(Value::Number(env.random(limit as u32) as f32))
+        },
+        Function::PICK => {
+            let mut list = evaluate_list(&arguments[0], cursor, env)?;
+            if list.is_empty() {
+                return Err(error(ErrorKind::EmptyList));
+            }
+            let index = env.random(list.len() as u32) as usize;
+            Ok(list.swap_remove(index))
+        },
         _ => {
-            let (res, advance_by) = match get_value(tokens[split], &tokens, cursor, variables) {
-                Ok(res) => res,
-                Err(err) => return Err(err),
+            // The rest are maths on a single number. Angles are in degrees.
+            let x = evaluate_number(&arguments[0], cursor, env)?;
+            let result = match function {
+                Function::SQRT => x.sqrt(),
+                Function::SIN => x.to_radians().sin(),
+                Function::COS => x.to_radians().cos(),
+                Function::ARCTAN => x.atan().to_degrees(),
+                Function::ABS => x.abs(),
+                _ => x.trunc(),
             };
-            end_pos = split + advance_by + 1;
-            res
-        }
-    };
-    println!("Operand 2 is {operand_2}");
-    println!("end_pos is {end_pos}");
-    Ok((operand_1, operand_2, end_pos))
-}
\ No newline at end of file
+            number(result).ok_or_else(|| error(ErrorKind::InvalidArgument))
+        },
+    }
+}
+
+/// A number value, unless the result of some maths is infinite or not a number.
+fn number(value: f32) -> Option<Value>
+{
+    value.is_finite().then_some(Value::Number(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::ErrorKind;
+    use crate::structs::{normalize_degrees, Cursor, Value};
+    use crate::test_support::{fail, run, run_seeded, Run};
+
+    /// What running `source` fails with, and where.
+    fn error(source: &str) -> (ErrorKind, usize, usize)
+    {
+        let err = fail(source);
+        (err.kind, err.span.line, err.span.column)
+    }
+
+    fn variable(run: &Run, name: &str) -> Option<Value>
+    {
+        run.env.get(name).cloned()
+    }
+
+    #[test]
+    fn blocks_run_as_often_as_their_conditions_allow()
+    {
+        let run = run("\
+MAKE \"i \"0 MAKE \"odd \"0
+WHILE LT :i \"5 [
+  ADDASSIGN \"i \"1
+  IF EQ :i \"3 [ MAKE \"three :i ] IF NE :i \"3
+  [ ADDASSIGN \"odd \"1 ]
+]
+PENDOWN FORWARD
+  :i
+");
+        let numbers = ["i", "three", "odd"].map(|name| variable(&run, name));
+        assert_eq!(numbers, [5.0, 3.0, 4.0].map(|number| Some(Value::Number(number))));
+        assert_eq!((run.cursor.x_coord, run.cursor.y_coord), (100.0, 95.0));
+    }
+
+    #[test]
+    fn nothing_runs_if_the_program_does_not_parse()
+    {
+        // Were the first line run, the loop would never end
+        assert_eq!(error("WHILE \"TRUE [ PENDOWN ]\nFORWARD ]"), (ErrorKind::ExpectedExpression, 2, 9));
+    }
+
+    #[test]
+    fn runtime_errors_point_at_what_caused_them()
+    {
+        assert_eq!(error("PENDOWN\nFORWARD :nope"), (ErrorKind::UnknownVariable, 2, 9));
+        assert_eq!(error("MAKE \"x / \"1 \"0"), (ErrorKind::DivisionByZero, 1, 9));
+        assert_eq!(error("IF \"2 [ ]"), (ErrorKind::TypeMismatch { expected: "boolean", found: "number" }, 1, 4));
+        assert_eq!(error("ADDASSIGN \"nope \"1"), (ErrorKind::UnknownVariable, 1, 1));
+        assert_eq!(error("FORWARD \"1e30"), (ErrorKind::OutOfBounds, 1, 1));
+    }
+
+    #[test]
+    fn headings_are_fractional_and_kept_within_a_turn()
+    {
+        assert_eq!(normalize_degrees(-90.0), 270.0);
+        assert_eq!(normalize_degrees(720.5), 0.5);
+        assert_eq!(normalize_degrees(-1e-6), 0.0);
+        let run = run("TURN \"22.5 TURN \"-45 TURN \"0.25");
+        assert_eq!(run.cursor.direction, 337.75);
+    }
+
+    #[test]
+    fn moves_follow_the_heading()
+    {
+        let mut cursor = Cursor::new(100.0, 100.0);
+        assert_eq!(cursor.destination(0.0, 10.0), (100.0, 90.0));
+        assert_eq!(cursor.destination(90.0, 10.0), (110.0, 100.0));
+        cursor.turn(45.0);
+        let (x, y) = cursor.destination(cursor.direction, 2f32.sqrt());
+        assert_eq!((x, y), (101.0, 99.0));
+    }
+
+    #[test]
+    fn arcs_and_circles_leave_the_turtle_where_it_was()
+    {
+        let run = run("PENDOWN TURN \"90 ARC \"90 \"10 CIRCLE \"20");
+        assert_eq!((run.cursor.x_coord, run.cursor.y_coord, run.cursor.direction), (100.0, 100.0, 90.0));
+    }
+
+    #[test]
+    fn home_goes_back_to_the_start_facing_up()
+    {
+        let run = run("PENDOWN FORWARD \"10 TURN \"90 FORWARD \"10 HOME");
+        assert_eq!((run.cursor.x_coord, run.cursor.y_coord, run.cursor.direction), (100.0, 100.0, 0.0));
+    }
+
+    #[test]
+    fn lists_are_built_and_taken_apart()
+    {
+        let run = run("\
+MAKE \"steps [10 twenty TRUE [1 2]]
+MAKE \"first FIRST :steps
+MAKE \"rest BUTFIRST :steps
+MAKE \"last ITEM \"4 :steps
+MAKE \"count COUNT :steps
+MAKE \"both LPUT \"3 FPUT \"0 [1 2]
+");
+        assert_eq!(variable(&run, "first"), Some(Value::Number(10.0)));
+        assert_eq!(variable(&run, "rest").unwrap().to_string(), "[twenty TRUE [1 2]]");
+        assert_eq!(variable(&run, "last").unwrap().to_string(), "[1 2]");
+        assert_eq!(variable(&run, "count"), Some(Value::Number(4.0)));
+        assert_eq!(variable(&run, "both").unwrap().to_string(), "[0 1 2 3]");
+    }
+
+    #[test]
+    fn repeat_and_foreach_loop_over_counts_and_lists()
+    {
+        let run = run("\
+MAKE \"total \"0
+MAKE \"passes \"0
+FOREACH \"step [1 2 3] [ ADDASSIGN \"total :step ]
+REPEAT + \"2 \"1 [ ADDASSIGN \"passes \"1 ]
+REPEAT \"0 [ ADDASSIGN \"passes \"10 ]
+");
+        assert_eq!(variable(&run, "total"), Some(Value::Number(6.0)));
+        assert_eq!(variable(&run, "passes"), Some(Value::Number(3.0)));
+    }
+
+    #[test]
+    fn mixed_values_are_type_errors()
+    {
+        let mismatch = |expected, found| ErrorKind::TypeMismatch { expected, found };
+        let kind = |source| fail(source).kind;
+        assert_eq!(kind("FORWARD [1 2]"), mismatch("number", "list"));
+        assert_eq!(kind("MAKE \"x + \"1 \"TRUE"), mismatch("number", "boolean"));
+        assert_eq!(kind("FOREACH \"i \"3 [ ]"), mismatch("list", "number"));
+        assert_eq!(kind("MAKE \"x FIRST []"), ErrorKind::EmptyList);
+        assert_eq!(kind("MAKE \"x ITEM \"3 [1 2]"), ErrorKind::IndexOutOfRange);
+        assert_eq!(kind("REPEAT \"-1 [ ]"), ErrorKind::InvalidCount);
+    }
+
+    #[test]
+    fn expressions_evaluate_infix_prefix_and_functions()
+    {
+        let run = run("\
+MAKE \"x 5
+MAKE \"sum (:x + 2) * 3 - 2 ^ 3 ^ 2 / 64
+MAKE \"prefix * + :x 1 % 7 4
+MAKE \"negated -:x + ABS -2
+MAKE \"math SQRT 16 + INT 2.7 + COS 0 + SIN 0 + ARCTAN 0
+MAKE \"compared AND GE :x 5 NOT LE :x 4
+MAKE \"either OR EQ :x 1 NE :x 1
+");
+        assert_eq!(variable(&run, "sum"), Some(Value::Number(13.0)));
+        assert_eq!(variable(&run, "prefix"), Some(Value::Number(18.0)));
+        assert_eq!(variable(&run, "negated"), Some(Value::Number(-3.0)));
+        assert_eq!(variable(&run, "math"), Some(Value::Number(7.0)));
+        assert_eq!(variable(&run, "compared"), Some(Value::Bool(true)));
+        assert_eq!(variable(&run, "either"), Some(Value::Bool(true)));
+    }
+
+    #[test]
+    fn random_numbers_follow_the_seed()
+    {
+        let draw = |seed| {
+            let run = run_seeded("MAKE \"a RANDOM 1000000 MAKE \"b RANDOM 1000000", seed);
+            (variable(&run, "a"), variable(&run, "b"))
+        };
+        assert_eq!(draw(1), draw(1));
+        assert_ne!(draw(1), draw(2));
+        assert_ne!(draw(1).0, draw(1).1);
+    }
+
+    #[test]
+    fn lines_take_the_pen_color_and_size()
+    {
+        let run = run("PENDOWN SETPENRGB 255 128 0 SETPENSIZE 3 FORWARD 10 SETPENCOLOR 1 SETPENSIZE 0.5 FORWARD 10");
+        let svg = run.canvas.to_text().unwrap();
+        let thick = r##"<path d="M 100 100 L 100 90" fill="none" stroke="#ff8000" stroke-width="3" "##;
+        assert!(svg.contains(thick), "{svg}");
+        let thin = r##"<path d="M 100 90 L 100 80" fill="none" stroke="#0000ff" stroke-width="0.5" "##;
+        assert!(svg.contains(thin), "{svg}");
+    }
+
+    #[test]
+    fn fills_and_backgrounds_reach_the_svg()
+    {
+        let run = run("SETBACKGROUND [0 0 255] PENDOWN BEGINFILL FORWARD 10 TURN 90 FORWARD 10 ENDFILL");
+        let svg = run.canvas.to_text().unwrap();
+        assert!(svg.contains(r##"<rect width="200" height="200" fill="#0000ff"/>"##), "{svg}");
+        assert!(svg.contains(r##"<path d="M 100 100 L 100 90 L 110 90 Z" fill="#ffffff" stroke="none"/>"##), "{svg}");
+    }
+
+    #[test]
+    fn bad_pens_and_fills_are_errors()
+    {
+        let kind = |source| fail(source).kind;
+        assert_eq!(kind("SETPENCOLOR 16"), ErrorKind::InvalidPenColor);
+        assert_eq!(kind("SETPENRGB 0 256 0"), ErrorKind::InvalidRgb);
+        assert_eq!(kind("SETBACKGROUND [0 0]"), ErrorKind::InvalidRgb);
+        assert_eq!(kind("SETPENSIZE 0"), ErrorKind::InvalidPenSize);
+        assert_eq!(kind("SETPENSIZE 2000000"), ErrorKind::PenSizeTooLarge);
+        assert_eq!(kind("BEGINFILL BEGINFILL"), ErrorKind::FillAlreadyStarted);
+        assert_eq!(kind("ENDFILL"), ErrorKind::EndFillWithoutBeginFill);
+    }
+
+    #[test]
+    fn states_are_saved_and_restored_without_drawing()
+    {
+        let run = run("\
+PENDOWN SETPENCOLOR 1 SETPENSIZE 2
+PUSHSTATE
+  TURN 45 FORWARD 10 PENUP SETPENCOLOR 2 SETPENSIZE 4
+  PUSHSTATE FORWARD 5 POPSTATE
+POPSTATE
+FORWARD 10
+");
+        let svg = run.canvas.to_text().unwrap();
+        assert_eq!(svg.matches("<path").count(), 2, "{svg}");
+        let last = r##"<path d="M 100 100 L 100 90" fill="none" stroke="#0000ff" stroke-width="2" "##;
+        assert!(svg.contains(last), "{svg}");
+        assert_eq!(fail("PUSHSTATE POPSTATE POPSTATE").kind, ErrorKind::PopStateWithoutPushState);
+    }
+}
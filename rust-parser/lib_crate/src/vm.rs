@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::bytecode::{Instruction, InstructionKind};
+use crate::canvas::Canvas;
+use crate::errors::{ErrorKind, LogoError};
+use crate::interpreter::{NativeProcedure, Turtle};
+use crate::lsystem::execute_lsystem;
+use crate::structs::{Cursor, Environment, Expression, Span, Value};
+use crate::utils::{check_condition, evaluate, evaluate_list, evaluate_name, evaluate_number, execute_procedure};
+
+/// A REPEAT or FOREACH that is part way through.
+enum LoopState {
+    /// Passes still to run, including the current one.
+    Repeat(u64),
+    /// Items not yet bound.
+    Foreach(std::vec::IntoIter<Value>),
+}
+
+/// The instructions being run by the top level or by one procedure call.
+struct Frame {
+    code: Rc<[Instruction]>,
+    pc: usize,
+    loops: Vec<LoopState>,
+}
+
+impl Frame {
+    fn new(code: Rc<[Instruction]>) -> Frame {
+        Frame { code, pc: 0, loops: Vec::new() }
+    }
+}
+
+/// Runs compiled instructions one at a time.
+///
+/// Procedure calls push a frame rather than recursing, so the depth of the
+/// Rust stack does not depend on the program. Variables live in the
+/// `Environment` as before, with a scope pushed for each call.
+pub struct Vm {
+    frames: Vec<Frame>,
+    /// For each ASK in progress, the turtle that was drawing before it.
+    asking: Vec<String>,
+}
+
+impl Vm {
+    pub fn new(code: Rc<[Instruction]>) -> Vm {
+        Vm { frames: vec![Frame::new(code)], asking: Vec::new() }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// How many frames are running: one for the top level plus one for each
+    /// procedure call in progress.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The instruction that will run next, if any.
+    pub fn current(&self) -> Option<&Instruction> {
+        let frame = self.frames.last()?;
+        frame.code.get(frame.pc)
+    }
+
+    /// Runs until the top level instructions are finished. If an instruction
+    /// fails, every call in progress is abandoned.
+    pub fn run(&mut self, canvas: &mut dyn Canvas, cursor: &mut Cursor, env: &mut Environment) -> Result<(), LogoError> {
+        while !self.is_finished() {
+            if let Err(err) = self.step(canvas, cursor, env) {
+                self.abort(canvas, cursor, env);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a single instruction.
+    pub fn step(&mut self, canvas: &mut dyn Canvas, cursor: &mut Cursor, env: &mut Environment) -> Result<(), LogoError> {
+        let Some(frame) = self.frames.last_mut() else {
+            return Ok(());
+        };
+        let code = Rc::clone(&frame.code);
+        let Some(instruction) = code.get(frame.pc) else {
+            self.return_from_call(canvas, env);
+            return Ok(());
+        };
+        frame.pc += 1;
+
+        let span = instruction.span;
+        env.usage.step(span)?;
+        match &instruction.kind {
+            InstructionKind::Procedure(procedure) => {
+                execute_procedure(canvas, procedure, span, cursor, env)?;
+                env.tracer.statement(procedure.name(), span, env.depth(), cursor, None);
+            },
+            InstructionKind::Branch { statement, condition, target } => {
+                let outcome = check_condition(condition, cursor, env)?;
+                env.tracer.statement(statement, span, env.depth(), cursor, Some(outcome));
+                if !outcome {
+                    self.frame().pc = *target;
+                }
+            },
+            InstructionKind::Jump(target) => {
+                self.frame().pc = *target;
+            },
+            InstructionKind::Repeat { count, end } => {
+                let times = evaluate_number(count, cursor, env)?;
+                if times.fract() != 0.0 || times < 0.0 {
+                    return Err(LogoError::new(ErrorKind::InvalidCount, count.span, count.token()));
+                }
+                env.tracer.statement("REPEAT", span, env.depth(), cursor, None);
+                let frame = self.frame();
+                if times == 0.0 {
+                    frame.pc = *end;
+                }
+                else {
+                    frame.loops.push(LoopState::Repeat(times as u64));
+                }
+            },
+            InstructionKind::Loop { body } => {
+                let frame = self.frame();
+                match frame.loops.last_mut() {
+                    Some(LoopState::Repeat(remaining)) if *remaining > 1 => {
+                        *remaining -= 1;
+                        frame.pc = *body;
+                    },
+                    Some(LoopState::Repeat(_)) => {
+                        frame.loops.pop();
+                    },
+                    _ => unreachable!("LOOP outside of a REPEAT"),
+                }
+            },
+            InstructionKind::Foreach { name, list, end } => {
+                let items = evaluate_list(list, cursor, env)?;
+                env.tracer.statement("FOREACH", span, env.depth(), cursor, None);
+                let mut items = items.into_iter();
+                match items.next() {
+                    Some(item) => {
+                        env.make(name, item);
+                        self.frame().loops.push(LoopState::Foreach(items));
+                    },
+                    None => self.frame().pc = *end,
+                }
+            },
+            InstructionKind::Next { name, body } => {
+                let frame = self.frame();
+                let Some(LoopState::Foreach(items)) = frame.loops.last_mut() else {
+                    unreachable!("NEXT outside of a FOREACH");
+                };
+                match items.next() {
+                    Some(item) => {
+                        env.make(name, item);
+                        frame.pc = *body;
+                    },
+                    None => {
+                        frame.loops.pop();
+                    },
+                }
+            },
+            InstructionKind::Ask(turtle) => {
+                let name = evaluate_name(turtle, cursor, env)?;
+                let previous = env.turtle().to_string();
+                if !env.select_turtle(cursor, &name) {
+                    return Err(LogoError::new(ErrorKind::UnknownTurtle, turtle.span, turtle.token()));
+                }
+                env.tracer.statement("ASK", span, env.depth(), cursor, None);
+                self.asking.push(previous);
+            },
+            InstructionKind::EndAsk => {
+                let previous = self.asking.pop().expect("ENDASK follows an ASK");
+                env.select_turtle(cursor, &previous);
+            },
+            InstructionKind::LSystem(lsystem) => {
+                execute_lsystem(canvas, lsystem, span, cursor, env)?;
+                env.tracer.statement("LSYSTEM", span, env.depth(), cursor, None);
+            },
+            InstructionKind::Call(name, arguments) => {
+                self.call(name, arguments, span, canvas, cursor, env)?;
+            },
+            InstructionKind::Return => {
+                self.return_from_call(canvas, env);
+            },
+        }
+        Ok(())
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("A frame is running")
+    }
+
+    /// Binds the arguments to a fresh scope and starts running the procedure body in it.
+    fn call(&mut self, name: &str, arguments: &[Expression], span: Span, canvas: &mut dyn Canvas, cursor: &mut Cursor, env: &mut Environment) -> Result<(), LogoError> {
+        let procedure = match (env.procedures.get(name), env.natives.get(name)) {
+            (Some(procedure), _) => Rc::clone(procedure),
+            (None, Some(native)) => {
+                let native = Rc::clone(native);
+                return call_native(&native, arguments, span, canvas, cursor, env);
+            },
+            (None, None) => return Err(LogoError::new(ErrorKind::UndefinedProcedure, span, name)),
+        };
+        if env.depth() >= env.usage.limits.max_depth {
+            return Err(LogoError::new(ErrorKind::RecursionTooDeep, span, name));
+        }
+
+        let mut scope = HashMap::new();
+        for (parameter, argument) in procedure.parameters.iter().zip(arguments) {
+            let value = evaluate(argument, cursor, env)?;
+            env.tracer.record_change(parameter, &value);
+            scope.insert(parameter.clone(), value);
+        }
+        env.tracer.statement(name, span, env.depth(), cursor, None);
+
+        env.push_scope(scope);
+        canvas.begin_group(name);
+        self.frames.push(Frame::new(Rc::clone(&procedure.code)));
+        Ok(())
+    }
+
+    /// Finishes the running frame. Only frames above the top level belong to
+    /// procedure calls, with a scope and group to close.
+    fn return_from_call(&mut self, canvas: &mut dyn Canvas, env: &mut Environment) {
+        self.frames.pop();
+        if !self.frames.is_empty() {
+            canvas.end_group();
+            env.pop_scope();
+        }
+    }
+
+    /// Abandons every call and ASK in progress, leaving the turtle that was
+    /// drawing at the top level in `cursor`.
+    pub fn abort(&mut self, canvas: &mut dyn Canvas, cursor: &mut Cursor, env: &mut Environment) {
+        while !self.is_finished() {
+            self.return_from_call(canvas, env);
+        }
+        if let Some(outermost) = self.asking.drain(..).next() {
+            env.select_turtle(cursor, &outermost);
+        }
+    }
+}
+
+/// Calls a procedure provided by the program embedding the interpreter,
+/// which runs straight away rather than in a frame of its own.
+fn call_native(native: &NativeProcedure, arguments: &[Expression], span: Span, canvas: &mut dyn Canvas, cursor: &mut Cursor, env: &mut Environment) -> Result<(), LogoError>
+{
+    let values = arguments.iter()
+        .map(|argument| evaluate(argument, cursor, env))
+        .collect::<Result<Vec<Value>, LogoError>>()?;
+    let mut turtle = Turtle { canvas, cursor, env, span, name: &native.name };
+    (native.function)(&mut turtle, &values)?;
+    env.tracer.statement(&native.name, span, env.depth(), cursor, None);
+    Ok(())
+}
+
+/// Runs a compiled block of instructions to the end.
+pub fn execute(code: &Rc<[Instruction]>, canvas: &mut dyn Canvas, cursor: &mut Cursor, env: &mut Environment) -> Result<(), LogoError>
+{
+    Vm::new(Rc::clone(code)).run(canvas, cursor, env)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::ErrorKind;
+    use crate::limits::{Limits, Usage};
+    use crate::structs::Value;
+    use crate::test_support::{fail, run, try_run_with, Run};
+
+    fn variable(run: &Run, name: &str) -> Option<Value>
+    {
+        run.env.get(name).cloned()
+    }
+
+    #[test]
+    fn procedures_take_arguments_in_a_scope_of_their_own()
+    {
+        let run = run("\
+MAKE \"size \"1
+PENDOWN
+TO Box \"size
+  MAKE \"inside :size
+  FORWARD :size
+END
+Box + \"10 \"5
+");
+        assert_eq!((run.cursor.x_coord, run.cursor.y_coord), (100.0, 85.0));
+        assert_eq!((variable(&run, "size"), variable(&run, "inside")), (Some(Value::Number(1.0)), None));
+    }
+
+    #[test]
+    fn procedures_recurse()
+    {
+        let run = run("\
+MAKE \"count \"0
+TO Down \"n
+  IF GT :n \"0 [
+    ADDASSIGN \"count \"1
+    Down - :n \"1
+  ]
+END
+Down \"50
+");
+        assert_eq!(variable(&run, "count"), Some(Value::Number(50.0)));
+    }
+
+    #[test]
+    fn runaway_recursion_is_an_error()
+    {
+        let err = fail("TO Forever\n  Forever\nEND\nForever");
+        assert_eq!((err.kind, err.span.line, err.span.column), (ErrorKind::RecursionTooDeep, 2, 3));
+    }
+
+    #[test]
+    fn each_turtle_draws_from_its_own_cursor()
+    {
+        let run = run("\
+NEWTURTLE \"t2
+ASK \"t2 [ PENDOWN TURN 90 FORWARD 10 ]
+PENDOWN FORWARD 10
+ASK \"t2 [ FORWARD 10 ]
+");
+        assert_eq!(run.paths(), ["M 100 100 L 110 100", "M 100 100 L 100 90", "M 110 100 L 120 100"]);
+        // Back to the first turtle after each ASK
+        assert_eq!((run.cursor.y_coord, run.cursor.direction), (90.0, 0.0));
+    }
+
+    #[test]
+    fn turtles_must_be_made_once_before_they_are_asked()
+    {
+        assert_eq!(fail("ASK \"t2 [ ]").kind, ErrorKind::UnknownTurtle);
+        assert_eq!(fail("NEWTURTLE \"t2 NEWTURTLE \"t2").kind, ErrorKind::DuplicateTurtle);
+        assert_eq!(
+            fail("NEWTURTLE \"t2 BEGINFILL ASK \"t2 [ BEGINFILL ]").kind,
+            ErrorKind::FillByAnotherTurtle("main".to_string())
+        );
+    }
+
+    #[test]
+    fn loops_take_steps_in_proportion_to_their_passes()
+    {
+        // Each pass of the WHILE runs the branch, ADDASSIGN and the jump back
+        let source = "MAKE \"i 0\nWHILE LT :i 20000 [\n  ADDASSIGN \"i 1\n]\n";
+        let limited = |max_steps| {
+            let limits = Limits { max_steps: Some(max_steps), ..Limits::default() };
+            try_run_with(source, |env| env.usage = Usage::new(limits))
+        };
+        let run = limited(1 + 3 * 20_000 + 1).unwrap();
+        assert_eq!(variable(&run, "i"), Some(Value::Number(20000.0)));
+        assert_eq!(limited(3 * 20_000).err().map(|err| err.kind), Some(ErrorKind::StepLimitExceeded));
+    }
+}
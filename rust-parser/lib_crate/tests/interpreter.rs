@@ -0,0 +1,88 @@
+//! Embeds the interpreter the way another program would.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use lib_crate::canvas::{create_canvas, Format};
+use lib_crate::errors::ErrorKind;
+use lib_crate::interpreter::Interpreter;
+use lib_crate::limits::Limits;
+use lib_crate::structs::Value;
+
+fn interpreter() -> Interpreter
+{
+    Interpreter::new(create_canvas(Format::Svg, 200, 200, false))
+}
+
+#[test]
+fn runs_programs_and_reports_what_they_drew()
+{
+    let mut logo = interpreter();
+    logo.load("MAKE \"side 30\nPENDOWN\nREPEAT 4 [\n  FORWARD :side\n  TURN 90\n]\n").unwrap();
+    logo.run().unwrap();
+
+    assert!(logo.is_finished());
+    assert_eq!(logo.segments().len(), 4);
+    assert_eq!(logo.segments()[0].start, (100.0, 100.0));
+    assert_eq!(logo.segments()[0].end, (100.0, 70.0));
+    assert_eq!(logo.variables().get("side"), Some(&Value::Number(30.0)));
+    assert_eq!((logo.cursor().x_coord, logo.cursor().y_coord), (100.0, 100.0));
+
+    // Later loads build on what came before
+    logo.load("FORWARD :side").unwrap();
+    logo.run().unwrap();
+    assert_eq!(logo.segments().len(), 5);
+}
+
+#[test]
+fn steps_one_statement_at_a_time()
+{
+    let mut logo = interpreter();
+    logo.load("PENDOWN\nREPEAT 2 [\n  FORWARD 10\n]\nMAKE \"done TRUE\n").unwrap();
+
+    let mut steps = 0;
+    while logo.step().unwrap() {
+        steps += 1;
+    }
+    // PENDOWN, REPEAT, FORWARD twice, then MAKE
+    assert_eq!(steps + 1, 5);
+    assert_eq!(logo.segments().len(), 2);
+    assert_eq!(logo.variables().get("done"), Some(&Value::Bool(true)));
+}
+
+#[test]
+fn calls_native_procedures()
+{
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let mut logo = interpreter();
+    let log = Rc::clone(&seen);
+    logo.register("LOG", 1, move |_, args| {
+        log.borrow_mut().push(args[0].clone());
+        Ok(())
+    }).unwrap();
+    logo.register("JUMP", 2, |turtle, args| {
+        match args {
+            [Value::Number(x), Value::Number(y)] => turtle.move_to(*x, *y),
+            _ => Err(turtle.error("JUMP takes two numbers")),
+        }
+    }).unwrap();
+    assert!(logo.register("FORWARD", 1, |_, _| Ok(())).is_err());
+
+    logo.load("JUMP 10 20\nLOG XCOR\nLOG + YCOR 1\n").unwrap();
+    logo.run().unwrap();
+    assert_eq!(*seen.borrow(), [Value::Number(10.0), Value::Number(21.0)]);
+
+    // Moves draw like any other
+    logo.load("PENDOWN\nJUMP 10 50\n").unwrap();
+    logo.run().unwrap();
+    assert_eq!(logo.segments().len(), 1);
+    assert_eq!(logo.segments()[0].end, (10.0, 50.0));
+
+    logo.set_limits(Limits { max_segments: Some(1), ..Limits::default() });
+    logo.load("JUMP 20 50\nJUMP 20 60\n").unwrap();
+    assert_eq!(logo.run().unwrap_err().kind, ErrorKind::SegmentLimitExceeded);
+
+    logo.load("JUMP \"a 1").unwrap();
+    let err = logo.run().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Native("JUMP takes two numbers".to_string()));
+    assert_eq!(err.token, "JUMP");
+}
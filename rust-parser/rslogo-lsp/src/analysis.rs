@@ -0,0 +1,311 @@
+use std::collections::{BTreeSet, HashSet};
+use std::ops::Range as Indices;
+use std::path::{Path, PathBuf};
+use lib_crate::{check, lexer, load};
+use lib_crate::check::Severity;
+use lib_crate::dialect::Dialect;
+use lib_crate::errors::LogoError;
+use lib_crate::load::Sources;
+use lib_crate::structs::{Span, Token, TokenKind};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, Diagnostic, DiagnosticSeverity, Hover, HoverContents, Location, MarkupContent,
+    MarkupKind, Position, Range, Uri,
+};
+use crate::docs;
+
+/// Where the diagnostics come from, as shown by editors.
+const SOURCE: &str = "rslogo";
+
+/// What the server knows about one open document.
+pub struct Analysis {
+    text: String,
+    /// The document's own tokens.
+    tokens: Vec<Token>,
+    /// The tokens of the prelude, the document and every file it loads, or
+    /// the error that stopped them loading.
+    expanded: Result<Vec<Token>, LogoError>,
+    sources: Sources,
+}
+
+impl Analysis {
+    /// Reads `text`, written in `dialect`, loading files relative to `path`,
+    /// or to the current directory for a document not saved yet.
+    pub fn new(text: String, path: Option<&Path>, dialect: Dialect) -> Analysis {
+        let mut sources = Sources::default();
+        let path = path.unwrap_or(Path::new("untitled.lg"));
+        let expanded = load::expand(&text, path, true, dialect, &mut sources);
+        // Read as the parser reads it, so that `fd` is found as FORWARD
+        let mut tokens = dialect.translate(lexer::tokenize(&dialect.without_comments(&text)));
+        if let Ok(expanded) = &expanded {
+            let defined: HashSet<String> = definitions(expanded).map(|token| token.kind.to_string()).collect();
+            dialect.name_calls(&mut tokens, |name| defined.contains(name));
+        }
+        Analysis { tokens, text, expanded, sources }
+    }
+
+    /// Every error and warning `rslogo check` would report. Those in files
+    /// the document loads are shown at its start.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let found = match &self.expanded {
+            Ok(tokens) => check::check(tokens),
+            Err(err) => vec![check::Diagnostic { severity: Severity::Error, error: err.clone() }],
+        };
+        found.into_iter()
+            .map(|diagnostic| {
+                let span = diagnostic.error.span;
+                let (range, message) = match self.sources.get(span.file) {
+                    Some(file) if span.file != 0 => {
+                        (Range::default(), format!("{} line {}: {}", file.path, span.line, diagnostic.error))
+                    },
+                    _ => (range(&self.text, span), diagnostic.error.to_string()),
+                };
+                let severity = match diagnostic.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                };
+                Diagnostic {
+                    range,
+                    severity: Some(severity),
+                    source: Some(SOURCE.to_string()),
+                    message,
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    /// How to use the built-in or procedure at `position`.
+    pub fn hover(&self, position: Position) -> Option<Hover> {
+        let token = self.token_at(position)?;
+        let TokenKind::Word(word) = &token.kind else {
+            return None;
+        };
+        let value = match docs::find(word) {
+            Some(doc) => format!("```logo\n{}\n```\n{}", doc.usage, doc.about),
+            None => {
+                let name = self.procedure(word)?;
+                let mut value = format!("```logo\n{}\n```", self.signature(name));
+                match self.sources.get(name.span.file) {
+                    Some(file) if file.path == load::PRELUDE_PATH => value.push_str("\nFrom the prelude"),
+                    Some(file) if name.span.file != 0 => value.push_str(&format!("\nFrom {}", file.path)),
+                    _ => {},
+                }
+                value
+            },
+        };
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value }),
+            range: Some(range(&self.text, token.span)),
+        })
+    }
+
+    /// Where the procedure or variable at `position` is defined. Procedures
+    /// from the prelude have nowhere to go.
+    pub fn definition(&self, position: Position, uri: &Uri) -> Option<Location> {
+        let token = self.token_at(position)?;
+        let span = match &token.kind {
+            TokenKind::Word(word) => self.procedure(word)?.span,
+            TokenKind::Variable(name) => self.variable(name, token)?.span,
+            _ => return None,
+        };
+        if span.file == 0 {
+            return Some(Location::new(uri.clone(), range(&self.text, span)));
+        }
+        let file = self.sources.get(span.file)?;
+        let path = Path::new(&file.path).canonicalize().ok()?;
+        Some(Location::new(path_to_uri(&path)?, range(&file.text, span)))
+    }
+
+    /// The variables in the document after a `:`, or else every built-in
+    /// and procedure that can be called.
+    pub fn completion(&self, position: Position) -> Vec<CompletionItem> {
+        let in_variable = self.token_at(position).is_some_and(|token| matches!(token.kind, TokenKind::Variable(_)));
+        if in_variable {
+            let names: BTreeSet<&str> = self.variable_definitions(0..self.tokens.len())
+                .chain(self.procedures().into_iter().flat_map(|procedure| self.parameters(procedure.start)))
+                .filter_map(|token| match &token.kind {
+                    TokenKind::Value(name) => Some(name.as_str()),
+                    _ => None,
+                })
+                .collect();
+            return names.into_iter()
+                .map(|name| CompletionItem {
+                    label: name.to_string(),
+                    kind: Some(CompletionItemKind::VARIABLE),
+                    ..Default::default()
+                })
+                .collect();
+        }
+
+        let keywords = docs::DOCS.iter().map(|doc| CompletionItem {
+            label: doc.name.to_string(),
+            kind: Some(CompletionItemKind::KEYWORD),
+            detail: Some(doc.usage.to_string()),
+            documentation: Some(lsp_types::Documentation::String(doc.about.to_string())),
+            ..Default::default()
+        });
+        let procedures = definitions(self.all_tokens()).map(|name| CompletionItem {
+            label: name.kind.to_string(),
+            kind: Some(CompletionItemKind::FUNCTION),
+            detail: Some(self.signature(name)),
+            ..Default::default()
+        });
+        keywords.chain(procedures).collect()
+    }
+
+    /// The tokens of every file, or just the document's if loading failed.
+    fn all_tokens(&self) -> &[Token] {
+        self.expanded.as_deref().unwrap_or(&self.tokens)
+    }
+
+    /// The document's token under `position`, preferring one the position
+    /// is inside over one it is just after.
+    fn token_at(&self, position: Position) -> Option<&Token> {
+        let line = self.text.lines().nth(position.line as usize)?;
+        let mut units = 0;
+        let column = 1 + line.chars()
+            .take_while(|c| {
+                units += c.len_utf16();
+                units <= position.character as usize
+            })
+            .count();
+        let on_line = || self.tokens.iter().filter(|token| token.span.line == position.line as usize + 1);
+        on_line()
+            .find(|token| (token.span.column..token.span.column + token.span.length).contains(&column))
+            .or_else(|| on_line().find(|token| token.span.column + token.span.length == column))
+    }
+
+    /// The name token of the procedure called `name`.
+    fn procedure(&self, name: &str) -> Option<&Token> {
+        definitions(self.all_tokens()).find(|token| matches!(&token.kind, TokenKind::Word(word) if word == name))
+    }
+
+    /// `TO name "parameter ...` for the procedure with this name token.
+    fn signature(&self, name: &Token) -> String {
+        let tokens = self.all_tokens();
+        let index = tokens.iter().position(|token| token.span == name.span).unwrap_or(tokens.len());
+        let mut signature = format!("TO {}", name.kind);
+        for parameter in tokens[index + 1..].iter().take_while(|token| matches!(token.kind, TokenKind::Value(_))) {
+            signature.push(' ');
+            signature.push_str(&parameter.kind.to_string());
+        }
+        signature
+    }
+
+    /// Where the variable `name` used at `used` is given a value: a parameter
+    /// of the procedure it is in, or else the first MAKE or FOREACH naming it
+    /// in that procedure, at the top level, or anywhere.
+    fn variable(&self, name: &str, used: &Token) -> Option<&Token> {
+        let named = |token: &&Token| matches!(&token.kind, TokenKind::Value(value) if value == name);
+        let index = self.tokens.iter().position(|token| token.span == used.span)?;
+        let procedures = self.procedures();
+        if let Some(procedure) = procedures.iter().find(|procedure| procedure.contains(&index)) {
+            let found = self.parameters(procedure.start).find(named)
+                .or_else(|| self.variable_definitions(procedure.clone()).find(named));
+            if found.is_some() {
+                return found;
+            }
+        }
+        let top_level = self.variable_definitions(0..self.tokens.len())
+            .filter(|token| {
+                let index = self.tokens.iter().position(|other| other.span == token.span).unwrap_or_default();
+                !procedures.iter().any(|procedure| procedure.contains(&index))
+            })
+            .find(named);
+        top_level.or_else(|| self.variable_definitions(0..self.tokens.len()).find(named))
+    }
+
+    /// The tokens of each procedure in the document, from `TO` to `END`.
+    fn procedures(&self) -> Vec<Indices<usize>> {
+        let mut procedures = Vec::new();
+        let mut start = None;
+        for (index, token) in self.tokens.iter().enumerate() {
+            match &token.kind {
+                TokenKind::Word(word) if word == "TO" => start = Some(index),
+                TokenKind::Word(word) if word == "END" => {
+                    if let Some(start) = start.take() {
+                        procedures.push(start..index + 1);
+                    }
+                },
+                _ => {},
+            }
+        }
+        if let Some(start) = start {
+            procedures.push(start..self.tokens.len());
+        }
+        procedures
+    }
+
+    /// The parameters of the procedure whose `TO` is at `start`.
+    fn parameters(&self, start: usize) -> impl Iterator<Item = &Token> {
+        self.tokens.iter().skip(start + 2).take_while(|token| matches!(token.kind, TokenKind::Value(_)))
+    }
+
+    /// The names given to MAKE and FOREACH among `indices`.
+    fn variable_definitions(&self, indices: Indices<usize>) -> impl Iterator<Item = &Token> {
+        let tokens = &self.tokens[indices];
+        tokens.windows(2)
+            .filter(|pair| matches!(&pair[0].kind, TokenKind::Word(word) if word == "MAKE" || word == "FOREACH"))
+            .map(|pair| &pair[1])
+            .filter(|token| matches!(token.kind, TokenKind::Value(_)))
+    }
+}
+
+/// The name token of every `TO` definition in `tokens`.
+fn definitions(tokens: &[Token]) -> impl Iterator<Item = &Token>
+{
+    tokens.windows(2)
+        .filter(|pair| matches!(&pair[0].kind, TokenKind::Word(to) if to == "TO")
+            && matches!(pair[1].kind, TokenKind::Word(_)))
+        .map(|pair| &pair[1])
+}
+
+/// The LSP range of `span` in `text`, counting characters in UTF-16 as LSP does.
+fn range(text: &str, span: Span) -> Range
+{
+    let line = text.lines().nth(span.line.saturating_sub(1)).unwrap_or_default();
+    let character = |column: usize| line.chars().take(column.saturating_sub(1)).map(char::len_utf16).sum::<usize>() as u32;
+    let line = span.line.saturating_sub(1) as u32;
+    Range::new(Position::new(line, character(span.column)), Position::new(line, character(span.column + span.length)))
+}
+
+/// The file a `file://` URI names.
+pub fn uri_to_path(uri: &Uri) -> Option<PathBuf>
+{
+    let path = uri.as_str().strip_prefix("file://")?;
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| std::str::from_utf8(bytes.get(index + 1..index + 3)?).ok())
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            },
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            },
+        }
+    }
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+/// The `file://` URI of an absolute path.
+pub fn path_to_uri(path: &Path) -> Option<Uri>
+{
+    let mut uri = String::from("file://");
+    for byte in path.to_str()?.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        }
+        else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    uri.parse().ok()
+}
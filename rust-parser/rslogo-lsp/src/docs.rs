@@ -0,0 +1,92 @@
+/// What hovering over a built-in shows.
+pub struct Doc {
+    pub name: &'static str,
+    /// How the built-in is written, with its arguments.
+    pub usage: &'static str,
+    pub about: &'static str,
+}
+
+const fn doc(name: &'static str, usage: &'static str, about: &'static str) -> Doc
+{
+    Doc { name, usage, about }
+}
+
+/// Every command, query, function and word operator in the language.
+pub const DOCS: &[Doc] = &[
+    // Commands
+    doc("PENUP", "PENUP", "Stops the turtle drawing as it moves."),
+    doc("PENDOWN", "PENDOWN", "Makes the turtle draw as it moves."),
+    doc("FORWARD", "FORWARD distance", "Moves the turtle forward along its heading."),
+    doc("BACK", "BACK distance", "Moves the turtle backward, away from its heading."),
+    doc("LEFT", "LEFT distance", "Moves the turtle sideways to its left, without turning."),
+    doc("RIGHT", "RIGHT distance", "Moves the turtle sideways to its right, without turning."),
+    doc(
+        "SETPENCOLOR",
+        "SETPENCOLOR color",
+        "Sets the pen to one of 16 colors, a whole number from 0 to 15: 0 black, 1 blue, 2 cyan, \
+         3 green, 4 red, 5 magenta, 6 yellow, 7 white, 8 brown, 9 tan, 10 forest, 11 aqua, 12 salmon, \
+         13 purple, 14 orange, 15 grey. Any other number is an error.",
+    ),
+    doc("TURN", "TURN degrees", "Turns the turtle clockwise, or anticlockwise for a negative angle."),
+    doc("SETHEADING", "SETHEADING degrees", "Points the turtle at a heading, where 0 is up and 90 is right."),
+    doc("SETX", "SETX x", "Moves the turtle to a new x coordinate."),
+    doc("SETY", "SETY y", "Moves the turtle to a new y coordinate."),
+    doc("MAKE", "MAKE \"name value", "Sets a variable, making it if it does not exist."),
+    doc("ADDASSIGN", "ADDASSIGN \"name number", "Adds a number to a variable."),
+    doc("IF", "IF condition [ statements ]", "Runs the statements if the condition is TRUE."),
+    doc("WHILE", "WHILE condition [ statements ]", "Runs the statements for as long as the condition is TRUE."),
+    doc("TO", "TO name \"parameter ... statements END", "Defines a procedure."),
+    doc("END", "END", "Ends the definition of a procedure."),
+    doc("ARC", "ARC degrees radius", "Draws an arc centred on the turtle, clockwise from its heading."),
+    doc("CIRCLE", "CIRCLE radius", "Draws a circle centred on the turtle."),
+    doc("HOME", "HOME", "Moves the turtle back to where it started, facing up."),
+    doc("REPEAT", "REPEAT count [ statements ]", "Runs the statements a number of times."),
+    doc("FOREACH", "FOREACH \"name list [ statements ]", "Runs the statements once for each item of a list."),
+    doc("SETPENRGB", "SETPENRGB red green blue", "Sets the pen color, each part a whole number from 0 to 255."),
+    doc("SETPENSIZE", "SETPENSIZE width", "Sets the width of the lines drawn."),
+    doc("SETBACKGROUND", "SETBACKGROUND color", "Sets the background to a color number or a [red green blue] list."),
+    doc("BEGINFILL", "BEGINFILL", "Starts recording the turtle's path as a shape to fill. Only one turtle can fill at a time."),
+    doc("ENDFILL", "ENDFILL", "Fills the shape recorded since BEGINFILL with the pen color."),
+    doc("PUSHSTATE", "PUSHSTATE", "Saves the turtle's position, heading and pen."),
+    doc("POPSTATE", "POPSTATE", "Restores what the last PUSHSTATE saved."),
+    doc("NEWTURTLE", "NEWTURTLE name", "Adds a turtle at the starting position, for ASK to use."),
+    doc("ASK", "ASK name [ statements ]", "Runs the statements with another turtle drawing."),
+    doc("LSYSTEM", "LSYSTEM axiom rules depth [ actions ]", "Draws an L-system, rewriting the axiom depth times."),
+    doc("LOAD", "LOAD \"file", "Includes another file, found next to this one."),
+    doc("RERANDOM", "RERANDOM seed", "Restarts RANDOM and PICK from a whole number seed."),
+    // Queries
+    doc("XCOR", "XCOR", "The turtle's x coordinate."),
+    doc("YCOR", "YCOR", "The turtle's y coordinate."),
+    doc("HEADING", "HEADING", "The turtle's heading in degrees."),
+    doc("COLOR", "COLOR", "The pen color."),
+    // Functions
+    doc("FIRST", "FIRST list", "The first item of a list."),
+    doc("BUTFIRST", "BUTFIRST list", "A list without its first item."),
+    doc("ITEM", "ITEM index list", "An item of a list, counting from 1."),
+    doc("COUNT", "COUNT list", "The number of items in a list."),
+    doc("FPUT", "FPUT item list", "A list with an item added at the front."),
+    doc("LPUT", "LPUT item list", "A list with an item added at the end."),
+    doc("SQRT", "SQRT number", "The square root of a number."),
+    doc("SIN", "SIN degrees", "The sine of an angle."),
+    doc("COS", "COS degrees", "The cosine of an angle."),
+    doc("ARCTAN", "ARCTAN number", "The angle in degrees whose tangent is the number."),
+    doc("ABS", "ABS number", "A number without its sign."),
+    doc("INT", "INT number", "A number without its fractional part."),
+    doc("RANDOM", "RANDOM limit", "A whole number from 0 up to, but not including, the limit."),
+    doc("PICK", "PICK list", "An item of a list chosen at random."),
+    doc("NOT", "NOT condition", "TRUE if the condition is FALSE, and FALSE if it is TRUE."),
+    // Operators written as words
+    doc("EQ", "EQ a b", "TRUE if a and b are equal. Also written a = b."),
+    doc("NE", "NE a b", "TRUE if a and b are not equal. Also written a <> b."),
+    doc("GT", "GT a b", "TRUE if a is greater than b. Also written a > b."),
+    doc("LT", "LT a b", "TRUE if a is less than b. Also written a < b."),
+    doc("GE", "GE a b", "TRUE if a is at least b. Also written a >= b."),
+    doc("LE", "LE a b", "TRUE if a is at most b. Also written a <= b."),
+    doc("AND", "AND a b", "TRUE if both conditions are TRUE."),
+    doc("OR", "OR a b", "TRUE if either condition is TRUE."),
+];
+
+pub fn find(name: &str) -> Option<&'static Doc>
+{
+    DOCS.iter().find(|doc| doc.name == name)
+}
@@ -0,0 +1,192 @@
+//! A language server for Logo programs, speaking LSP over stdin and stdout.
+//! It shows the problems `rslogo check` finds, documents built-ins on hover,
+//! goes to where procedures and variables are defined, and completes names.
+//!
+//! Programs are read in the strict dialect unless the server is started with
+//! `--dialect ucb`, as `rslogo` is, or the editor's initialization options
+//! say `{"dialect": "ucb"}`.
+
+mod analysis;
+mod docs;
+
+use std::collections::HashMap;
+use std::error::Error;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    self, DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest, Request as _};
+use lsp_types::{
+    CompletionOptions, CompletionParams, CompletionResponse, GotoDefinitionParams, GotoDefinitionResponse, HoverParams,
+    HoverProviderCapability, OneOf, PublishDiagnosticsParams, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Uri,
+};
+use lib_crate::dialect::Dialect;
+use analysis::Analysis;
+
+type Failure = Box<dyn Error + Send + Sync>;
+
+fn main() -> Result<(), Failure>
+{
+    let mut dialect = dialect_argument(std::env::args().skip(1))?;
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        // Editors send the whole document on every change
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![":".to_string()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let initialize = connection.initialize(serde_json::to_value(capabilities)?)?;
+    if let Some(name) = initialize.pointer("/initializationOptions/dialect").and_then(|name| name.as_str()) {
+        dialect = name.parse()?;
+    }
+
+    let mut server = Server { connection, documents: HashMap::new(), dialect };
+    server.run()?;
+    drop(server);
+    io_threads.join()?;
+    Ok(())
+}
+
+struct Server {
+    connection: Connection,
+    /// The text of every open document.
+    documents: HashMap<Uri, String>,
+    dialect: Dialect,
+}
+
+impl Server {
+    /// Handles messages until the client asks the server to shut down.
+    fn run(&mut self) -> Result<(), Failure> {
+        let receiver = self.connection.receiver.clone();
+        for message in receiver {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    let response = self.respond(request);
+                    self.connection.sender.send(response.into())?;
+                },
+                Message::Notification(notification) => self.notice(notification)?,
+                Message::Response(_) => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn respond(&self, request: Request) -> Response {
+        let id = request.id.clone();
+        let result = match request.method.as_str() {
+            HoverRequest::METHOD => request.extract::<HoverParams>(HoverRequest::METHOD).map(|(_, params)| {
+                let at = params.text_document_position_params;
+                let hover = self.analyse(&at.text_document.uri).and_then(|analysis| analysis.hover(at.position));
+                serde_json::to_value(hover)
+            }),
+            GotoDefinition::METHOD => {
+                request.extract::<GotoDefinitionParams>(GotoDefinition::METHOD).map(|(_, params)| {
+                    let at = params.text_document_position_params;
+                    let location = self.analyse(&at.text_document.uri)
+                        .and_then(|analysis| analysis.definition(at.position, &at.text_document.uri));
+                    serde_json::to_value(location.map(GotoDefinitionResponse::Scalar))
+                })
+            },
+            Completion::METHOD => request.extract::<CompletionParams>(Completion::METHOD).map(|(_, params)| {
+                let at = params.text_document_position;
+                let items = self.analyse(&at.text_document.uri)
+                    .map(|analysis| analysis.completion(at.position))
+                    .unwrap_or_default();
+                serde_json::to_value(CompletionResponse::Array(items))
+            }),
+            method => {
+                return Response::new_err(id, ErrorCode::MethodNotFound as i32, format!("Unknown method {method}"));
+            },
+        };
+        match result {
+            Ok(Ok(value)) => Response::new_ok(id, value),
+            Ok(Err(err)) => Response::new_err(id, ErrorCode::InternalError as i32, err.to_string()),
+            Err(err) => Response::new_err(id, ErrorCode::InvalidParams as i32, err.to_string()),
+        }
+    }
+
+    /// Keeps track of the open documents, checking each as it changes.
+    fn notice(&mut self, notification: Notification) -> Result<(), Failure> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let Some(params) = params::<DidOpenTextDocument>(notification) else {
+                    return Ok(());
+                };
+                let uri = params.text_document.uri;
+                self.documents.insert(uri.clone(), params.text_document.text);
+                self.publish(uri)
+            },
+            DidChangeTextDocument::METHOD => {
+                let Some(params) = params::<DidChangeTextDocument>(notification) else {
+                    return Ok(());
+                };
+                let uri = params.text_document.uri;
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(uri.clone(), change.text);
+                }
+                self.publish(uri)
+            },
+            DidCloseTextDocument::METHOD => {
+                let Some(params) = params::<DidCloseTextDocument>(notification) else {
+                    return Ok(());
+                };
+                let uri = params.text_document.uri;
+                self.documents.remove(&uri);
+                // Clear what was shown for the document
+                self.publish(uri)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn publish(&self, uri: Uri) -> Result<(), Failure> {
+        let diagnostics = self.analyse(&uri).map(|analysis| analysis.diagnostics()).unwrap_or_default();
+        let params = PublishDiagnosticsParams { uri, diagnostics, version: None };
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+        self.connection.sender.send(notification.into())?;
+        Ok(())
+    }
+
+    fn analyse(&self, uri: &Uri) -> Option<Analysis> {
+        let text = self.documents.get(uri)?;
+        Some(Analysis::new(text.clone(), analysis::uri_to_path(uri).as_deref(), self.dialect))
+    }
+}
+
+/// The parameters of a notification, or `None` if they are malformed. There
+/// is no reply to report that in, so it is logged and the server carries on.
+fn params<N: notification::Notification>(notification: Notification) -> Option<N::Params>
+{
+    match serde_json::from_value(notification.params) {
+        Ok(params) => Some(params),
+        Err(err) => {
+            eprintln!("Ignoring {} with malformed parameters: {err}", notification.method);
+            None
+        },
+    }
+}
+
+/// The dialect named by `--dialect name` or `--dialect=name` among `args`,
+/// the last one if there are several, or the strict dialect if there is none.
+fn dialect_argument(mut args: impl Iterator<Item = String>) -> Result<Dialect, Failure>
+{
+    let mut dialect = Dialect::Strict;
+    while let Some(arg) = args.next() {
+        let name = match arg.strip_prefix("--dialect") {
+            Some("") => args.next().ok_or("--dialect needs a value")?,
+            Some(value) if value.starts_with('=') => value[1..].to_string(),
+            _ => return Err(format!("unexpected argument '{arg}'").into()),
+        };
+        dialect = name.parse()?;
+    }
+    Ok(dialect)
+}
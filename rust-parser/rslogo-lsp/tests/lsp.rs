@@ -0,0 +1,280 @@
+//! Talks to `rslogo-lsp` over stdio the way an editor would, checking its
+//! diagnostics, hovers, definitions and completions.
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use serde_json::{json, Value};
+
+const PROGRAM: &str = "\
+TO BOX \"side
+    REPEAT 4 [
+        FORWARD :side
+        TURN 90
+    ]
+END
+
+MAKE \"size 20
+SETPENCOLOR 3
+BOX :size
+SQUARE :size
+";
+
+/// A client for one run of the server.
+struct Client {
+    server: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+impl Client {
+    /// Starts the server and goes through the initialize handshake.
+    fn start() -> Client {
+        Client::start_with(&[], Value::Null)
+    }
+
+    /// Starts the server with `args`, passing `options` as the editor's
+    /// initialization options.
+    fn start_with(args: &[&str], options: Value) -> Client {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_rslogo-lsp"))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("rslogo-lsp runs");
+        let input = server.stdin.take().unwrap();
+        let output = BufReader::new(server.stdout.take().unwrap());
+        let mut client = Client { server, input, output, next_id: 0 };
+
+        let result = client.request("initialize", json!({ "capabilities": {}, "initializationOptions": options }));
+        assert_eq!(result["capabilities"]["hoverProvider"], json!(true));
+        client.notify("initialized", json!({}));
+        client
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(self.input, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+        self.input.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            self.output.read_line(&mut header).expect("server is still running");
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length: ") {
+                length = value.parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        self.output.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Sends a request and returns the result of its response, skipping
+    /// any notifications that come first.
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        loop {
+            let message = self.receive();
+            if message["id"] == json!(id) {
+                assert!(message.get("error").is_none(), "{message}");
+                return message["result"].clone();
+            }
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// Waits for the next diagnostics published.
+    fn diagnostics(&mut self) -> Vec<Value> {
+        loop {
+            let message = self.receive();
+            if message["method"] == "textDocument/publishDiagnostics" {
+                return message["params"]["diagnostics"].as_array().unwrap().clone();
+            }
+        }
+    }
+
+    fn open(&mut self, uri: &str, text: &str) -> Vec<Value> {
+        let document = json!({ "uri": uri, "languageId": "logo", "version": 1, "text": text });
+        self.notify("textDocument/didOpen", json!({ "textDocument": document }));
+        self.diagnostics()
+    }
+
+    /// Sends a request about a position in a document.
+    fn at(&mut self, method: &str, uri: &str, line: u32, character: u32) -> Value {
+        let position = json!({ "textDocument": { "uri": uri }, "position": { "line": line, "character": character } });
+        self.request(method, position)
+    }
+
+    fn stop(mut self) {
+        self.request("shutdown", Value::Null);
+        self.notify("exit", Value::Null);
+        assert!(self.server.wait().unwrap().success());
+    }
+}
+
+/// Writes `files` into a directory for the test, returning the directory
+/// and its `file://` URI.
+fn directory(test: &str, files: &[(&str, &str)]) -> (PathBuf, String)
+{
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join(test);
+    fs::create_dir_all(&directory).expect("temporary directory is writable");
+    for (name, text) in files {
+        fs::write(directory.join(name), text).unwrap();
+    }
+    let directory = directory.canonicalize().unwrap();
+    let uri = format!("file://{}", directory.display());
+    (directory, uri)
+}
+
+#[test]
+fn reports_problems_as_the_document_changes()
+{
+    let mut client = Client::start();
+    let uri = "file:///nowhere/program.lg";
+    assert_eq!(client.open(uri, PROGRAM), Vec::<Value>::new());
+
+    let document = json!({ "uri": uri, "version": 2 });
+    let change = json!({ "textDocument": document, "contentChanges": [{ "text": "FORWARD 10\nBACKWARD 5\n" }] });
+    client.notify("textDocument/didChange", change);
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics.len(), 1, "{diagnostics:?}");
+    assert_eq!(diagnostics[0]["severity"], json!(1));
+    assert_eq!(diagnostics[0]["range"], json!({
+        "start": { "line": 1, "character": 0 },
+        "end": { "line": 1, "character": 8 },
+    }));
+    assert!(diagnostics[0]["message"].as_str().unwrap().contains("BACKWARD"));
+
+    client.notify("textDocument/didClose", json!({ "textDocument": { "uri": uri } }));
+    assert_eq!(client.diagnostics(), Vec::<Value>::new());
+    client.stop();
+}
+
+#[test]
+fn carries_on_after_a_malformed_notification()
+{
+    let mut client = Client::start();
+    client.notify("textDocument/didOpen", json!({ "textDocument": { "uri": 42 } }));
+    client.notify("textDocument/didChange", json!("nonsense"));
+
+    let uri = "file:///nowhere/program.lg";
+    assert_eq!(client.open(uri, PROGRAM), Vec::<Value>::new());
+    let hover = client.at("textDocument/hover", uri, 8, 3);
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("SETPENCOLOR"), "{hover}");
+    client.stop();
+}
+
+#[test]
+fn hovers_document_built_ins_and_procedures()
+{
+    let mut client = Client::start();
+    let uri = "file:///nowhere/program.lg";
+    client.open(uri, PROGRAM);
+
+    let hover = client.at("textDocument/hover", uri, 8, 3);
+    let text = hover["contents"]["value"].as_str().unwrap();
+    assert!(text.contains("SETPENCOLOR color"), "{text}");
+    assert!(text.contains("from 0 to 15"), "{text}");
+
+    let hover = client.at("textDocument/hover", uri, 9, 1);
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("TO BOX \"side"), "{hover}");
+    let hover = client.at("textDocument/hover", uri, 10, 1);
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("From the prelude"), "{hover}");
+
+    // Nothing to say about numbers
+    assert_eq!(client.at("textDocument/hover", uri, 8, 13), Value::Null);
+    client.stop();
+}
+
+#[test]
+fn goes_to_procedures_and_variables()
+{
+    let (directory, base) = directory("definitions", &[
+        ("shapes.lg", "// Shapes\nTO TRIANGLE \"side\n    REPEAT 3 [\n        FORWARD :side\n        TURN 120\n    ]\nEND\n"),
+    ]);
+    let uri = format!("{base}/main.lg");
+    let text = format!("LOAD \"shapes.lg\n{PROGRAM}TRIANGLE 5\n");
+
+    let mut client = Client::start();
+    assert_eq!(client.open(&uri, &text), Vec::<Value>::new());
+    let range = |line: u32, start: u32, end: u32| json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": end },
+    });
+
+    // A call to BOX goes to its name after TO
+    let location = client.at("textDocument/definition", &uri, 10, 0);
+    assert_eq!(location, json!({ "uri": uri, "range": range(1, 3, 6) }));
+
+    // :side inside BOX goes to its parameter, and :size to the MAKE
+    let location = client.at("textDocument/definition", &uri, 3, 19);
+    assert_eq!(location, json!({ "uri": uri, "range": range(1, 7, 12) }));
+    let location = client.at("textDocument/definition", &uri, 10, 6);
+    assert_eq!(location, json!({ "uri": uri, "range": range(8, 5, 10) }));
+
+    // TRIANGLE is in the loaded file, and SQUARE in the prelude, which has no file
+    let location = client.at("textDocument/definition", &uri, 12, 2);
+    let shapes = format!("file://{}", directory.join("shapes.lg").display());
+    assert_eq!(location, json!({ "uri": shapes, "range": range(1, 3, 11) }));
+    assert_eq!(client.at("textDocument/definition", &uri, 11, 2), Value::Null);
+    client.stop();
+}
+
+#[test]
+fn completes_keywords_procedures_and_variables()
+{
+    let mut client = Client::start();
+    let uri = "file:///nowhere/program.lg";
+    let text = format!("{PROGRAM}FORWARD :s\nFO\n");
+    client.open(uri, &text);
+
+    let labels = |items: Value| -> Vec<String> {
+        items.as_array().unwrap().iter().map(|item| item["label"].as_str().unwrap().to_string()).collect()
+    };
+    let words = labels(client.at("textDocument/completion", uri, 12, 2));
+    for word in ["FORWARD", "SETPENCOLOR", "XCOR", "RANDOM", "BOX", "SQUARE", "POLYGON"] {
+        assert!(words.iter().any(|label| label == word), "{word} missing from {words:?}");
+    }
+
+    let variables = labels(client.at("textDocument/completion", uri, 11, 10));
+    assert_eq!(variables, ["side", "size"]);
+    client.stop();
+}
+
+#[test]
+fn reads_ucb_programs_in_the_ucb_dialect()
+{
+    let ucb = "to box :side\n  repeat 4 [fd :side rt 90] ; a square\nend\npd\nBox 20\n";
+    let uri = "file:///nowhere/ucb.lg";
+
+    let mut client = Client::start();
+    assert!(!client.open(uri, ucb).is_empty());
+    client.stop();
+
+    let mut client = Client::start_with(&["--dialect", "ucb"], Value::Null);
+    assert_eq!(client.open(uri, ucb), Vec::<Value>::new());
+    let hover = client.at("textDocument/hover", uri, 1, 14);
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("FORWARD distance"), "{hover}");
+    let hover = client.at("textDocument/hover", uri, 4, 1);
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("TO BOX \"SIDE"), "{hover}");
+    client.stop();
+
+    let mut client = Client::start_with(&[], json!({ "dialect": "ucb" }));
+    assert_eq!(client.open(uri, ucb), Vec::<Value>::new());
+    client.stop();
+}
@@ -0,0 +1,439 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use lib_crate::{bytecode, lexer, load, parser, utils};
+use lib_crate::bytecode::InstructionKind;
+use lib_crate::canvas::{create_canvas, hex, Canvas};
+use lib_crate::load::Sources;
+use lib_crate::structs::{Cursor, Environment, Expression, Value};
+use lib_crate::vm::Vm;
+use crate::{check_dimensions, output_format, save_image, DebugArgs};
+
+const HELP: &str = "\
+Commands:
+  break LINE | PROC   stop before LINE runs, or when PROC is called (b)
+  delete LINE | PROC  remove a breakpoint
+  breakpoints         list breakpoints and watches
+  step                run one statement, stepping into procedure calls (s)
+  next                run one statement, stepping over procedure calls (n)
+  finish              run until the current procedure returns
+  continue            run until a breakpoint, a watch changes or the end (c)
+  print EXPR          show the value of an expression, such as :size (p)
+  watch EXPR          show EXPR at every stop, and stop when it changes
+  unwatch N           remove watch number N
+  cursor              show the turtle's position, heading and pen
+  vars                show the variables in scope
+  list                show the source around the current line (l)
+  snapshot [PATH]     save the drawing so far
+  help                show this message
+  quit                exit (as does end of input) (q)";
+
+/// How far to run before stopping again.
+#[derive(Clone, Copy)]
+enum Run {
+    Step,
+    /// Until a statement at or above this frame depth.
+    Next(usize),
+    /// Until a statement below this frame depth.
+    Finish(usize),
+    Continue,
+}
+
+/// An expression shown at every stop, along with the last value it had.
+struct Watch {
+    text: String,
+    expression: Expression,
+    value: String,
+}
+
+struct Debugger {
+    /// The program and every file it loads, the program being file 0.
+    sources: Sources,
+    image_path: PathBuf,
+    /// Save the drawing to `image_path` whenever a breakpoint is hit.
+    snapshot: bool,
+    canvas: Box<dyn Canvas>,
+    cursor: Cursor,
+    env: Environment,
+    vm: Vm,
+    line_breaks: BTreeSet<usize>,
+    procedure_breaks: BTreeSet<String>,
+    watches: Vec<Watch>,
+    /// Set once the program has been resumed for the first time.
+    started: bool,
+    /// Set once the program has finished or failed.
+    done: bool,
+}
+
+impl Debugger {
+    /// Runs instructions until `run` says to stop, a breakpoint is hit, a
+    /// watch changes, or the program ends.
+    fn resume(&mut self, run: Run) {
+        if self.done {
+            println!("The program has finished");
+            return;
+        }
+
+        // Nothing has run yet, so the first statement is still to be checked
+        if !std::mem::replace(&mut self.started, true) && !matches!(run, Run::Step) && self.at_statement() {
+            let (file, line) = self.line();
+            if file == 0 && self.line_breaks.contains(&line) {
+                self.announce(&format!("Breakpoint at line {line}"));
+                self.show_location();
+                self.show_watches();
+                return;
+            }
+        }
+
+        // The procedure with a breakpoint being entered, and its frame depth
+        let mut entered: Option<(String, usize)> = None;
+        loop {
+            let before = self.vm.depth();
+            let calling = match self.vm.current().map(|instruction| &instruction.kind) {
+                Some(InstructionKind::Call(name, _)) if self.procedure_breaks.contains(name) => Some(name.clone()),
+                _ => None,
+            };
+            if let Err(err) = self.vm.step(self.canvas.as_mut(), &mut self.cursor, &mut self.env) {
+                eprint!("{}", self.sources.render(&err));
+                println!("The program stopped with an error");
+                self.done = true;
+                return;
+            }
+            if self.vm.is_finished() {
+                println!("The program has finished");
+                self.done = true;
+                if let Err(err) = save_image(self.canvas.as_ref(), &self.image_path) {
+                    eprintln!("{err}");
+                }
+                return;
+            }
+
+            let depth = self.vm.depth();
+            if let Some(name) = calling.filter(|_| depth > before) {
+                entered = Some((name, depth));
+            }
+            // A call that returns without running a statement is not stopped in
+            if entered.as_ref().is_some_and(|(_, frame)| depth < *frame) {
+                entered = None;
+            }
+            if !self.at_statement() {
+                continue;
+            }
+            let (file, line) = self.line();
+            let changed = self.update_watches();
+
+            let reason = if let Some((name, _)) = entered.take_if(|(_, frame)| *frame == depth) {
+                Some(format!("Breakpoint at procedure {name}"))
+            }
+            else if file == 0 && self.line_breaks.contains(&line) && !matches!(run, Run::Step) {
+                Some(format!("Breakpoint at line {line}"))
+            }
+            else if !changed.is_empty() {
+                Some(changed.join("\n"))
+            }
+            else {
+                None
+            };
+            if let Some(reason) = reason {
+                self.announce(&reason);
+                break;
+            }
+
+            let stop = match run {
+                Run::Step => true,
+                Run::Next(from) => depth <= from,
+                Run::Finish(from) => depth < from,
+                Run::Continue => false,
+            };
+            if stop {
+                break;
+            }
+        }
+        self.show_location();
+        self.show_watches();
+    }
+
+    /// Whether the next instruction starts a statement, where the program can stop.
+    fn at_statement(&self) -> bool {
+        self.vm.current().is_some_and(|instruction| instruction.kind.is_statement())
+    }
+
+    /// Says why the program stopped, saving a snapshot at a breakpoint if asked to.
+    fn announce(&self, reason: &str) {
+        println!("{reason}");
+        if self.snapshot && reason.starts_with("Breakpoint") {
+            self.save_snapshot(&self.image_path);
+        }
+    }
+
+    /// The file and source line of the statement that will run next.
+    fn line(&self) -> (usize, usize) {
+        self.vm.current().map(|instruction| (instruction.span.file, instruction.span.line)).unwrap_or_default()
+    }
+
+    /// The text of file `file`, which is only named when it is not the program.
+    fn source(&self, file: usize) -> (&str, Option<&str>) {
+        match self.sources.get(file) {
+            Some(source) => (&source.text, (file != 0).then_some(source.path.as_str())),
+            None => ("", None),
+        }
+    }
+
+    fn show_location(&self) {
+        let (file, line) = self.line();
+        let (source, path) = self.source(file);
+        if let Some(path) = path {
+            println!("In {path}");
+        }
+        let text = source.lines().nth(line.saturating_sub(1)).unwrap_or_default();
+        println!("{line:>4} | {}", text.trim_end());
+    }
+
+    fn list(&self) {
+        let (file, line) = self.line();
+        let (source, path) = self.source(file);
+        if let Some(path) = path {
+            println!("In {path}");
+        }
+        let first = line.saturating_sub(5).max(1);
+        for (index, text) in source.lines().enumerate().skip(first - 1).take(11) {
+            let marker = if index + 1 == line { "->" } else { "  " };
+            println!("{marker}{:>4} | {}", index + 1, text.trim_end());
+        }
+    }
+
+    /// Parses `text` as an expression and evaluates it in the current scope.
+    fn evaluate(&mut self, text: &str) -> Result<Value, String> {
+        let expression = parse(text)?;
+        let cursor = &self.cursor;
+        self.env.keeping_random(|env| utils::evaluate(&expression, cursor, env)).map_err(|err| err.to_string())
+    }
+
+    fn add_watch(&mut self, text: &str) {
+        match parse(text) {
+            Ok(expression) => {
+                let mut watch = Watch { text: text.to_string(), expression, value: String::new() };
+                watch.value = watch_value(&watch.expression, &self.cursor, &mut self.env);
+                println!("Watch {}: {} = {}", self.watches.len() + 1, watch.text, watch.value);
+                self.watches.push(watch);
+            },
+            Err(err) => println!("{err}"),
+        }
+    }
+
+    /// Evaluates every watch again, describing those whose value changed.
+    fn update_watches(&mut self) -> Vec<String> {
+        let mut changed = Vec::new();
+        for (index, watch) in self.watches.iter_mut().enumerate() {
+            let value = watch_value(&watch.expression, &self.cursor, &mut self.env);
+            if value != watch.value {
+                changed.push(format!("Watch {}: {} changed from {} to {}", index + 1, watch.text, watch.value, value));
+                watch.value = value;
+            }
+        }
+        changed
+    }
+
+    fn show_watches(&self) {
+        for (index, watch) in self.watches.iter().enumerate() {
+            println!("  watch {}: {} = {}", index + 1, watch.text, watch.value);
+        }
+    }
+
+    fn show_breakpoints(&self) {
+        if self.line_breaks.is_empty() && self.procedure_breaks.is_empty() && self.watches.is_empty() {
+            println!("No breakpoints or watches");
+            return;
+        }
+        for line in &self.line_breaks {
+            println!("  line {line}");
+        }
+        for name in &self.procedure_breaks {
+            println!("  procedure {name}");
+        }
+        self.show_watches();
+    }
+
+    fn show_cursor(&self) {
+        let cursor = &self.cursor;
+        println!("  x: {}", self.env.axes.x_from_canvas(cursor.x_coord));
+        println!("  y: {}", self.env.axes.y_from_canvas(cursor.y_coord));
+        println!("  heading: {}", cursor.direction);
+        println!("  pen: {:?}", cursor.pen_status);
+        println!("  color: {}", hex(cursor.pen_color));
+        println!("  size: {}", cursor.pen_size);
+        println!("  filling: {}", cursor.fill.is_some());
+    }
+
+    fn show_variables(&self) {
+        let mut locals = self.env.locals();
+        let mut globals = self.env.globals();
+        if locals.is_empty() && globals.is_empty() {
+            println!("No variables");
+            return;
+        }
+        locals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, value) in locals {
+            println!("  :{name} = {value} (local)");
+        }
+        for (name, value) in globals {
+            println!("  :{name} = {value}");
+        }
+    }
+
+    fn save_snapshot(&self, path: &Path) {
+        match save_image(self.canvas.as_ref(), path) {
+            Ok(()) => println!("Saved the drawing to {}", path.display()),
+            Err(err) => eprintln!("{err}"),
+        }
+    }
+
+    /// Adds or removes a breakpoint on a line number or procedure name.
+    fn set_breakpoint(&mut self, target: &str, add: bool) {
+        let changed = match target.parse::<usize>() {
+            Ok(line) if add => self.line_breaks.insert(line),
+            Ok(line) => self.line_breaks.remove(&line),
+            Err(_) if add && !self.env.procedures.contains_key(target) => {
+                println!("No procedure named {target}");
+                return;
+            },
+            Err(_) if add => self.procedure_breaks.insert(target.to_string()),
+            Err(_) => self.procedure_breaks.remove(target),
+        };
+        match (add, changed) {
+            (true, true) => println!("Breakpoint set at {target}"),
+            (true, false) => println!("There is already a breakpoint at {target}"),
+            (false, true) => println!("Breakpoint at {target} removed"),
+            (false, false) => println!("No breakpoint at {target}"),
+        }
+    }
+}
+
+/// The value of a watch, or the error evaluating it, such as a variable not
+/// having been made yet. RANDOM and PICK in a watch leave the program's
+/// random numbers as they were.
+fn watch_value(expression: &Expression, cursor: &Cursor, env: &mut Environment) -> String
+{
+    match env.keeping_random(|env| utils::evaluate(expression, cursor, env)) {
+        Ok(value) => value.to_string(),
+        Err(err) => format!("<{}>", err.kind),
+    }
+}
+
+fn parse(text: &str) -> Result<Expression, String>
+{
+    parser::parse_expression(&lexer::tokenize(text)).map_err(|err| err.to_string())
+}
+
+pub fn run(args: DebugArgs) -> Result<(), i32>
+{
+    let (width, height) = (args.width, args.height);
+    check_dimensions(width, height)?;
+    let format = output_format(&args.image_path, args.format)?;
+
+    let source = match std::fs::read_to_string(&args.file_path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("Error opening file: {err}");
+            return Err(1);
+        }
+    };
+    let mut sources = Sources::default();
+    let program = match load::expand(&source, &args.file_path, !args.no_prelude, args.dialect, &mut sources).and_then(|tokens| parser::parse(&tokens)) {
+        Ok(program) => bytecode::compile(program),
+        Err(err) => {
+            eprint!("{}", sources.render(&err));
+            return Err(1);
+        }
+    };
+
+    let mut env = Environment::new(program.procedures);
+    if let Some(seed) = args.seed {
+        env.seed_random(seed);
+    }
+    let mut debugger = Debugger {
+        sources,
+        image_path: args.image_path,
+        snapshot: args.snapshot,
+        canvas: create_canvas(format, width, height, false),
+        cursor: Cursor::new((width / 2) as f32, (height / 2) as f32),
+        env,
+        started: false,
+        done: program.main.is_empty(),
+        vm: Vm::new(program.main),
+        line_breaks: BTreeSet::new(),
+        procedure_breaks: BTreeSet::new(),
+        watches: Vec::new(),
+    };
+
+    println!("{HELP}");
+    if debugger.done {
+        println!("The program is empty");
+    }
+    else {
+        debugger.show_location();
+    }
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(debug) ");
+        let _ = io::stdout().flush();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        let line = line.trim();
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+
+        match (command, argument) {
+            ("", _) => {},
+            ("step" | "s", "") => debugger.resume(Run::Step),
+            ("next" | "n", "") => {
+                let depth = debugger.vm.depth();
+                debugger.resume(Run::Next(depth));
+            },
+            ("finish", "") => {
+                let depth = debugger.vm.depth();
+                if depth <= 1 {
+                    println!("Not inside a procedure");
+                }
+                else {
+                    debugger.resume(Run::Finish(depth));
+                }
+            },
+            ("continue" | "c", "") => debugger.resume(Run::Continue),
+            ("break" | "b", target) if !target.is_empty() => debugger.set_breakpoint(target, true),
+            ("delete", target) if !target.is_empty() => debugger.set_breakpoint(target, false),
+            ("breakpoints", "") => debugger.show_breakpoints(),
+            ("print" | "p", text) if !text.is_empty() => match debugger.evaluate(text) {
+                Ok(value) => println!("{value}"),
+                Err(err) => println!("{err}"),
+            },
+            ("watch", text) if !text.is_empty() => debugger.add_watch(text),
+            ("unwatch", number) => match number.parse::<usize>() {
+                Ok(number) if (1..=debugger.watches.len()).contains(&number) => {
+                    let watch = debugger.watches.remove(number - 1);
+                    println!("Removed watch {}", watch.text);
+                },
+                _ => println!("No watch numbered {number}"),
+            },
+            ("cursor", "") => debugger.show_cursor(),
+            ("vars", "") => debugger.show_variables(),
+            ("list" | "l", "") => debugger.list(),
+            ("snapshot", path) => {
+                let path = if path.is_empty() { debugger.image_path.clone() } else { PathBuf::from(path) };
+                debugger.save_snapshot(&path);
+            },
+            ("help", "") => println!("{HELP}"),
+            ("quit" | "q", "") => break,
+            _ => println!("Unknown command, type help for a list"),
+        }
+    }
+
+    Ok(())
+}
@@ -1,165 +1,467 @@
-use std::collections::HashMap;
-use unsvg::Image;
-use std::fs::File;
-use std::hash::Hash;
-use clap::Parser;
-use lib_crate::utils;
-use std::io::{BufRead, BufReader};
-use lib_crate::structs::{Cursor};
+mod debug;
+mod repl;
 
-/// A simple program to parse four arguments using clap.
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use clap::{Parser, Subcommand};
+use lib_crate::{bytecode, check, format, load, parser};
+use lib_crate::animate::{AnimatedCanvas, Animation};
+use lib_crate::canvas::{create_canvas, Axes, Canvas, Format, Origin, YAxis};
+use lib_crate::fit::FitCanvas;
+use lib_crate::interpreter::Interpreter;
+use lib_crate::limits::Limits;
+use lib_crate::dialect::Dialect;
+use lib_crate::load::Sources;
+use lib_crate::trace::{Level, TraceFormat, Tracer};
 
+/// Runs a Logo program and saves what the turtle drew.
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args
+{
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub run: Option<RunArgs>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct RunArgs
 {
     /// Path to a file
-    pub file_path: std::path::PathBuf,
-    /// Path to an svg or png image
-    pub image_path: std::path::PathBuf,
+    pub file_path: PathBuf,
+    /// Path to the output, or - to print text formats
+    pub image_path: PathBuf,
     /// Height
     pub height: u32,
     /// Width
     pub width: u32,
+    /// Output format: svg, png, unsvg, ascii, json or gcode. Chosen from the
+    /// extension of the output path if not given
+    #[arg(long)]
+    pub format: Option<Format>,
+    /// Put each pen color on its own layer in svg and png output
+    #[arg(long)]
+    pub layers: bool,
+    /// Print an event to stderr for every statement executed
+    #[arg(long)]
+    pub trace: bool,
+    /// Format of trace events and log messages: text or json
+    #[arg(long, default_value = "text")]
+    pub trace_format: TraceFormat,
+    /// Most verbose log messages to print: error, warn, info, debug or trace
+    #[arg(long, default_value = "warn")]
+    pub log_level: Level,
+    /// Seed for RANDOM, so that a program draws the same thing on every run
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Save an SVG in which each line appears in the order it was drawn
+    #[arg(long, conflicts_with = "frames")]
+    pub animate: bool,
+    /// Lines drawn per second in --animate output
+    #[arg(long, default_value = "50", value_parser = parse_speed)]
+    pub speed: f32,
+    /// Save a numbered PNG of the drawing so far after every FRAMES lines,
+    /// such as out_0001.png, instead of a single image
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub frames: Option<u64>,
+    /// Where XCOR and YCOR are zero: top-left or center
+    #[arg(long, default_value = "top-left")]
+    pub origin: Origin,
+    /// Which way YCOR and SETY increase: down or up
+    #[arg(long, default_value = "down")]
+    pub y_axis: YAxis,
+    /// Scale and move the drawing so that all of it fits in the image
+    #[arg(long)]
+    pub fit: bool,
+    /// Print the compiled instructions instead of running the program
+    #[arg(long)]
+    pub dump_bytecode: bool,
+    /// Leave out the procedures every program can otherwise use, such as SQUARE
+    #[arg(long)]
+    pub no_prelude: bool,
+    /// Syntax the program is written in: strict, or ucb for UCBLogo's
+    /// lowercase keywords, abbreviations such as FD and :name parameters
+    #[arg(long, default_value = "strict")]
+    pub dialect: Dialect,
+    /// Stop the program after it runs this many instructions
+    #[arg(long)]
+    pub max_steps: Option<u64>,
+    /// Most procedure calls in progress at once [default: 1000]
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+    /// Stop the program after it draws this many lines
+    #[arg(long)]
+    pub max_segments: Option<u64>,
+    /// Largest distance from zero the turtle may reach on either axis [default: 1000000]
+    #[arg(long)]
+    pub max_coordinate: Option<f32>,
+    /// Stop the program after it runs for this many seconds
+    #[arg(long, value_parser = parse_seconds)]
+    pub max_time: Option<Duration>,
+    /// Use limits suited to programs that are not trusted, for any limit not
+    /// given. When the program goes over a limit, the drawing so far is saved
+    #[arg(long)]
+    pub sandbox: bool,
+}
+
+impl RunArgs {
+    /// The limits given on the command line, falling back to the sandbox or
+    /// default ones.
+    fn limits(&self) -> Limits {
+        let base = if self.sandbox { Limits::sandbox() } else { Limits::default() };
+        Limits {
+            max_steps: self.max_steps.or(base.max_steps),
+            max_depth: self.max_depth.unwrap_or(base.max_depth),
+            max_segments: self.max_segments.or(base.max_segments),
+            max_coordinate: self.max_coordinate.unwrap_or(base.max_coordinate),
+            max_time: self.max_time.or(base.max_time),
+        }
+    }
+}
+
+fn parse_speed(s: &str) -> Result<f32, String>
+{
+    match s.parse::<f32>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("'{s}' is not a number of lines per second greater than zero")),
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String>
+{
+    let seconds: f64 = s.parse().map_err(|_| format!("'{s}' is not a number of seconds"))?;
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("'{s}' is not a number of seconds"))
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ReplArgs
+{
+    /// Path to the output
+    pub image_path: PathBuf,
+    /// Height
+    pub height: u32,
+    /// Width
+    pub width: u32,
+    /// Output format, as for running a file
+    #[arg(long)]
+    pub format: Option<Format>,
+    /// Put each pen color on its own layer in svg and png output
+    #[arg(long)]
+    pub layers: bool,
+    /// Seed for RANDOM
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Leave out the procedures every program can otherwise use
+    #[arg(long)]
+    pub no_prelude: bool,
+    /// Syntax the programs are written in, as for running a file
+    #[arg(long, default_value = "strict")]
+    pub dialect: Dialect,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DebugArgs
+{
+    /// Path to a file
+    pub file_path: PathBuf,
+    /// Path to the output, written when the program finishes
+    pub image_path: PathBuf,
+    /// Height
+    pub height: u32,
+    /// Width
+    pub width: u32,
+    /// Output format, as for running a file
+    #[arg(long)]
+    pub format: Option<Format>,
+    /// Save the drawing so far to the output path whenever a breakpoint is hit
+    #[arg(long)]
+    pub snapshot: bool,
+    /// Seed for RANDOM
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Leave out the procedures every program can otherwise use
+    #[arg(long)]
+    pub no_prelude: bool,
+    /// Syntax the program is written in: strict, or ucb for UCBLogo's
+    /// lowercase keywords, abbreviations such as FD and :name parameters
+    #[arg(long, default_value = "strict")]
+    pub dialect: Dialect,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command
+{
+    /// Read statements from stdin, saving the image after each one
+    Repl(ReplArgs),
+    /// Run a program one statement at a time, reading debugger commands from stdin
+    Debug(DebugArgs),
+    /// Check programs for problems without running them. Exits with 1 if
+    /// any file has an error, or a warning with --deny-warnings, and 0 otherwise
+    Check {
+        /// Paths to the files to check
+        #[arg(required = true)]
+        file_paths: Vec<PathBuf>,
+        /// Fail on warnings as well as errors
+        #[arg(long)]
+        deny_warnings: bool,
+        /// Leave out the procedures every program can otherwise use
+        #[arg(long)]
+        no_prelude: bool,
+        /// Syntax the programs are written in, as for running a file
+        #[arg(long, default_value = "strict")]
+        dialect: Dialect,
+    },
+    /// Rewrite programs with consistent indentation, keyword casing and
+    /// bracket placement, keeping their comments
+    Fmt {
+        /// Paths to the files to format
+        #[arg(required = true)]
+        file_paths: Vec<PathBuf>,
+        /// Only list the files that are not formatted, exiting with 1 if there are any
+        #[arg(long)]
+        check: bool,
+        /// Leave out the procedures every program can otherwise use
+        #[arg(long)]
+        no_prelude: bool,
+        /// Syntax the programs are written in, as for running a file
+        #[arg(long, default_value = "strict")]
+        dialect: Dialect,
+    },
 }
 
 fn main() -> Result<(), i32>
 {
     let args: Args = Args::parse();
 
+    match (args.command, args.run) {
+        (Some(Command::Repl(repl)), _) => repl::run(repl),
+        (Some(Command::Debug(debug)), _) => debug::run(debug),
+        (Some(Command::Check { file_paths, deny_warnings, no_prelude, dialect }), _) => {
+            check_files(&file_paths, deny_warnings, !no_prelude, dialect)
+        },
+        (Some(Command::Fmt { file_paths, check, no_prelude, dialect }), _) => {
+            format_files(&file_paths, check, !no_prelude, dialect)
+        },
+        (None, Some(run)) => run_file(run),
+        (None, None) => {
+            eprintln!("Expected a program to run");
+            Err(1)
+        }
+    }
+}
+
+fn run_file(args: RunArgs) -> Result<(), i32>
+{
     // Access the parsed arguments
+    let limits = args.limits();
     let file_path = args.file_path;
     let image_path = args.image_path;
     let height = args.height;
     let width = args.width;
 
-    let mut image = Image::new(width, height);
-    let mut cursor = Cursor::new((width / 2) as f32, (height / 2) as f32);
-    let mut variables: HashMap<String, f32> = HashMap::new();
-    let mut return_map: HashMap<usize, usize> = HashMap::new();
-
-    // Work line by line, parsing then executing program
-    let file = match File::open(&file_path) {
-        Ok(f) => f,
+    let source = match std::fs::read_to_string(&file_path) {
+        Ok(source) => source,
         Err(err) => {
             eprintln!("Error opening file: {err}");
             return Err(1);
         }
     };
 
-    let reader = BufReader::new(file);
-
-    let lines = reader.lines().map(|l| l.unwrap()).collect::<Vec<_>>();
-    let mut line_number = 0;
-    while line_number < lines.len()
-    {
-        let line = lines[line_number].trim();
-        println!("Processing line: {line}");
-        if line.starts_with("IF") {
-            match utils::check_condition(line.strip_prefix("IF ").unwrap(), &mut cursor, &mut variables) {
-                Ok((result, adv)) => {
-                    if result {
-                        println!("CONDITION IS TRUE");
-                        line_number = line_number + 1;
-                        continue;
-                    }
-                    else {
-                        println!("CONDITION IS FALSE");
-                        println!("Start line: {line_number}");
-                        line_number = match utils::jump_to_matching_bracket(line_number + 1, &lines) {
-                            Ok(line_number) => line_number,
-                            Err(err) => {
-                                eprintln!("{err}");
-                                return Err(1)
-                            }
-                        };
-                        println!("After jumping line: {line_number}");
-                        continue
-                    }
-                }
-                Err(err) => {
-                    eprintln!("{err}");
-                    return Err(1)
-                }
-            }
+    // Load, parse and compile the whole program up front, then run the instructions
+    let mut sources = Sources::default();
+    let tokens = load::expand(&source, &file_path, !args.no_prelude, args.dialect, &mut sources);
+    let program = match tokens.and_then(|tokens| parser::parse(&tokens)) {
+        Ok(program) => program,
+        Err(err) => {
+            eprint!("{}", sources.render(&err));
+            return Err(1)
         }
-        else if line.starts_with("WHILE") {
-            match utils::check_condition(line.strip_prefix("WHILE ").unwrap(), &mut cursor, &mut variables) {
-                Ok((result, adv)) => {
-                    if result {
-                        println!("CONDITION IS TRUE");
-                        // Add return line number
-                        let return_line = match utils::jump_to_matching_bracket(line_number + 1, &lines) {
-                            Ok(line) => line - 1,
-                            Err(err) => {
-                                eprintln!("{err}");
-                                return Err(1)
-                            }
-                        };
-                        println!("adding return line {return_line} for statement on line {line_number}");
-                        return_map.insert(utils::jump_to_matching_bracket(line_number + 1, &lines).unwrap() - 1, line_number);
-                        line_number = line_number + 1;
-                        continue;
-                    }
-                    else {
-                        println!("CONDITION IS FALSE");
-                        line_number = match utils::jump_to_matching_bracket(line_number + 1, &lines) {
-                            Ok(line) => line,
-                            Err(err) => {
-                                eprintln!("{err}");
-                                return Err(1)
-                            }
-                        };
-                        continue
-                    }
-                }
-                Err(err) => {
-                    eprintln!("{err}");
-                    return Err(1)
-                }
-            }
+    };
+
+    let program = bytecode::compile(program);
+    if args.dump_bytecode {
+        print!("{}", bytecode::dump(&program, &sources));
+        return Ok(());
+    }
+
+    check_dimensions(width, height)?;
+    let format = output_format(&image_path, args.format)?;
+
+    let animation = match (args.animate, args.frames) {
+        (true, _) if format != Format::Svg => {
+            eprintln!("--animate only writes svg");
+            return Err(1);
+        },
+        (true, _) => Some(Animation::Smil { speed: args.speed }),
+        (_, Some(_)) if format != Format::Png => {
+            eprintln!("--frames only writes png");
+            return Err(1);
+        },
+        (_, Some(every)) => Some(Animation::Frames { every: every as usize }),
+        (false, None) => None,
+    };
+    let layers = args.layers;
+    let make = move || -> Box<dyn Canvas> {
+        match animation {
+            Some(animation) => Box::new(AnimatedCanvas::new(width, height, animation)),
+            None => create_canvas(format, width, height, layers),
         }
-        else if line.starts_with(']') {
-            // Check if this is the end of a while loop
-            if return_map.contains_key(&line_number) {
-                line_number = *return_map.get(&line_number).expect("Key exists");
+    };
+    let canvas = if args.fit { Box::new(FitCanvas::new(Box::new(make))) } else { make() };
+
+    let mut logo = Interpreter::new(canvas);
+    logo.set_axes(Axes::new(args.origin, args.y_axis, width, height));
+    logo.set_tracer(Tracer::new(args.log_level, args.trace_format, args.trace));
+    if let Some(seed) = args.seed {
+        logo.seed_random(seed);
+    }
+    logo.set_limits(limits);
+    logo.load_program(program);
+    if let Err(err) = logo.run() {
+        eprint!("{}", sources.render(&err));
+        // What was drawn before a limit was hit shows what the program was doing
+        if err.kind.is_limit() {
+            match save_image(logo.canvas(), &image_path) {
+                Ok(()) => eprintln!("Saved the drawing so far to {}", image_path.display()),
+                Err(err) => eprintln!("{err}"),
             }
-            else {
-                line_number = line_number + 1;
+        }
+        return Err(1)
+    }
+
+    if let Err(err) = save_image(logo.canvas(), &image_path) {
+        eprintln!("{err}");
+        return Err(1);
+    }
+
+    Ok(())
+}
+
+/// Prints every problem in each file, then a summary line for the file.
+fn check_files(file_paths: &[PathBuf], deny_warnings: bool, prelude: bool, dialect: Dialect) -> Result<(), i32>
+{
+    let mut failed = false;
+    for file_path in file_paths {
+        let path = file_path.display().to_string();
+        let source = match std::fs::read_to_string(file_path) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("Error opening {path}: {err}");
+                failed = true;
+                continue;
             }
+        };
+
+        let mut sources = Sources::default();
+        let diagnostics = match load::expand(&source, file_path, prelude, dialect, &mut sources) {
+            Ok(tokens) => check::check(&tokens),
+            Err(err) => vec![check::Diagnostic { severity: check::Severity::Error, error: err }],
+        };
+        for diagnostic in &diagnostics {
+            eprint!("{}", sources.render_as(diagnostic.severity.label(), &diagnostic.error));
+        }
+        let errors = diagnostics.iter().filter(|d| d.severity == check::Severity::Error).count();
+        let warnings = diagnostics.len() - errors;
+        if diagnostics.is_empty() {
+            println!("{path}: ok");
         }
         else {
-            match utils::handle_line(line, &mut image, &mut cursor, &mut variables) {
-                Ok(_) => {},
-                Err(err) => {
-                    eprintln!("{err}");
-                    return Err(1)
-                }
-            }
-            line_number = line_number + 1;
+            println!("{path}: {}, {}", plural(errors, "error"), plural(warnings, "warning"));
         }
+        failed |= errors > 0 || (deny_warnings && warnings > 0);
     }
 
+    if failed {
+        return Err(1);
+    }
+    Ok(())
+}
 
-    match image_path.extension().map(|s| s.to_str()).flatten() {
-        Some("svg") => {
-            let res = image.save_svg(&image_path);
-            if let Err(e) = res {
-                eprintln!("Error saving svg: {e}");
-                return Err(1);
+/// Formats each file in place, or with `check` only reports the files that
+/// formatting would change. Files that do not parse are left alone.
+fn format_files(file_paths: &[PathBuf], check: bool, prelude: bool, dialect: Dialect) -> Result<(), i32>
+{
+    let mut failed = false;
+    for file_path in file_paths {
+        let path = file_path.display().to_string();
+        let source = match std::fs::read_to_string(file_path) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("Error opening {path}: {err}");
+                failed = true;
+                continue;
             }
-        }
-        Some("png") => {
-            let res = image.save_png(&image_path);
-            if let Err(e) = res {
-                eprintln!("Error saving png: {e}");
-                return Err(1);
+        };
+
+        // The procedures the file loads have to be known to parse calls to
+        // them, while the file itself is parsed once its keywords are fixed
+        let mut sources = Sources::default();
+        let formatted = load::expand(&source, file_path, prelude, dialect, &mut sources)
+            .map(|tokens| tokens.into_iter().filter(|token| token.span.file != 0).collect::<Vec<_>>())
+            .and_then(|loaded| parser::parse(&loaded))
+            .and_then(|loaded| format::format_source(&source, &loaded.arities(), dialect));
+        let formatted = match formatted {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                eprint!("{}", sources.render(&err));
+                failed = true;
+                continue;
             }
+        };
+        if formatted == source {
+            continue;
         }
-        _ => {
-            eprintln!("File extension not supported");
-            return Err(1);
+        if check {
+            println!("{path}: not formatted");
+            failed = true;
         }
+        else if let Err(err) = std::fs::write(file_path, formatted) {
+            eprintln!("Error saving {path}: {err}");
+            failed = true;
+        }
+    }
+
+    if failed {
+        return Err(1);
     }
+    Ok(())
+}
+
+fn plural(count: usize, noun: &str) -> String
+{
+    if count == 1 { format!("1 {noun}") } else { format!("{count} {noun}s") }
+}
 
+/// A drawing cannot have a zero-length side.
+pub fn check_dimensions(width: u32, height: u32) -> Result<(), i32>
+{
+    if width == 0 || height == 0 {
+        eprintln!("Image dimensions must be greater than zero");
+        return Err(1);
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// The format given by `--format`, or else by the extension of `image_path`.
+pub fn output_format(image_path: &Path, format: Option<Format>) -> Result<Format, i32>
+{
+    match format.or_else(|| Format::from_path(image_path)) {
+        Some(format) => Ok(format),
+        None => {
+            eprintln!("File extension not supported, use --format to choose one");
+            Err(1)
+        },
+    }
+}
+
+/// Saves the drawing to `image_path`, or prints it if the path is `-`.
+pub fn save_image(canvas: &dyn Canvas, image_path: &Path) -> Result<(), String>
+{
+    if image_path == Path::new("-") {
+        let text = canvas.to_text().ok_or("This format cannot be printed, save it to a file instead")?;
+        print!("{text}");
+        return Ok(());
+    }
+    canvas.save(image_path).map_err(|e| format!("Error saving {}: {e}", image_path.display()))
+}
@@ -0,0 +1,219 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use lib_crate::{bytecode, load, parser, vm};
+use lib_crate::bytecode::CompiledProgram;
+use lib_crate::errors::{ErrorKind, LogoError};
+use lib_crate::canvas::{create_canvas, Canvas, Format};
+use lib_crate::dialect::Dialect;
+use lib_crate::load::Sources;
+use lib_crate::structs::{Cursor, Environment};
+use crate::{check_dimensions, output_format, save_image, ReplArgs};
+
+const HELP: &str = "\
+Enter Logo statements to run them. The image is saved after each one.
+Meta-commands:
+  .reset   clear the drawing, variables and procedures
+  .undo    undo the last statement
+  .vars    show global variables
+  .help    show this message
+  .quit    exit (as does end of input)";
+
+/// Where input typed into the REPL is said to come from. Files it loads are
+/// found relative to the current directory.
+const STDIN: &str = "<stdin>";
+
+/// The state built up by the statements entered so far.
+struct Session {
+    width: u32,
+    height: u32,
+    format: Format,
+    layers: bool,
+    prelude: bool,
+    dialect: Dialect,
+    /// Where RANDOM and PICK start from, so that replaying the history
+    /// draws the same numbers again.
+    seed: u64,
+    canvas: Box<dyn Canvas>,
+    cursor: Cursor,
+    env: Environment,
+    /// Every input that ran successfully, so that undo can replay all but the last.
+    history: Vec<String>,
+    /// The files read for the last input, for showing its errors.
+    sources: Sources,
+}
+
+impl Session {
+    fn new(width: u32, height: u32, format: Format, layers: bool, prelude: bool, dialect: Dialect, seed: u64)
+        -> Session {
+        let mut session = Session {
+            width,
+            height,
+            format,
+            layers,
+            prelude,
+            dialect,
+            seed,
+            canvas: create_canvas(format, width, height, layers),
+            cursor: Cursor::new((width / 2) as f32, (height / 2) as f32),
+            env: Environment::new(Default::default()),
+            history: Vec::new(),
+            sources: Sources::default(),
+        };
+        session.env.seed_random(seed);
+        if prelude {
+            // The prelude only defines procedures, so it cannot fail
+            let _ = session.compile(load::PRELUDE, Path::new(load::PRELUDE_PATH), Dialect::Strict)
+                .and_then(|program| session.execute(program));
+        }
+        session
+    }
+
+    fn reset(&mut self) {
+        *self = Session::new(self.width, self.height, self.format, self.layers, self.prelude, self.dialect, self.seed);
+    }
+
+    /// Runs one input on top of the current state. A failed input leaves
+    /// the state exactly as it was before.
+    fn run(&mut self, source: &str) -> Result<(), LogoError> {
+        // An input that does not parse has not changed anything yet
+        let program = self.compile(source, Path::new(STDIN), self.dialect)?;
+        match self.execute(program) {
+            Ok(()) => {
+                self.history.push(source.to_string());
+                Ok(())
+            },
+            Err(err) => {
+                let sources = std::mem::take(&mut self.sources);
+                self.replay();
+                self.sources = sources;
+                Err(err)
+            },
+        }
+    }
+
+    /// Reads an input, along with any files it loads, without running it.
+    fn compile(&mut self, source: &str, path: &Path, dialect: Dialect) -> Result<CompiledProgram, LogoError> {
+        self.sources = Sources::default();
+        let mut tokens = load::expand(source, path, false, dialect, &mut self.sources)?;
+        let known = self.env.arities();
+        dialect.name_calls(&mut tokens, |name| known.contains_key(name));
+        Ok(bytecode::compile(parser::parse_with_procedures(&tokens, &known)?))
+    }
+
+    fn execute(&mut self, program: CompiledProgram) -> Result<(), LogoError> {
+        self.env.procedures.extend(program.procedures);
+        vm::execute(&program.main, self.canvas.as_mut(), &mut self.cursor, &mut self.env)
+    }
+
+    fn undo(&mut self) -> bool {
+        if self.history.pop().is_none() {
+            return false;
+        }
+        self.replay();
+        true
+    }
+
+    /// Rebuilds the state from scratch by running the history again.
+    fn replay(&mut self) {
+        let history = std::mem::take(&mut self.history);
+        self.reset();
+        for source in &history {
+            // Each of these ran successfully before, so it will again
+            let _ = self.compile(source, Path::new(STDIN), self.dialect).and_then(|program| self.execute(program));
+        }
+        self.history = history;
+    }
+
+    fn print_variables(&self) {
+        let mut variables = self.env.globals();
+        if variables.is_empty() {
+            println!("No variables");
+            return;
+        }
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, value) in variables {
+            println!(":{name} = {value}");
+        }
+    }
+}
+
+/// Parse errors that more input could fix, so the REPL keeps reading.
+fn is_incomplete(err: &LogoError) -> bool
+{
+    matches!(err.kind, ErrorKind::MissingCloseBracket | ErrorKind::MissingCloseParen | ErrorKind::MissingEnd)
+}
+
+pub fn run(args: ReplArgs) -> Result<(), i32>
+{
+    let image_path = args.image_path.as_path();
+    check_dimensions(args.width, args.height)?;
+    let format = output_format(image_path, args.format)?;
+
+    // Without a seed, one is picked for the session so that undo still
+    // draws the same numbers
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut session = Session::new(args.width, args.height, format, args.layers, !args.no_prelude, args.dialect, seed);
+    if let Err(err) = save_image(session.canvas.as_ref(), image_path) {
+        eprintln!("{err}");
+        return Err(1);
+    }
+    println!("{HELP}");
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut buffer = String::new();
+    loop {
+        print!("{}", if buffer.is_empty() { "> " } else { ".. " });
+        let _ = io::stdout().flush();
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+
+        if buffer.is_empty() {
+            match line.trim() {
+                "" => continue,
+                ".quit" => break,
+                ".help" => {
+                    println!("{HELP}");
+                    continue;
+                },
+                ".vars" => {
+                    session.print_variables();
+                    continue;
+                },
+                ".reset" => session.reset(),
+                ".undo" => {
+                    if !session.undo() {
+                        println!("Nothing to undo");
+                        continue;
+                    }
+                },
+                _ => {
+                    buffer = line;
+                },
+            }
+        }
+        else {
+            buffer.push('\n');
+            buffer.push_str(&line);
+        }
+
+        if !buffer.is_empty() {
+            if let Err(err) = session.run(&buffer) {
+                if is_incomplete(&err) {
+                    continue;
+                }
+                eprint!("{}", session.sources.render(&err));
+            }
+            buffer.clear();
+        }
+
+        if let Err(err) = save_image(session.canvas.as_ref(), image_path) {
+            eprintln!("{err}");
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,48 @@
+//! Checks that `--animate` and `--frames` show every line in drawing order.
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Five lines, then a fill drawn without any more lines.
+const PROGRAM: &str = "PENDOWN\nREPEAT 5 [\n  FORWARD 10\n  TURN 72\n]\nPENUP\nBEGINFILL\nCIRCLE 20\nENDFILL\n";
+
+/// Draws `PROGRAM` into a directory of its own, which is returned.
+fn draw(name: &str, args: &[&str]) -> PathBuf
+{
+    let dir = common::temporary(name);
+    let _ = fs::remove_dir_all(&dir);
+    let extension = if args.contains(&"--animate") { "svg" } else { "png" };
+    let result = common::run(&format!("{name}/program"), PROGRAM, &format!("{name}/{name}.{extension}"), args);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    dir
+}
+
+#[test]
+fn animate_shows_each_shape_in_turn()
+{
+    let dir = draw("animate", &["--animate", "--speed", "10"]);
+    let svg = fs::read_to_string(dir.join("animate.svg")).expect("animation was saved");
+    let begins: Vec<&str> = svg.match_indices("begin=\"")
+        .map(|(start, key)| {
+            let rest = &svg[start + key.len()..];
+            &rest[..rest.find('"').unwrap()]
+        })
+        .collect();
+    assert_eq!(begins, ["0.1s", "0.2s", "0.3s", "0.4s", "0.5s", "0.5s"]);
+    assert_eq!(svg.matches(r#"visibility="hidden""#).count(), 6);
+}
+
+#[test]
+fn frames_are_numbered_in_order()
+{
+    let dir = draw("frames", &["--frames", "2"]);
+    let mut frames: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.ends_with(".png"))
+        .collect();
+    frames.sort();
+    assert_eq!(frames, ["frames_0001.png", "frames_0002.png", "frames_0003.png"]);
+}
@@ -0,0 +1,56 @@
+//! Runs `rslogo check` on programs with and without problems, checking what
+//! it reports and the exit codes CI relies on.
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+/// Writes each of `files` and checks them all with `args`, returning the
+/// exit code and what was printed on stdout.
+fn check(files: &[(&str, &str)], args: &[&str]) -> (Option<i32>, String)
+{
+    let paths: Vec<String> = files.iter()
+        .map(|(name, source)| {
+            let path = common::temporary(&format!("check_{name}.lg"));
+            fs::write(&path, source).expect("temporary directory is writable");
+            path.display().to_string()
+        })
+        .collect();
+    let output = Command::new(env!("CARGO_BIN_EXE_rslogo"))
+        .arg("check")
+        .args(args)
+        .args(&paths)
+        .output()
+        .expect("rslogo runs");
+    (output.status.code(), String::from_utf8(output.stdout).unwrap())
+}
+
+const OK: (&str, &str) = ("ok", "FORWARD 1\n");
+const WARNING: (&str, &str) = ("warning", "IF TRUE [ MAKE \"a 1 ]\nFORWARD :a\n");
+const ERRORS: (&str, &str) = ("errors", "FORWARD\nADDASSIGN \"b 1\n");
+
+#[test]
+fn passes_programs_without_errors()
+{
+    let (code, stdout) = check(&[OK, WARNING], &[]);
+    assert_eq!(code, Some(0));
+    assert!(stdout.contains("check_ok.lg: ok\n"), "{stdout}");
+    assert!(stdout.contains("check_warning.lg: 0 errors, 1 warning\n"), "{stdout}");
+}
+
+#[test]
+fn fails_on_warnings_only_when_denied()
+{
+    assert_eq!(check(&[WARNING], &["--deny-warnings"]).0, Some(1));
+    assert_eq!(check(&[OK], &["--deny-warnings"]).0, Some(0));
+}
+
+#[test]
+fn reports_every_error_in_every_file()
+{
+    let (code, stdout) = check(&[ERRORS, OK], &[]);
+    assert_eq!(code, Some(1));
+    assert!(stdout.contains("check_errors.lg: 2 errors, 0 warnings\n"), "{stdout}");
+    assert!(stdout.contains("check_ok.lg: ok\n"), "{stdout}");
+}
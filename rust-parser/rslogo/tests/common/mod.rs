@@ -0,0 +1,61 @@
+//! Helpers shared by the tests that run `rslogo` on programs of their own.
+
+// Each test file uses only some of these
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// `path` within the directory Cargo gives tests for temporary files.
+pub fn temporary(path: &str) -> PathBuf
+{
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(path)
+}
+
+/// Writes `source` to `name.lg` and runs it on a 200 by 200 canvas with
+/// `args`, drawing to `image`, or to stdout for `-`. Both paths are in the
+/// temporary directory, and any image left from an earlier run is removed
+/// first.
+pub fn run(name: &str, source: &str, image: &str, args: &[&str]) -> Output
+{
+    let program = temporary(&format!("{name}.lg"));
+    if let Some(directory) = program.parent() {
+        fs::create_dir_all(directory).expect("temporary directory is writable");
+    }
+    fs::write(&program, source).expect("temporary directory is writable");
+    let image = if image == "-" { PathBuf::from("-") } else { temporary(image) };
+    let _ = fs::remove_file(&image);
+
+    Command::new(env!("CARGO_BIN_EXE_rslogo"))
+        .arg(&program)
+        .arg(&image)
+        .args(["200", "200"])
+        .args(args)
+        .output()
+        .expect("rslogo runs")
+}
+
+/// Runs `source` like `run`, printing the drawing as JSON to stdout.
+pub fn draw_json(name: &str, source: &str, args: &[&str]) -> Output
+{
+    run(name, source, "-", &[&["--format", "json"], args].concat())
+}
+
+/// Runs `rslogo` with `args`, typing `input` into it, and returns what it
+/// printed. Anything it reports on stderr is left out.
+pub fn type_into(args: &[&str], input: &str) -> String
+{
+    let mut rslogo = Command::new(env!("CARGO_BIN_EXE_rslogo"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("rslogo runs");
+    rslogo.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = rslogo.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
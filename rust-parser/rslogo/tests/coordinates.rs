@@ -0,0 +1,46 @@
+//! Checks `--origin`, `--y-axis` and `--fit` against the JSON output, which
+//! gives the position of every line on the canvas.
+
+use std::process::Output;
+
+mod common;
+
+/// The JSON printed by a run that succeeded.
+fn printed(result: Output) -> String
+{
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    String::from_utf8(result.stdout).expect("JSON is UTF-8")
+}
+
+#[test]
+fn coordinates_follow_the_origin_and_y_axis()
+{
+    // The turtle starts in the middle whatever the origin, and draws a dot
+    // where SETX and SETY put it
+    let source = "MAKE \"x XCOR\nMAKE \"y YCOR\nSETX + :x 10\nSETY + :y 20\nPENDOWN\nFORWARD 0\n";
+    let dot = |x: &str, y: &str| format!(r#""from":[{x},{y}],"to":[{x},{y}]"#);
+
+    let json = printed(common::draw_json("top_left", source, &[]));
+    assert!(json.contains(&dot("110.0", "120.0")), "{json}");
+
+    let json = printed(common::draw_json("center", source, &["--origin", "center"]));
+    assert!(json.contains(&dot("110.0", "120.0")), "{json}");
+
+    let json = printed(common::draw_json("center_up", source, &["--origin", "center", "--y-axis", "up"]));
+    assert!(json.contains(&dot("110.0", "80.0")), "{json}");
+}
+
+#[test]
+fn fit_scales_the_drawing_into_the_canvas()
+{
+    // A 1000x500 rectangle, far bigger than the canvas and mostly off it
+    let source = "PENDOWN\nFORWARD 500\nTURN 90\nFORWARD 1000\n";
+
+    let json = printed(common::draw_json("unfitted", source, &[]));
+    assert!(json.contains(r#""to":[1100.0,-400.0]"#), "{json}");
+
+    // Scaled to 180 pixels wide, leaving a margin of 10, and centred
+    let json = printed(common::draw_json("fitted", source, &["--fit"]));
+    assert!(json.contains(r#""from":[10.0,145.0],"to":[10.0,55.0]"#), "{json}");
+    assert!(json.contains(r#""from":[10.0,55.0],"to":[190.0,55.0]"#), "{json}");
+}
@@ -0,0 +1,83 @@
+//! Drives `rslogo debug` through its commands on stdin, checking where the
+//! program stops.
+
+mod common;
+
+use std::fs;
+
+/// Debugs `source` with `commands` typed in, returning what was printed.
+fn debug(name: &str, source: &str, commands: &str, args: &[&str]) -> String
+{
+    let program = common::temporary(&format!("{name}.lg"));
+    fs::write(&program, source).expect("temporary directory is writable");
+    let program = program.display().to_string();
+    let image = common::temporary(&format!("{name}.svg")).display().to_string();
+    common::type_into(&[&["debug", &program, &image, "200", "200"], args].concat(), commands)
+}
+
+#[test]
+fn stops_at_a_breakpoint_on_the_first_line()
+{
+    let commands = "print YCOR\nbreak 1\ncontinue\nprint YCOR\ncontinue\n";
+    let output = debug("first_line", "FORWARD 10\nTURN 90\n", commands, &[]);
+    let (before, stopped) = output.split_once("Breakpoint at line 1").expect(&output);
+    // Stopped before moving, and then ran to the end
+    let start = before.lines().find_map(|line| line.strip_prefix("(debug) ")).unwrap();
+    assert!(stopped.contains(&format!("(debug) {start}\n")), "{output}");
+    assert!(stopped.contains("The program has finished"), "{output}");
+}
+
+#[test]
+fn stops_in_a_procedure_only_when_it_runs_a_statement()
+{
+    let source = "\
+TO NOTHING
+END
+TO LINE
+    FORWARD 10
+END
+NOTHING
+TURN 90
+LINE
+";
+    let output = debug("procedures", source, "break NOTHING\nbreak LINE\ncontinue\ncontinue\n", &[]);
+    assert!(!output.contains("Breakpoint at procedure NOTHING"), "{output}");
+    assert!(output.contains("Breakpoint at procedure LINE\n   4 |     FORWARD 10"), "{output}");
+}
+
+#[test]
+fn watches_leave_random_numbers_alone()
+{
+    let source = "MAKE \"a RANDOM 1000\nMAKE \"b RANDOM 1000\nFORWARD 1\n";
+    // The watch changes at each statement, so it takes a few to reach the end
+    let commands = "continue\ncontinue\ncontinue\ncontinue\nprint :b\n";
+    let watched = debug("watched", source, &format!("watch RANDOM 1000\nprint RANDOM 1000\n{commands}"), &["--seed", "3"]);
+    let unwatched = debug("unwatched", source, commands, &["--seed", "3"]);
+    // What the last command printed, before the prompt left waiting at the end
+    let value = |output: &str| {
+        output.lines().rev().find_map(|line| line.strip_prefix("(debug) ").filter(|value| !value.is_empty())).map(str::to_string)
+    };
+    assert_eq!(value(&watched), value(&unwatched));
+}
+
+#[test]
+fn step_goes_into_procedures_and_next_goes_over_them()
+{
+    let source = "TO Line\n  FORWARD 10\nEND\nLine\nTURN 90\n";
+    let stepped = debug("step", source, "step\ncursor\n", &[]);
+    assert!(stepped.contains("(debug)    2 |   FORWARD 10\n(debug)   x: 100\n  y: 100\n"), "{stepped}");
+    let nexted = debug("next", source, "next\ncursor\n", &[]);
+    assert!(nexted.contains("(debug)    5 | TURN 90\n(debug)   x: 100\n  y: 90\n  heading: 0\n"), "{nexted}");
+}
+
+#[test]
+fn snapshots_save_the_drawing_so_far()
+{
+    let snapshot = common::temporary("snapshot_so_far.svg");
+    let _ = fs::remove_file(&snapshot);
+    let source = "PENDOWN\nFORWARD 10\nFORWARD 10\n";
+    let commands = format!("break 3\ncontinue\nsnapshot {}\ncontinue\n", snapshot.display());
+    debug("snapshot", source, &commands, &[]);
+    let svg = fs::read_to_string(&snapshot).unwrap();
+    assert_eq!(svg.matches("<path").count(), 1, "{svg}");
+}
@@ -0,0 +1,97 @@
+//! Checks that `--dialect ucb` runs programs written for UCBLogo the same as
+//! the strict programs they stand for.
+
+mod common;
+
+use std::fs;
+
+const UCB: &str = "\
+; A square spiral
+to spiral :size
+  if :size < 60 [
+    fd :size rt 90
+    Spiral :Size + 10   ; names are not case sensitive
+  ]
+end
+
+make \"step 10
+PD
+repeat 3 [fd :step lt 45 + 45 bk 5]
+spiral 20
+";
+
+const STRICT: &str = "\
+TO SPIRAL \"SIZE
+    IF :SIZE < 60 [
+        FORWARD :SIZE
+        TURN 90
+        SPIRAL :SIZE + 10
+    ]
+END
+
+MAKE \"STEP 10
+PENDOWN
+REPEAT 3 [
+    FORWARD :STEP
+    TURN -90
+    BACK 5
+]
+SPIRAL 20
+";
+
+/// Runs `source` on a 200x200 canvas, printing the lines drawn as JSON.
+#[test]
+fn ucb_programs_draw_what_their_strict_versions_do()
+{
+    let ucb = common::draw_json("ucb", UCB, &["--dialect", "ucb"]);
+    assert!(ucb.status.success(), "{}", String::from_utf8_lossy(&ucb.stderr));
+    let strict = common::draw_json("strict", STRICT, &[]);
+    assert!(strict.status.success(), "{}", String::from_utf8_lossy(&strict.stderr));
+    assert_eq!(String::from_utf8_lossy(&ucb.stdout), String::from_utf8_lossy(&strict.stdout));
+}
+
+#[test]
+fn strict_syntax_stays_the_default()
+{
+    let output = common::draw_json("ucb_as_strict", UCB, &[]);
+    assert!(!output.status.success());
+
+    // LEFT and RIGHT only turn in the UCB dialect
+    let sideways = common::draw_json("sideways", "PENDOWN\nLEFT 10\n", &[]);
+    assert!(String::from_utf8_lossy(&sideways.stdout).contains(r#""to":[90.0,100.0]"#));
+    let turned = common::draw_json("turned", "PENDOWN\nLEFT 10\n", &["--dialect", "ucb"]);
+    assert!(String::from_utf8_lossy(&turned.stdout).contains(r#""segments":[]"#));
+}
+
+#[test]
+fn ucb_programs_call_the_prelude_and_loaded_files_in_any_case()
+{
+    fs::write(common::temporary("ucb_shapes.lg"), "to triangle :side\n  repeat 3 [fd :side rt 120]\nend\n")
+        .expect("temporary directory is writable");
+
+    let ucb = "load \"ucb_shapes.lg\npd\nsquare 50\npolygon 5 40\nTriangle 30\n";
+    let ucb = common::draw_json("ucb_prelude", ucb, &["--dialect", "ucb"]);
+    assert!(ucb.status.success(), "{}", String::from_utf8_lossy(&ucb.stderr));
+    let strict = "TO TRIANGLE \"SIDE\nREPEAT 3 [\nFORWARD :SIDE\nTURN 120\n]\nEND\nPENDOWN\nSQUARE 50\nPOLYGON 5 40\nTRIANGLE 30\n";
+    let strict = common::draw_json("strict_prelude", strict, &[]);
+    assert!(strict.status.success(), "{}", String::from_utf8_lossy(&strict.stderr));
+    assert_eq!(String::from_utf8_lossy(&ucb.stdout), String::from_utf8_lossy(&strict.stdout));
+}
+
+#[test]
+fn ucb_lists_of_words_stay_as_written_and_comments_end_words()
+{
+    let ucb = "\
+pd
+make \"moves [lt rt]
+fd 10;back 5
+foreach \"m :moves [
+  if eq :m \"lt [lt 90 fd 10]
+]
+fd 10 * count :moves ; twice
+";
+    let ucb = common::draw_json("ucb_lists", ucb, &["--dialect", "ucb"]);
+    assert!(ucb.status.success(), "{}", String::from_utf8_lossy(&ucb.stderr));
+    let strict = common::draw_json("strict_lists", "PENDOWN\nFORWARD 10\nTURN -90\nFORWARD 10\nFORWARD 20\n", &[]);
+    assert_eq!(String::from_utf8_lossy(&ucb.stdout), String::from_utf8_lossy(&strict.stdout));
+}
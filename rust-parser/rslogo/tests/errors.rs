@@ -0,0 +1,56 @@
+//! Checks how `rslogo` reports programs it cannot run: where the error is,
+//! and that it exits with 1 instead of panicking.
+
+mod common;
+
+/// Runs `source`, which should fail, returning what was printed on stderr.
+fn fail(name: &str, source: &str) -> String
+{
+    let output = common::run(name, source, &format!("{name}.svg"), &[]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{source:?} printed {stderr}");
+    stderr
+}
+
+#[test]
+fn points_at_the_error_in_the_source()
+{
+    let stderr = fail("unknown_variable", "PENDOWN\nFORWARD :nope\n");
+    let path = common::temporary("unknown_variable.lg").display().to_string();
+    let expected = format!("\
+error[UnknownVariable]: No matching variable found: :nope
+ --> {path}:2:9
+  |
+2 | FORWARD :nope
+  |         ^^^^^
+");
+    assert!(stderr.starts_with(&expected), "{stderr}");
+}
+
+#[test]
+fn malformed_programs_do_not_panic()
+{
+    let programs = [
+        "FORWARD",
+        "IF",
+        "IF EQ [\n]",
+        "WHILE [ ] ]",
+        "MAKE",
+        "MAKE :x",
+        "ADDASSIGN \"x",
+        "FORWARD + \"1",
+        "FORWARD \"ten",
+        "TURN / \"1 \"0",
+        "TO",
+        "TO A \"x\nA",
+        "END",
+        "SETPENCOLOR \"300",
+        "]",
+        "[",
+        "\"",
+        ":",
+    ];
+    for (index, source) in programs.into_iter().enumerate() {
+        fail(&format!("malformed_{index}"), source);
+    }
+}
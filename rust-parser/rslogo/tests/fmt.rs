@@ -0,0 +1,155 @@
+//! Checks that `rslogo fmt` lays programs out the same way every time,
+//! without changing what they do or losing their comments.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use lib_crate::{bytecode, format, lexer, parser};
+use lib_crate::dialect::Dialect;
+use lib_crate::load::Sources;
+
+const MESSY: &str = "\
+// Draws squares
+
+to square \"n   // a square
+ repeat 4 [ forward :n turn 90 ]
+end
+
+
+make \"words [forward xcor]
+IF true [   // always
+  // inside
+  square (:n + 2) * 3
+  FORWARD 5 - -:n
+  forward // distance
+  10
+]
+";
+
+const TIDY: &str = "\
+// Draws squares
+
+TO square \"n // a square
+  REPEAT 4 [
+    FORWARD :n
+    TURN 90
+  ]
+END
+
+MAKE \"words [forward xcor]
+IF TRUE [ // always
+  // inside
+  square (:n + 2) * 3
+  FORWARD 5 - -:n
+  FORWARD // distance
+    10
+]
+";
+
+/// The compiled program, without the line numbers that formatting changes.
+fn instructions(source: &str) -> String
+{
+    let program = parser::parse(&lexer::tokenize(source)).expect("program parses");
+    bytecode::dump(&bytecode::compile(program), &Sources::default())
+        .lines()
+        .map(|line| line.split(';').next().unwrap().trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn formats_layout_and_keywords_keeping_comments()
+{
+    assert_eq!(format::format_source(MESSY, &HashMap::new(), Dialect::Strict).unwrap(), TIDY);
+    assert_eq!(format::format_source(TIDY, &HashMap::new(), Dialect::Strict).unwrap(), TIDY);
+    assert_eq!(format::format_source("", &HashMap::new(), Dialect::Strict).unwrap(), "");
+}
+
+#[test]
+fn formatting_examples_keeps_their_meaning()
+{
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("logo_examples");
+    for entry in fs::read_dir(examples).expect("logo_examples exists") {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|extension| extension != "lg") {
+            continue;
+        }
+        let source = fs::read_to_string(&path).unwrap();
+        // Programs that fail to parse cannot be formatted
+        let Ok(formatted) = format::format_source(&source, &HashMap::new(), Dialect::Strict) else {
+            continue;
+        };
+        assert_eq!(instructions(&formatted), instructions(&source), "{}", path.display());
+        assert_eq!(format::format_source(&formatted, &HashMap::new(), Dialect::Strict).unwrap(), formatted, "{}", path.display());
+    }
+}
+
+fn rslogo_fmt(path: &Path, check: bool) -> bool
+{
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rslogo"));
+    command.arg("fmt").arg(path);
+    if check {
+        command.arg("--check");
+    }
+    command.output().expect("rslogo runs").status.success()
+}
+
+#[test]
+fn check_reports_files_that_need_formatting()
+{
+    let path: PathBuf = Path::new(env!("CARGO_TARGET_TMPDIR")).join("messy.lg");
+    fs::write(&path, MESSY).expect("temporary directory is writable");
+
+    assert!(!rslogo_fmt(&path, true));
+    assert_eq!(fs::read_to_string(&path).unwrap(), MESSY);
+
+    assert!(rslogo_fmt(&path, false));
+    assert_eq!(fs::read_to_string(&path).unwrap(), TIDY);
+    assert!(rslogo_fmt(&path, true));
+}
+
+/// Formats each `name.lg` in `tests/fmt_fixtures/`, which is laid out as
+/// someone might write it by hand, and compares it with `name.formatted.lg`.
+#[test]
+fn fixtures_format_as_expected()
+{
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fmt_fixtures");
+    let mut inputs: Vec<PathBuf> = fs::read_dir(&fixtures)
+        .expect("fmt_fixtures exists")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().ends_with(".lg") && !path.to_string_lossy().ends_with(".formatted.lg"))
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "No fixtures found in {}", fixtures.display());
+
+    for input in inputs {
+        let expected_path = input.with_extension("formatted.lg");
+        let source = fs::read_to_string(&input).unwrap();
+        let expected = fs::read_to_string(&expected_path).unwrap();
+        let formatted = format::format_source(&source, &HashMap::new(), Dialect::Strict).unwrap();
+        assert_eq!(formatted, expected, "{}", input.display());
+        assert!(rslogo_fmt(&expected_path, true), "{} is not formatted", expected_path.display());
+    }
+}
+
+#[test]
+fn formats_ucb_programs_in_their_own_dialect()
+{
+    let ucb = "to sq :n\nrepeat 4 [fd :n rt 90] ; side\nend\nmake \"moves [lt rt]\npd sq 10;small\n";
+    let tidy = "TO sq :n\n  REPEAT 4 [\n    FD :n\n    RT 90\n  ] ; side\nEND\nMAKE \"moves [lt rt]\nPD\nsq 10 ;small\n";
+    assert_eq!(format::format_source(ucb, &HashMap::new(), Dialect::Ucb).unwrap(), tidy);
+    assert_eq!(format::format_source(tidy, &HashMap::new(), Dialect::Ucb).unwrap(), tidy);
+
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ucb.lg");
+    fs::write(&path, ucb).expect("temporary directory is writable");
+    assert!(!rslogo_fmt(&path, false));
+    let formatted = Command::new(env!("CARGO_BIN_EXE_rslogo"))
+        .arg("fmt")
+        .arg(&path)
+        .args(["--dialect", "ucb"])
+        .output()
+        .expect("rslogo runs");
+    assert!(formatted.status.success(), "{}", String::from_utf8_lossy(&formatted.stderr));
+    assert_eq!(fs::read_to_string(&path).unwrap(), tidy);
+}
@@ -0,0 +1,47 @@
+//! Checks that the output format is chosen from the image's extension, or
+//! from `--format`, and that each backend writes what it should.
+
+mod common;
+
+use std::fs;
+
+const PROGRAM: &str = "PENDOWN FORWARD 10 TURN 90 FORWARD 5\n";
+
+/// Draws `PROGRAM` into `image`, returning what was written there.
+fn draw(name: &str, image: &str, args: &[&str]) -> Vec<u8>
+{
+    let output = common::run(name, PROGRAM, image, args);
+    assert!(output.status.success(), "{output:?}");
+    fs::read(common::temporary(image)).unwrap()
+}
+
+#[test]
+fn formats_follow_the_extension()
+{
+    assert!(draw("svg", "formats.svg", &[]).starts_with(b"<svg"));
+    assert!(draw("png", "formats.png", &[]).starts_with(b"\x89PNG"));
+    assert!(draw("json", "formats.json", &[]).starts_with(br##"{"background":"#000000""##));
+    assert!(draw("gcode", "formats.gcode", &[]).starts_with(b"; rslogo plot\n"));
+    let ascii = String::from_utf8(draw("ascii", "formats.txt", &[])).unwrap();
+    assert_eq!(ascii.lines().filter(|line| line.contains('#')).count(), 3, "{ascii}");
+}
+
+#[test]
+fn the_format_flag_wins_over_the_extension()
+{
+    assert!(draw("flag_json", "flag.txt", &["--format", "json"]).starts_with(b"{"));
+    assert!(draw("flag_unsvg", "flag.svg", &["--format", "unsvg"]).starts_with(b"<svg"));
+
+    let output = common::run("stdout", PROGRAM, "-", &["--format", "gcode"]);
+    let gcode = String::from_utf8(output.stdout).unwrap();
+    assert!(gcode.contains("G1 X100 Y110\nG1 X105 Y110\n"), "{gcode}");
+}
+
+#[test]
+fn unknown_extensions_are_errors()
+{
+    let output = common::run("unknown", PROGRAM, "unknown.bmp", &[]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("File extension not supported, use --format to choose one\n"), "{stderr}");
+}
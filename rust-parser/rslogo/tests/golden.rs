@@ -0,0 +1,226 @@
+//! Runs every program in `logo_examples/` and compares the result with the
+//! expected output saved next to it: `name.svg` for a program that draws, or
+//! `name.err` holding the kind and `line:column` of the error a `*_err.lg`
+//! program stops with, e.g. `UnknownVariable 5:6`. The message itself is not
+//! compared, so that rewording it does not fail the test.
+//!
+//! Drawings are compared shape by shape, with a small tolerance on each
+//! coordinate, so that harmless changes to number formatting or grouping do
+//! not fail the test. Run with `RSLOGO_BLESS=1` to save the current output as
+//! the expected output instead.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const WIDTH: &str = "200";
+const HEIGHT: &str = "200";
+
+/// Furthest apart two coordinates can be and still count as the same.
+const TOLERANCE: f32 = 0.01;
+
+#[test]
+fn logo_examples_match_expected_output()
+{
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("logo_examples");
+    let bless = std::env::var("RSLOGO_BLESS").is_ok_and(|value| value != "0");
+
+    let mut programs: Vec<PathBuf> = fs::read_dir(&examples)
+        .expect("logo_examples exists")
+        .map(|entry| entry.expect("logo_examples is readable").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "lg"))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty(), "No programs found in {}", examples.display());
+
+    let failures: Vec<String> = programs.iter()
+        .filter_map(|program| {
+            let name = program.file_name().unwrap().to_string_lossy().to_string();
+            check_example(program, bless).err().map(|message| format!("{name}: {message}"))
+        })
+        .collect();
+    assert!(
+        failures.is_empty(),
+        "{} of {} examples did not match (run with RSLOGO_BLESS=1 to accept the new output):\n{}",
+        failures.len(), programs.len(), failures.join("\n"),
+    );
+}
+
+#[test]
+fn drawings_compare_within_the_tolerance_and_ignore_grouping()
+{
+    let svg = |group: &str, y: &str, stroke: &str| format!(
+        r##"<svg width="200" height="200" viewBox="0 0 200 200">
+    <rect width="200" height="200" fill="#000000"/>
+    {group}<path d="M 100 100 L 100 {y}" fill="none" stroke="{stroke}"/>
+</svg>"##
+    );
+    let drawing = |group, y, stroke| Drawing::parse(&svg(group, y, stroke)).unwrap();
+    let expected = drawing("", "90", "#ffffff");
+
+    assert_eq!(compare(&expected, &drawing("<g id=\"Box\">", "90.004", "#ffffff")), Ok(()));
+    assert!(compare(&expected, &drawing("", "90.1", "#ffffff")).is_err());
+    assert!(compare(&expected, &drawing("", "90", "#ff0000")).is_err());
+    let mut longer = drawing("", "90", "#ffffff");
+    longer.shapes.push(drawing("", "80", "#ffffff").shapes.remove(0));
+    assert_eq!(compare(&expected, &longer), Err("expected 1 shapes, found 2".to_string()));
+}
+
+#[test]
+fn errors_are_read_from_the_snippet()
+{
+    let stderr = "error[UnknownVariable]: No matching variable found: :a\n --> C:/logo/prog.lg:12:3\n   |\n";
+    assert_eq!(error_summary(stderr), Some("UnknownVariable 12:3".to_string()));
+    assert_eq!(error_summary("error[UnknownVariable]: No matching variable found: :a\n"), None);
+    assert_eq!(error_summary("Error opening file\n --> prog.lg:1:1\n"), None);
+}
+
+/// Runs one program and compares the result with its expected output, or
+/// saves the result as the expected output if `bless` is set.
+fn check_example(program: &Path, bless: bool) -> Result<(), String>
+{
+    let stem = program.file_stem().unwrap().to_string_lossy().to_string();
+    let expect_error = stem.ends_with("_err");
+    let golden_svg = program.with_extension("svg");
+    let golden_err = program.with_extension("err");
+
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{stem}.svg"));
+    let _ = fs::remove_file(&output);
+    let result = Command::new(env!("CARGO_BIN_EXE_rslogo"))
+        .arg(program)
+        .arg(&output)
+        .args([WIDTH, HEIGHT, "--seed", "1"])
+        .output()
+        .map_err(|e| format!("could not run rslogo: {e}"))?;
+    let stderr = String::from_utf8_lossy(&result.stderr);
+
+    if expect_error {
+        if result.status.code() != Some(1) {
+            return Err(format!("expected an error and exit code 1, found {}", result.status));
+        }
+        let summary = error_summary(&stderr).ok_or_else(|| format!("no error kind and location in {:?}", stderr))?;
+        if bless {
+            let _ = fs::remove_file(&golden_svg);
+            return fs::write(&golden_err, format!("{summary}\n")).map_err(|e| e.to_string());
+        }
+        let expected = fs::read_to_string(&golden_err).map_err(|e| format!("no {}: {e}", golden_err.display()))?;
+        if summary != expected.trim_end() {
+            return Err(format!("expected the error {}, found {summary}: {}", expected.trim_end(), stderr.trim_end()));
+        }
+        return Ok(());
+    }
+
+    if !result.status.success() {
+        return Err(format!("failed: {}", stderr.trim_end()));
+    }
+    let svg = fs::read_to_string(&output).map_err(|e| format!("no output written: {e}"))?;
+    if bless {
+        let _ = fs::remove_file(&golden_err);
+        return fs::write(&golden_svg, svg).map_err(|e| e.to_string());
+    }
+    let expected = fs::read_to_string(&golden_svg).map_err(|e| format!("no {}: {e}", golden_svg.display()))?;
+    compare(&Drawing::parse(&expected)?, &Drawing::parse(&svg)?)
+}
+
+/// The kind from the `error[Kind]:` line of a rendered error, and the
+/// `line:column` from its ` --> path:line:column` line, as `Kind line:column`.
+fn error_summary(stderr: &str) -> Option<String>
+{
+    let kind = stderr.lines().find_map(|line| line.strip_prefix("error[")?.split_once("]:"))?.0;
+    let arrow = stderr.lines().find_map(|line| line.trim_start().strip_prefix("--> "))?;
+    let mut parts = arrow.rsplitn(3, ':');
+    let column = parts.next()?;
+    let line = parts.next()?;
+    Some(format!("{kind} {line}:{column}"))
+}
+
+/// What an SVG written by rslogo draws, ignoring how it is grouped.
+#[derive(Debug)]
+struct Drawing {
+    size: (String, String),
+    background: String,
+    shapes: Vec<Shape>,
+}
+
+/// A `<path>`: either a line, or a filled polygon if it has a fill.
+#[derive(Debug)]
+struct Shape {
+    points: Vec<(f32, f32)>,
+    fill: String,
+    stroke: String,
+    width: f32,
+}
+
+impl Drawing {
+    fn parse(svg: &str) -> Result<Drawing, String> {
+        let root = *elements(svg, "svg").first().ok_or("no <svg> element")?;
+        let size = (attribute(root, "width").unwrap_or_default(), attribute(root, "height").unwrap_or_default());
+        let background = elements(svg, "rect").first().and_then(|rect| attribute(rect, "fill")).unwrap_or_default();
+
+        let mut shapes = Vec::new();
+        for path in elements(svg, "path") {
+            let d = attribute(path, "d").ok_or("<path> without d")?;
+            let numbers = d.split_whitespace()
+                .filter(|part| !matches!(*part, "M" | "L" | "Z"))
+                .map(|part| part.parse::<f32>().map_err(|_| format!("bad number {part:?} in path")))
+                .collect::<Result<Vec<f32>, String>>()?;
+            let width = match attribute(path, "stroke-width") {
+                Some(width) => width.parse().map_err(|_| format!("bad stroke-width {width:?}"))?,
+                None => 1.0,
+            };
+            shapes.push(Shape {
+                points: numbers.chunks(2).map(|pair| (pair[0], pair.get(1).copied().unwrap_or(f32::NAN))).collect(),
+                fill: attribute(path, "fill").unwrap_or_default(),
+                stroke: attribute(path, "stroke").unwrap_or_default(),
+                width,
+            });
+        }
+        Ok(Drawing { size, background, shapes })
+    }
+}
+
+/// The text inside each `<name ...>` tag.
+fn elements<'a>(svg: &'a str, name: &str) -> Vec<&'a str>
+{
+    let open = format!("<{name} ");
+    svg.match_indices(&open)
+        .filter_map(|(start, _)| {
+            let end = svg[start..].find('>')?;
+            Some(&svg[start..start + end])
+        })
+        .collect()
+}
+
+fn attribute(element: &str, name: &str) -> Option<String>
+{
+    let key = format!(" {name}=\"");
+    let start = element.find(&key)? + key.len();
+    let end = element[start..].find('"')?;
+    Some(element[start..start + end].to_string())
+}
+
+fn compare(expected: &Drawing, found: &Drawing) -> Result<(), String>
+{
+    if expected.size != found.size {
+        return Err(format!("expected size {:?}, found {:?}", expected.size, found.size));
+    }
+    if expected.background != found.background {
+        return Err(format!("expected background {}, found {}", expected.background, found.background));
+    }
+    for (index, (a, b)) in expected.shapes.iter().zip(&found.shapes).enumerate() {
+        let same = a.fill == b.fill
+            && a.stroke == b.stroke
+            && (a.width - b.width).abs() <= TOLERANCE
+            && a.points.len() == b.points.len()
+            && a.points.iter().zip(&b.points).all(|(p, q)| {
+                (p.0 - q.0).abs() <= TOLERANCE && (p.1 - q.1).abs() <= TOLERANCE
+            });
+        if !same {
+            return Err(format!("shape {index} differs: expected {a:?}, found {b:?}"));
+        }
+    }
+    if expected.shapes.len() != found.shapes.len() {
+        return Err(format!("expected {} shapes, found {}", expected.shapes.len(), found.shapes.len()));
+    }
+    Ok(())
+}
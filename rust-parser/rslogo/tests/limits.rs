@@ -0,0 +1,54 @@
+//! Runs programs that never finish under each limit, checking that every
+//! limit stops them with its own error and keeps what was drawn so far.
+
+mod common;
+
+use std::fs;
+
+const SPIRAL: &str = "PENDOWN\nWHILE EQ 1 1 [\n  FORWARD 1\n  TURN 1\n]\n";
+const RECURSION: &str = "TO GROW \"n\n  FORWARD 1\n  GROW + :n 1\nEND\nPENDOWN\nGROW 1\n";
+
+/// Runs `source` with extra arguments, returning the first line of stderr
+/// and how many lines were saved, if any.
+fn stopped(name: &str, source: &str, args: &[&str]) -> (String, Option<usize>)
+{
+    let image = format!("{name}.svg");
+    let result = common::run(name, source, &image, args);
+    assert!(!result.status.success(), "{name} should have been stopped");
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let saved = fs::read_to_string(common::temporary(&image)).ok().map(|svg| svg.matches("<path ").count());
+    (stderr.lines().next().unwrap_or_default().to_string(), saved)
+}
+
+#[test]
+fn each_limit_stops_the_program_and_keeps_a_partial_image()
+{
+    let (error, saved) = stopped("steps", SPIRAL, &["--max-steps", "500"]);
+    assert_eq!(error, "error[StepLimitExceeded]: Program ran for too many steps");
+    assert!(saved.is_some_and(|lines| lines > 0));
+
+    let (error, saved) = stopped("segments", SPIRAL, &["--max-segments", "100"]);
+    assert_eq!(error, "error[SegmentLimitExceeded]: Program drew too many lines");
+    assert_eq!(saved, Some(100));
+
+    let (error, saved) = stopped("time", SPIRAL, &["--max-time", "0.2"]);
+    assert_eq!(error, "error[TimeLimitExceeded]: Program ran for too long");
+    assert!(saved.is_some_and(|lines| lines > 0));
+
+    // The spiral starts at x = 100 and curves right
+    let (error, saved) = stopped("coordinate", SPIRAL, &["--max-coordinate", "100.5"]);
+    assert_eq!(error, "error[OutOfBounds]: Turtle moved too far from the canvas");
+    assert!(saved.is_some_and(|lines| lines > 0));
+
+    let (error, saved) = stopped("depth", RECURSION, &["--max-depth", "20"]);
+    assert_eq!(error, "error[RecursionTooDeep]: Too many nested procedure calls: GROW");
+    assert_eq!(saved, Some(20));
+}
+
+#[test]
+fn other_errors_save_nothing()
+{
+    let (error, saved) = stopped("unknown", "PENDOWN\nFORWARD 10\nFORWARD :missing\n", &["--sandbox"]);
+    assert_eq!(error, "error[UnknownVariable]: No matching variable found: :missing");
+    assert_eq!(saved, None);
+}
@@ -0,0 +1,70 @@
+//! Checks that LOAD brings in procedures from other files, and that the
+//! prelude's procedures are there unless a program replaces them.
+
+mod common;
+
+use std::fs;
+use std::process::Output;
+
+/// Writes each `(name, source)` under a directory for the test, for the
+/// program there to load.
+fn write(test: &str, files: &[(&str, &str)])
+{
+    for (name, source) in files {
+        let path = common::temporary(test).join(name);
+        fs::create_dir_all(path.parent().unwrap()).expect("temporary directory is writable");
+        fs::write(path, source).expect("temporary directory is writable");
+    }
+}
+
+/// Runs `source` as `main.lg` in the test's directory.
+fn run_main(test: &str, source: &str, args: &[&str]) -> Output
+{
+    common::draw_json(&format!("{test}/main"), source, args)
+}
+
+fn lines(output: &Output) -> usize
+{
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).matches("\"from\"").count()
+}
+
+#[test]
+fn load_finds_files_next_to_the_file_loading_them()
+{
+    write("nested", &[
+        ("shapes/box.lg", "LOAD \"side.lg\nTO BOX \"n\n  REPEAT 4 [\n    SIDE :n\n  ]\nEND\n"),
+        ("shapes/side.lg", "TO SIDE \"n\n  FORWARD :n\n  TURN 90\nEND\n"),
+    ]);
+    // Loading a file twice only defines its procedures once
+    let source = "LOAD \"shapes/box.lg\nLOAD \"shapes/side.lg\nPENDOWN\nBOX 10\n";
+    assert_eq!(lines(&run_main("nested", source, &[])), 4);
+}
+
+#[test]
+fn load_reports_cycles_and_missing_files()
+{
+    write("cycle", &[("b.lg", "FORWARD 1\nLOAD \"main.lg\n")]);
+    let output = run_main("cycle", "LOAD \"b.lg\n", &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("File loads itself"), "{stderr}");
+    assert!(stderr.contains("b.lg:2"), "{stderr}");
+
+    let output = run_main("missing", "LOAD \"nowhere.lg\n", &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("Could not load file"), "{stderr}");
+}
+
+#[test]
+fn prelude_procedures_can_be_left_out_or_replaced()
+{
+    let source = "PENDOWN\nSQUARE 10\nSTAR 5 20\n";
+    assert_eq!(lines(&run_main("prelude", source, &[])), 14);
+    assert!(!run_main("prelude", source, &["--no-prelude"]).status.success());
+
+    // POLYGON and SQUARE give way to the program's own SQUARE
+    let source = "TO SQUARE \"a \"b\n  FORWARD + :a :b\nEND\nPENDOWN\nSQUARE 1 2\nPOLYGON 3 10\n";
+    assert_eq!(lines(&run_main("replaced", source, &[])), 4);
+}
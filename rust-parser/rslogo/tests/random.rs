@@ -0,0 +1,61 @@
+//! Checks that programs using RANDOM and PICK draw the same image every time
+//! they run with the same seed.
+
+mod common;
+
+use std::fs;
+use std::process::Output;
+
+/// Scatters squares of random sizes and colors.
+const SCATTER: &str = "\
+PENDOWN
+REPEAT 20 [
+    SETPENCOLOR PICK [1 2 4 9 14]
+    SETHEADING RANDOM 360
+    FORWARD RANDOM 50
+    SQUARE + 5 RANDOM 20
+]
+";
+
+/// Runs `source`, returning the SVG it drew.
+fn draw_svg(name: &str, source: &str, args: &[&str]) -> (Output, String)
+{
+    let image = format!("{name}.svg");
+    let output = common::run(name, source, &image, args);
+    let svg = fs::read_to_string(common::temporary(&image)).unwrap_or_default();
+    (output, svg)
+}
+
+#[test]
+fn the_same_seed_draws_the_same_svg()
+{
+    let (output, first) = draw_svg("seed_1", SCATTER, &["--seed", "7"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let (_, again) = draw_svg("seed_2", SCATTER, &["--seed", "7"]);
+    assert_eq!(first, again);
+
+    let (_, other) = draw_svg("seed_3", SCATTER, &["--seed", "8"]);
+    assert_ne!(first, other);
+}
+
+#[test]
+fn rerandom_restarts_the_sequence()
+{
+    // Whatever the seed given, RERANDOM makes the rest of the program the same
+    let source = format!("RERANDOM 42\n{SCATTER}");
+    let (output, first) = draw_svg("rerandom_1", &source, &["--seed", "1"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let (_, second) = draw_svg("rerandom_2", &source, &["--seed", "2"]);
+    assert_eq!(first, second);
+
+    let (output, _) = draw_svg("rerandom_bad", "RERANDOM 1.5\n", &[]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Argument out of range"));
+}
+
+#[test]
+fn pick_needs_a_list_with_something_in_it()
+{
+    let (output, _) = draw_svg("pick_empty", "FORWARD PICK []\n", &[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("List is empty"));
+}
@@ -0,0 +1,82 @@
+//! Types into `rslogo repl`, checking its meta-commands, and that inputs
+//! which fail, and undoing inputs, leave the session as it was.
+
+mod common;
+
+/// Enters `input` into a session, returning what `.vars` printed each time.
+fn repl(name: &str, input: &str, args: &[&str]) -> Vec<String>
+{
+    let image = common::temporary(&format!("{name}.svg")).display().to_string();
+    common::type_into(&[&["repl", &image, "200", "200"], args].concat(), input)
+        .lines()
+        .filter_map(|line| line.trim_start_matches(['>', '.', ' ']).strip_prefix(":a = "))
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn failed_inputs_and_undo_leave_the_session_as_it_was()
+{
+    let input = "\
+MAKE \"a \"3
+FORWARD :missing
+.vars
+IF \"TRUE [
+  ADDASSIGN \"a \"1
+]
+.vars
+MAKE \"a \"10
+.undo
+.vars
+";
+    assert_eq!(repl("failed", input, &[]), ["3", "4", "4"]);
+}
+
+#[test]
+fn random_numbers_survive_failed_inputs_and_undo()
+{
+    let input = "\
+MAKE \"a RANDOM 1000000
+.vars
+FORWARD :missing
+.vars
+FORWARD (
+)
+.vars
+MAKE \"b RANDOM 10
+.undo
+.vars
+";
+    let values = repl("random", input, &[]);
+    assert_eq!(values.len(), 4, "{values:?}");
+    assert!(values.iter().all(|value| *value == values[0]), "{values:?}");
+}
+
+#[test]
+fn the_same_seed_draws_the_same_numbers()
+{
+    let input = "MAKE \"a RANDOM 1000000\n.vars\n";
+    let first = repl("seed_1", input, &["--seed", "7"]);
+    assert_eq!(first, repl("seed_2", input, &["--seed", "7"]));
+    assert_ne!(first, repl("seed_3", input, &["--seed", "8"]));
+}
+
+#[test]
+fn undo_and_reset_take_back_the_drawing_and_variables()
+{
+    let image = common::temporary("meta.svg");
+    let path = image.display().to_string();
+    let session = |input: &str| {
+        let output = common::type_into(&["repl", &path, "200", "200"], input);
+        let svg = std::fs::read_to_string(&image).unwrap();
+        (output, svg.matches("<path").count())
+    };
+
+    let (output, drawn) = session("PENDOWN\nFORWARD \"10\nMAKE \"a \"3\nTURN \"90 FORWARD \"20\n.vars\n.undo\n.vars\n");
+    assert!(output.contains("> :a = 3\n> > :a = 3\n"), "{output}");
+    assert_eq!(drawn, 1);
+
+    let (output, drawn) = session("PENDOWN\nFORWARD \"10\nMAKE \"a \"3\n.reset\n.vars\n");
+    assert!(output.contains("No variables"), "{output}");
+    assert_eq!(drawn, 0);
+}
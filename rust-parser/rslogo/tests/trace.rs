@@ -0,0 +1,45 @@
+//! Checks that `--trace` writes one event per statement to stderr, and that
+//! nothing else is printed.
+
+mod common;
+
+const PROGRAM: &str = "PENDOWN\nMAKE \"x \"5\nFORWARD :x\n";
+
+/// Runs `PROGRAM`, returning stdout and stderr.
+fn trace(name: &str, args: &[&str]) -> (String, String)
+{
+    let output = common::run(name, PROGRAM, &format!("{name}.svg"), args);
+    assert!(output.status.success(), "{output:?}");
+    (String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn prints_nothing_without_trace()
+{
+    assert_eq!(trace("untraced", &[]), (String::new(), String::new()));
+}
+
+#[test]
+fn traces_each_statement_as_text()
+{
+    let (stdout, stderr) = trace("traced_text", &["--trace"]);
+    assert_eq!(stdout, "");
+    assert_eq!(stderr, "\
+[exec] line 1: PENDOWN x=100 y=100 heading=0 pen=down color=7
+[exec] line 2: MAKE x=100 y=100 heading=0 pen=down color=7 :x=5
+[exec] line 3: FORWARD x=100 y=95 heading=0 pen=down color=7
+");
+}
+
+#[test]
+fn traces_each_statement_as_json()
+{
+    let (_, stderr) = trace("traced_json", &["--trace", "--trace-format", "json"]);
+    let events: Vec<&str> = stderr.lines().collect();
+    assert_eq!(events.len(), 3, "{stderr}");
+    assert_eq!(
+        events[1],
+        r#"{"changes":{"x":5.0},"column":1,"cursor":{"color":7.0,"heading":0.0,"pen":"down","x":100.0,"y":100.0},"depth":0,"line":2,"statement":"MAKE"}"#
+    );
+    assert!(events[2].contains(r#""y":95.0"#), "{stderr}");
+}